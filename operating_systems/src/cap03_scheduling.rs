@@ -6,6 +6,11 @@ use std::{
 };
 
 use itertools::Itertools;
+use num_bigint::BigUint;
+
+pub mod dvfs;
+pub mod green_threads;
+pub mod proc_trace;
 
 #[derive(Debug, Clone, Copy)]
 struct Process {
@@ -26,6 +31,82 @@ impl Process {
 struct RealtimeProcess {
     computation_time: usize,
     period_length: usize,
+    // relative to the (nominal) release, constrained: deadline <= period_length
+    deadline: usize,
+    // phase, i.e. time of the first release
+    offset: usize,
+    // max delay of the actual release after the nominal one. we simulate the worst case, i.e. always the full jitter
+    jitter: usize,
+}
+
+impl RealtimeProcess {
+    fn new(computation_time: usize, period_length: usize) -> Self {
+        RealtimeProcess {
+            computation_time,
+            period_length,
+            deadline: period_length,
+            offset: 0,
+            jitter: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct DeadlineMiss {
+    process: usize,
+    deadline: usize,
+    remaining: usize,
+}
+
+// beyond this the schedule (16 bytes per round) gets too big to simulate round by round
+const MAX_SIMULATED_ROUNDS: usize = 1 << 20;
+
+#[derive(Debug)]
+enum RealtimeEvaluation {
+    Simulated {
+        schedule: Schedule,
+        misses: Vec<DeadlineMiss>,
+    },
+    // busy window too long, only worst case response times per process (None == may miss)
+    Analytic {
+        horizon: BigUint,
+        response_times: Vec<Option<usize>>,
+    },
+}
+
+impl RealtimeEvaluation {
+    fn schedulable(&self) -> bool {
+        match self {
+            RealtimeEvaluation::Simulated { misses, .. } => misses.is_empty(),
+            RealtimeEvaluation::Analytic { response_times, .. } => {
+                response_times.iter().all(Option::is_some)
+            }
+        }
+    }
+
+    fn print(self, ps: &[impl Debug]) {
+        match self {
+            RealtimeEvaluation::Simulated { schedule, misses } => {
+                schedule_to_text_diagram(ps, schedule);
+                print_deadline_misses(&misses);
+            }
+            RealtimeEvaluation::Analytic {
+                horizon,
+                response_times,
+            } => {
+                println!(
+                    "busy window of {} rounds is too long to simulate, falling back to response time analysis",
+                    horizon
+                );
+                for (i, response_time) in response_times.iter().enumerate() {
+                    match response_time {
+                        Some(r) => println!("Process {}: worst case response time {}", i, r),
+                        None => println!("Process {}: may miss its deadline", i),
+                    }
+                }
+            }
+        }
+    }
 }
 
 #[derive(Debug, Default)]
@@ -145,22 +226,94 @@ pub fn test_round_robin() {
     schedule_to_text_diagram(&v, schedule);
 }
 
-fn rate_monotonic(ps: impl Iterator<Item = RealtimeProcess>) -> Schedule {
-    let mut ps = ps.enumerate().collect_vec();
-    ps.sort_by_cached_key(|p| p.1.period_length);
+fn rate_monotonic(ps: impl Iterator<Item = RealtimeProcess>) -> RealtimeEvaluation {
+    fixed_priority(ps, |p| p.period_length)
+}
 
-    let tmax = ps
+fn deadline_monotonic(ps: impl Iterator<Item = RealtimeProcess>) -> RealtimeEvaluation {
+    fixed_priority(ps, |p| p.deadline)
+}
+
+// synchronous release, implicit deadlines: one hyperperiod is enough.
+// with offsets (and jitter, which is just a delayed release here) the schedule only becomes periodic
+// after the largest phase, so we simulate O_max + 2 * LCM (Leung & Whitehead).
+// the LCM of (nearly) coprime periods is basically their product, so no usize here
+fn simulation_horizon(ps: &[(usize, RealtimeProcess)]) -> BigUint {
+    let hyperperiod = ps
         .iter()
-        .map(|p| p.1.period_length)
+        .map(|p| BigUint::from(p.1.period_length))
         .reduce(num_integer::lcm)
         .unwrap_or_else(|| panic!("Could not calculate LCM from {:?}", ps));
+    let max_phase = ps
+        .iter()
+        .map(|p| p.1.offset + p.1.jitter)
+        .max()
+        .unwrap_or(0);
+
+    if max_phase == 0 {
+        hyperperiod
+    } else {
+        max_phase + 2_u32 * hyperperiod
+    }
+}
 
+// lower key == higher priority, ties are broken by input order
+fn fixed_priority(
+    ps: impl Iterator<Item = RealtimeProcess>,
+    priority: impl Fn(&RealtimeProcess) -> usize,
+) -> RealtimeEvaluation {
+    let original = ps.collect_vec();
+    let mut ps = original.iter().copied().enumerate().collect_vec();
+    ps.sort_by_cached_key(|p| priority(&p.1));
+    for (i, p) in &ps {
+        assert!(
+            p.jitter < p.deadline && p.deadline <= p.period_length,
+            "process {} needs jitter < deadline <= period: {:?}",
+            i,
+            p
+        );
+    }
+
+    let horizon = simulation_horizon(&ps);
+    let tmax = match usize::try_from(&horizon) {
+        Ok(tmax) if tmax <= MAX_SIMULATED_ROUNDS => tmax,
+        _ => {
+            return RealtimeEvaluation::Analytic {
+                horizon,
+                response_times: response_time_analysis(&original, priority),
+            }
+        }
+    };
+
+    // remaining computation time and absolute deadline of the current job
     let mut cycles_done: Vec<usize> = vec![0; ps.len()];
+    let mut deadlines: Vec<usize> = vec![0; ps.len()];
+    let mut misses: Vec<DeadlineMiss> = Vec::new();
+
     let mut result: Schedule = Schedule(Vec::new());
-    'outer: for round in 0..tmax {
+    // inclusive, so that deadlines at the very end of the horizon get checked as well
+    'outer: for round in 0..=tmax {
+        for i in 0..ps.len() {
+            if cycles_done[i] > 0 && deadlines[i] == round {
+                misses.push(DeadlineMiss {
+                    process: ps[i].0,
+                    deadline: round,
+                    remaining: cycles_done[i],
+                });
+                // abort the job, otherwise it would eat into the next period
+                cycles_done[i] = 0;
+            }
+        }
+        if round == tmax {
+            break;
+        }
+
         for i in 0..ps.len() {
-            if round % ps[i].1.period_length == 0 {
-                cycles_done[i] = ps[i].1.computation_time;
+            let p = &ps[i].1;
+            let release = p.offset + p.jitter;
+            if round >= release && (round - release) % p.period_length == 0 {
+                cycles_done[i] = p.computation_time;
+                deadlines[i] = round - p.jitter + p.deadline;
             }
         }
 
@@ -175,56 +328,166 @@ fn rate_monotonic(ps: impl Iterator<Item = RealtimeProcess>) -> Schedule {
         result.push(None);
     }
 
+    RealtimeEvaluation::Simulated {
+        schedule: result,
+        misses,
+    }
+}
+
+// classic worst case response time analysis with release jitter:
+// R_i = C_i + sum_{j in hp(i)} ceil((R_i + J_j) / T_j) * C_j
+// offsets are ignored, which is pessimistic but safe.
+// None if the process can miss its deadline (R_i + J_i > D_i)
+fn response_time_analysis(
+    ps: &[RealtimeProcess],
+    priority: impl Fn(&RealtimeProcess) -> usize,
+) -> Vec<Option<usize>> {
+    let mut order = ps.iter().enumerate().collect_vec();
+    order.sort_by_cached_key(|p| priority(p.1));
+
+    let mut result = vec![None; ps.len()];
+    for (n, (i, p)) in order.iter().enumerate() {
+        let higher = &order[..n];
+        let mut response = p.computation_time;
+        loop {
+            let next = p.computation_time
+                + higher
+                    .iter()
+                    .map(|(_, hp)| {
+                        (response + hp.jitter).div_ceil(hp.period_length) * hp.computation_time
+                    })
+                    .sum::<usize>();
+            if next + p.jitter > p.deadline {
+                break;
+            }
+            if next == response {
+                result[*i] = Some(response);
+                break;
+            }
+            response = next;
+        }
+    }
+
     result
 }
 
+fn print_deadline_misses(misses: &[DeadlineMiss]) {
+    if misses.is_empty() {
+        println!("no deadline misses");
+    }
+    for miss in misses {
+        println!(
+            "Process {} missed its deadline at {} with {} rounds left",
+            miss.process, miss.deadline, miss.remaining
+        );
+    }
+}
+
 pub fn test_rate_monotonic() {
     println!("\n## RATE MONOTONIC\n");
 
     println!("\n### Altklausur SS15:");
     let processes = vec![
-        RealtimeProcess {
-            computation_time: 2,
-            period_length: 10,
-        },
-        RealtimeProcess {
-            computation_time: 1,
-            period_length: 5,
-        },
-        RealtimeProcess {
-            computation_time: 5,
-            period_length: 20,
-        },
+        RealtimeProcess::new(2, 10),
+        RealtimeProcess::new(1, 5),
+        RealtimeProcess::new(5, 20),
     ];
 
     // TODO borrow, not move
-    let schedule = rate_monotonic(processes.clone().into_iter());
-    schedule_to_text_diagram(&processes, schedule);
+    rate_monotonic(processes.clone().into_iter()).print(&processes);
 
     println!("\n### Altklausur SS15:");
 
     let processes = vec![
+        RealtimeProcess::new(1, 6),
+        RealtimeProcess::new(1, 3),
+        RealtimeProcess::new(3, 18),
+        RealtimeProcess::new(2, 9),
+    ];
+
+    // TODO borrow, not move
+    rate_monotonic(processes.clone().into_iter()).print(&processes);
+}
+
+pub fn test_deadline_monotonic() {
+    println!("\n## DEADLINE MONOTONIC\n");
+
+    println!("\n### constrained deadline, RM misses:");
+    let processes = vec![
+        RealtimeProcess::new(2, 5),
         RealtimeProcess {
-            computation_time: 1,
-            period_length: 6,
+            deadline: 2,
+            ..RealtimeProcess::new(2, 6)
         },
+    ];
+
+    println!("RM:");
+    let evaluation = rate_monotonic(processes.clone().into_iter());
+    assert!(!evaluation.schedulable());
+    evaluation.print(&processes);
+
+    println!("DM:");
+    let evaluation = deadline_monotonic(processes.clone().into_iter());
+    assert!(evaluation.schedulable());
+    evaluation.print(&processes);
+    println!(
+        "worst case response times: {:?}",
+        response_time_analysis(&processes, |p| p.deadline)
+    );
+
+    println!("\n### with phase and jitter:");
+    let processes = vec![
         RealtimeProcess {
-            computation_time: 1,
-            period_length: 3,
+            deadline: 3,
+            offset: 1,
+            ..RealtimeProcess::new(1, 4)
         },
         RealtimeProcess {
-            computation_time: 3,
-            period_length: 18,
+            deadline: 5,
+            jitter: 1,
+            ..RealtimeProcess::new(2, 6)
         },
         RealtimeProcess {
-            computation_time: 2,
-            period_length: 9,
+            deadline: 10,
+            offset: 2,
+            ..RealtimeProcess::new(3, 12)
         },
     ];
 
-    // TODO borrow, not move
-    let schedule = rate_monotonic(processes.clone().into_iter());
-    schedule_to_text_diagram(&processes, schedule);
+    deadline_monotonic(processes.clone().into_iter()).print(&processes);
+    println!(
+        "worst case response times: {:?}",
+        response_time_analysis(&processes, |p| p.deadline)
+    );
+}
+
+pub fn test_huge_hyperperiod() {
+    println!("\n## HUGE HYPERPERIOD\n");
+
+    println!("\n### coprime periods, LCM would overflow usize:");
+    // the five largest primes below 2^16
+    let processes = vec![
+        RealtimeProcess::new(9000, 65449),
+        RealtimeProcess::new(9000, 65479),
+        RealtimeProcess::new(9000, 65497),
+        RealtimeProcess::new(9000, 65519),
+        RealtimeProcess::new(9000, 65521),
+    ];
+
+    let evaluation = rate_monotonic(processes.clone().into_iter());
+    assert!(evaluation.schedulable());
+    evaluation.print(&processes);
+
+    println!("\n### same, but overloaded:");
+    let processes = vec![
+        RealtimeProcess::new(30000, 65449),
+        RealtimeProcess::new(30000, 65479),
+        RealtimeProcess::new(30000, 65497),
+    ];
+
+    let evaluation = rate_monotonic(processes.clone().into_iter());
+    assert!(!evaluation.schedulable());
+    evaluation.print(&processes);
 }
 
 fn schedule_to_text_diagram(ps: &[impl Debug], s: Schedule) {
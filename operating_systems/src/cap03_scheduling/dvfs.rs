@@ -0,0 +1,297 @@
+use itertools::Itertools;
+
+use super::{simulation_horizon, RealtimeProcess, MAX_SIMULATED_ROUNDS};
+
+// computation_time is counted in rounds at full speed, i.e. in cycles. at speed s a job takes C / s.
+// static and cycle-conserving variants after Pillai & Shin, "Real-Time Dynamic Voltage Scaling
+// for Low-Power Embedded Operating Systems" (2001)
+
+const EPS: f64 = 1e-9;
+
+#[derive(Debug, Clone, Copy)]
+struct FrequencyLevel {
+    // relative to the max frequency
+    speed: f64,
+    power: f64,
+}
+
+#[derive(Debug, Clone)]
+struct Cpu {
+    // ascending by speed, the last one is full speed (1.0)
+    levels: Vec<FrequencyLevel>,
+    idle_power: f64,
+}
+
+impl Cpu {
+    // P ~ f * V^2 and V has to scale roughly linearly with f, so the dynamic part grows with s^3
+    fn cubic(speeds: &[f64], static_power: f64, dynamic_power: f64, idle_power: f64) -> Self {
+        let mut levels = speeds
+            .iter()
+            .map(|&speed| FrequencyLevel {
+                speed,
+                power: static_power + dynamic_power * speed.powi(3),
+            })
+            .collect_vec();
+        levels.sort_by(|a, b| a.speed.total_cmp(&b.speed));
+        assert!(
+            levels.last().map(|l| l.speed) == Some(1.0),
+            "need full speed as highest level: {:?}",
+            levels
+        );
+
+        Cpu { levels, idle_power }
+    }
+
+    // lowest level that is at least as fast as needed, full speed if nothing suffices
+    fn level_for(&self, speed: f64) -> usize {
+        self.levels
+            .iter()
+            .position(|l| l.speed + EPS >= speed)
+            .unwrap_or(self.levels.len() - 1)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Policy {
+    Edf,
+    RateMonotonic,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Dvfs {
+    FullSpeed,
+    // one speed for the whole run, the lowest one that keeps the task set schedulable
+    Static,
+    // lower the speed when jobs finish early, raise it again on release
+    CycleConserving,
+}
+
+#[derive(Debug, Clone)]
+struct EnergyReport {
+    horizon: usize,
+    energy: f64,
+    misses: usize,
+    time_at_level: Vec<f64>,
+    idle_time: f64,
+}
+
+fn static_speed(ps: &[RealtimeProcess], cpu: &Cpu, policy: Policy) -> usize {
+    match policy {
+        // EDF is schedulable as long as the density stays <= s
+        Policy::Edf => cpu.level_for(
+            ps.iter()
+                .map(|p| p.computation_time as f64 / p.deadline as f64)
+                .sum(),
+        ),
+        Policy::RateMonotonic => (0..cpu.levels.len())
+            .find(|&level| rm_schedulable_at(ps, cpu.levels[level].speed))
+            .unwrap_or(cpu.levels.len() - 1),
+    }
+}
+
+// same response time analysis as in the parent module, just with computation times scaled by 1 / s
+fn rm_schedulable_at(ps: &[RealtimeProcess], speed: f64) -> bool {
+    let order = ps.iter().sorted_by_key(|p| p.period_length).collect_vec();
+    order.iter().enumerate().all(|(n, p)| {
+        let c = p.computation_time as f64 / speed;
+        let mut response = c;
+        loop {
+            let next = c + order[..n]
+                .iter()
+                .map(|hp| {
+                    ((response + hp.jitter as f64) / hp.period_length as f64 - EPS).ceil()
+                        * (hp.computation_time as f64 / speed)
+                })
+                .sum::<f64>();
+            if next + p.jitter as f64 > p.deadline as f64 + EPS {
+                return false;
+            }
+            if next - response < EPS {
+                return true;
+            }
+            response = next;
+        }
+    })
+}
+
+// event based, because with fractional speeds nothing lines up with the rounds anymore.
+// `actual_cycles(process, job)` is what the job really needs, at most its computation_time
+fn simulate(
+    ps: &[RealtimeProcess],
+    cpu: &Cpu,
+    policy: Policy,
+    dvfs: Dvfs,
+    actual_cycles: &dyn Fn(usize, usize) -> f64,
+) -> EnergyReport {
+    let horizon = usize::try_from(&simulation_horizon(
+        &ps.iter().copied().enumerate().collect_vec(),
+    ))
+    .ok()
+    .filter(|h| *h <= MAX_SIMULATED_ROUNDS)
+    .unwrap_or_else(|| panic!("hyperperiod of {:?} too long for the DVFS simulation", ps));
+
+    // RM priority order, also used by cycle-conserving RM to hand out cycles
+    let rm_order = (0..ps.len())
+        .sorted_by_key(|&i| ps[i].period_length)
+        .collect_vec();
+    let static_level = static_speed(ps, cpu, policy);
+    let static_speed = cpu.levels[static_level].speed;
+
+    // per process state of its current job
+    let mut remaining = vec![0.0; ps.len()];
+    // worst case cycles left, the scheduler can't know the actual ones in advance
+    let mut wcet_left = vec![0.0; ps.len()];
+    let mut deadlines = vec![0.0; ps.len()];
+    let mut jobs = vec![0; ps.len()];
+    let mut next_release = ps.iter().map(|p| p.offset + p.jitter).collect_vec();
+    // ccEDF: assumed utilization, ccRM: cycles allocated until the next deadline
+    let mut utilization = vec![0.0; ps.len()];
+    let mut allocated = vec![0.0; ps.len()];
+
+    let mut report = EnergyReport {
+        horizon,
+        energy: 0.0,
+        misses: 0,
+        time_at_level: vec![0.0; cpu.levels.len()],
+        idle_time: 0.0,
+    };
+
+    let mut now = 0.0;
+    while now < horizon as f64 - EPS {
+        for i in 0..ps.len() {
+            if remaining[i] > EPS && deadlines[i] <= now + EPS {
+                report.misses += 1;
+                remaining[i] = 0.0;
+                wcet_left[i] = 0.0;
+                allocated[i] = 0.0;
+            }
+        }
+
+        let mut released = false;
+        for (i, p) in ps.iter().enumerate() {
+            if next_release[i] as f64 <= now + EPS {
+                remaining[i] = actual_cycles(i, jobs[i]).min(p.computation_time as f64);
+                wcet_left[i] = p.computation_time as f64;
+                deadlines[i] = (next_release[i] - p.jitter + p.deadline) as f64;
+                utilization[i] = p.computation_time as f64 / p.period_length as f64;
+                jobs[i] += 1;
+                next_release[i] += p.period_length;
+                released = true;
+            }
+        }
+
+        let next_deadline = (0..ps.len())
+            .map(|i| {
+                if remaining[i] > EPS {
+                    deadlines[i]
+                } else {
+                    (next_release[i] - ps[i].jitter + ps[i].deadline) as f64
+                }
+            })
+            .fold(f64::INFINITY, f64::min);
+        if released && dvfs == Dvfs::CycleConserving && policy == Policy::RateMonotonic {
+            // hand out as many cycles as the static speed could do until the next deadline,
+            // highest priority first
+            let mut cycles = static_speed * (next_deadline - now);
+            for &i in &rm_order {
+                allocated[i] = wcet_left[i].min(cycles);
+                cycles -= allocated[i];
+            }
+        }
+
+        let level = match (dvfs, policy) {
+            (Dvfs::FullSpeed, _) => cpu.levels.len() - 1,
+            (Dvfs::Static, _) => static_level,
+            (Dvfs::CycleConserving, Policy::Edf) => cpu.level_for(utilization.iter().sum()),
+            (Dvfs::CycleConserving, Policy::RateMonotonic) => {
+                cpu.level_for(allocated.iter().sum::<f64>() / (next_deadline - now))
+            }
+        };
+        let speed = cpu.levels[level].speed;
+
+        let running = (0..ps.len())
+            .filter(|&i| remaining[i] > EPS)
+            .min_by(|&a, &b| match policy {
+                Policy::Edf => deadlines[a].total_cmp(&deadlines[b]).then(a.cmp(&b)),
+                Policy::RateMonotonic => ps[a]
+                    .period_length
+                    .cmp(&ps[b].period_length)
+                    .then(a.cmp(&b)),
+            });
+
+        let next_event = next_release
+            .iter()
+            .map(|r| *r as f64)
+            .chain(
+                (0..ps.len())
+                    .filter(|&i| remaining[i] > EPS)
+                    .map(|i| deadlines[i]),
+            )
+            .chain(running.map(|i| now + remaining[i] / speed))
+            .fold(horizon as f64, f64::min);
+        let dt = next_event - now;
+
+        match running {
+            Some(i) => {
+                report.energy += cpu.levels[level].power * dt;
+                report.time_at_level[level] += dt;
+                remaining[i] -= speed * dt;
+                wcet_left[i] = (wcet_left[i] - speed * dt).max(0.0);
+                allocated[i] = (allocated[i] - speed * dt).max(0.0);
+                if remaining[i] <= EPS {
+                    // finished early: now we know how much this job really needed
+                    remaining[i] = 0.0;
+                    utilization[i] =
+                        (ps[i].computation_time as f64 - wcet_left[i]) / ps[i].period_length as f64;
+                    wcet_left[i] = 0.0;
+                    allocated[i] = 0.0;
+                }
+            }
+            None => {
+                report.energy += cpu.idle_power * dt;
+                report.idle_time += dt;
+            }
+        }
+
+        now = next_event;
+    }
+
+    report
+}
+
+pub fn test_dvfs() {
+    println!("\n## DVFS\n");
+
+    println!("\n### Pillai & Shin example:");
+    let processes = vec![
+        RealtimeProcess::new(3, 8),
+        RealtimeProcess::new(3, 10),
+        RealtimeProcess::new(1, 14),
+    ];
+    // first jobs use 2, 1 and 1 cycles, all later ones just 1
+    let actual_cycles = |i: usize, job: usize| if job == 0 { [2.0, 1.0, 1.0][i] } else { 1.0 };
+    let cpu = Cpu::cubic(&[0.5, 0.75, 1.0], 0.1, 1.0, 0.05);
+    println!("levels: {:?}, idle: {}", cpu.levels, cpu.idle_power);
+
+    for policy in [Policy::Edf, Policy::RateMonotonic] {
+        let full = simulate(&processes, &cpu, policy, Dvfs::FullSpeed, &actual_cycles);
+        println!("{:?} over hyperperiod {}:", policy, full.horizon);
+        for dvfs in [Dvfs::FullSpeed, Dvfs::Static, Dvfs::CycleConserving] {
+            let report = simulate(&processes, &cpu, policy, dvfs, &actual_cycles);
+            println!(
+                "  {:<16} energy {:>7.2} ({:>5.1}% of full speed), misses: {}, time per level: {:?}, idle: {:.2}",
+                format!("{:?}", dvfs),
+                report.energy,
+                100.0 * report.energy / full.energy,
+                report.misses,
+                report
+                    .time_at_level
+                    .iter()
+                    .map(|t| format!("{:.2}", t))
+                    .collect_vec(),
+                report.idle_time
+            );
+            assert_eq!(report.misses, 0);
+        }
+    }
+}
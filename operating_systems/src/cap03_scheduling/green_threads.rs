@@ -0,0 +1,367 @@
+use std::{
+    cell::RefCell,
+    collections::{BTreeSet, VecDeque},
+    ops::{Generator, GeneratorState},
+    pin::Pin,
+    rc::Rc,
+};
+
+use itertools::Itertools;
+
+use super::{
+    round_robin, schedule_to_text_diagram, simulation_horizon, DeadlineMiss, Process,
+    RealtimeEvaluation, RealtimeProcess, Schedule, MAX_SIMULATED_ROUNDS,
+};
+
+// a green thread is a plain coroutine. every resume is one round of cpu time: the task does one
+// unit of work and yields the cpu back, the resume that does the last unit returns instead.
+// so a task with computation_time n yields n - 1 times
+type Task = Pin<Box<dyn Generator<Yield = (), Return = ()>>>;
+
+// the runtime only resumes, the policy decides whom
+trait GreenPolicy {
+    // arrival or release
+    fn ready(&mut self, task: usize);
+    fn pick(&mut self) -> Option<usize>;
+    // task got resumed for one round and either yielded or returned
+    fn ran(&mut self, task: usize, finished: bool);
+    // deadline passed, the runtime dropped the task
+    fn abort(&mut self, task: usize);
+}
+
+// same decisions as `round_robin`, the quantum is counted in yields
+struct RoundRobin {
+    quantum: usize,
+    queue: VecDeque<usize>,
+    time_spent_on_cur: usize,
+}
+
+impl RoundRobin {
+    fn new(quantum: usize) -> Self {
+        RoundRobin {
+            quantum,
+            queue: VecDeque::new(),
+            time_spent_on_cur: 0,
+        }
+    }
+}
+
+impl GreenPolicy for RoundRobin {
+    fn ready(&mut self, task: usize) {
+        self.queue.push_back(task);
+    }
+
+    fn pick(&mut self) -> Option<usize> {
+        if self.time_spent_on_cur >= self.quantum {
+            self.queue.rotate_left(1.min(self.queue.len()));
+            self.time_spent_on_cur = 0;
+        }
+        self.queue.front().copied()
+    }
+
+    fn ran(&mut self, task: usize, finished: bool) {
+        assert_eq!(self.queue.front(), Some(&task));
+        if finished {
+            self.queue.pop_front();
+            self.time_spent_on_cur = 0;
+        } else {
+            self.time_spent_on_cur += 1;
+        }
+    }
+
+    fn abort(&mut self, task: usize) {
+        self.queue.retain(|t| *t != task);
+    }
+}
+
+// preemptive, lower key == higher priority, ties by task index. like `fixed_priority`
+struct FixedPriority {
+    priorities: Vec<usize>,
+    ready: BTreeSet<(usize, usize)>,
+}
+
+impl FixedPriority {
+    fn new(priorities: Vec<usize>) -> Self {
+        FixedPriority {
+            priorities,
+            ready: BTreeSet::new(),
+        }
+    }
+}
+
+impl GreenPolicy for FixedPriority {
+    fn ready(&mut self, task: usize) {
+        self.ready.insert((self.priorities[task], task));
+    }
+
+    fn pick(&mut self) -> Option<usize> {
+        self.ready.first().map(|(_, task)| *task)
+    }
+
+    fn ran(&mut self, task: usize, finished: bool) {
+        if finished {
+            self.abort(task);
+        }
+    }
+
+    fn abort(&mut self, task: usize) {
+        self.ready.remove(&(self.priorities[task], task));
+    }
+}
+
+struct GreenRuntime<P: GreenPolicy> {
+    policy: P,
+    tasks: Vec<Option<Task>>,
+    // rounds each task got since it was spawned, the work it has done
+    rounds: Vec<usize>,
+    schedule: Schedule,
+}
+
+impl<P: GreenPolicy> GreenRuntime<P> {
+    fn new(policy: P, task_count: usize) -> Self {
+        GreenRuntime {
+            policy,
+            tasks: (0..task_count).map(|_| None).collect(),
+            rounds: vec![0; task_count],
+            schedule: Schedule::new(),
+        }
+    }
+
+    fn spawn(&mut self, id: usize, task: Task) {
+        assert!(self.tasks[id].is_none(), "task {} is still running", id);
+        self.tasks[id] = Some(task);
+        self.rounds[id] = 0;
+        self.policy.ready(id);
+    }
+
+    // the rounds it got, if it was still running
+    fn kill(&mut self, id: usize) -> Option<usize> {
+        drop(self.tasks[id].take()?);
+        self.policy.abort(id);
+        Some(self.rounds[id])
+    }
+
+    fn alive(&self) -> bool {
+        self.tasks.iter().any(Option::is_some)
+    }
+
+    // one round: resume whatever the policy wants, or idle
+    fn step(&mut self) {
+        let Some(id) = self.policy.pick() else {
+            self.schedule.push(None);
+            return;
+        };
+        let task = self.tasks[id]
+            .as_mut()
+            .unwrap_or_else(|| panic!("policy picked dead task {}", id));
+
+        let finished = match task.as_mut().resume(()) {
+            GeneratorState::Yielded(()) => false,
+            GeneratorState::Complete(()) => true,
+        };
+        self.rounds[id] += 1;
+        if finished {
+            self.tasks[id] = None;
+        }
+        self.policy.ran(id, finished);
+        self.schedule.push(Some(id));
+    }
+
+    // `bodies[i]` is spawned once `ps[i]` arrives
+    fn run_processes(mut self, ps: &[Process], bodies: Vec<Task>) -> Schedule {
+        let mut pending = ps.iter().zip(bodies).enumerate().collect_vec();
+        // stable, same arrival order as `round_robin`
+        pending.sort_by_key(|(_, (p, _))| p.arrival);
+        let mut pending: VecDeque<_> = pending.into();
+
+        let mut round = 0;
+        while !pending.is_empty() || self.alive() {
+            while pending
+                .front()
+                .is_some_and(|(_, (p, _))| p.arrival <= round)
+            {
+                let (id, (_, body)) = pending.pop_front().unwrap();
+                self.spawn(id, body);
+            }
+            self.step();
+            round += 1;
+        }
+
+        self.schedule
+    }
+
+    // `jobs[i](n)` creates the coroutine for the n-th job of `ps[i]`.
+    // releases, deadlines and aborts like `fixed_priority`
+    fn run_realtime(
+        mut self,
+        ps: &[RealtimeProcess],
+        jobs: Vec<Box<dyn Fn(usize) -> Task>>,
+    ) -> (Schedule, Vec<DeadlineMiss>) {
+        let tmax = usize::try_from(&simulation_horizon(
+            &ps.iter().copied().enumerate().collect_vec(),
+        ))
+        .ok()
+        .filter(|h| *h <= MAX_SIMULATED_ROUNDS)
+        .unwrap_or_else(|| panic!("hyperperiod of {:?} too long to run", ps));
+
+        let mut released = vec![0; ps.len()];
+        let mut deadlines = vec![0; ps.len()];
+        let mut misses = Vec::new();
+        for round in 0..=tmax {
+            for (i, deadline) in deadlines.iter().enumerate() {
+                if *deadline != round {
+                    continue;
+                }
+                if let Some(done) = self.kill(i) {
+                    misses.push(DeadlineMiss {
+                        process: i,
+                        deadline: round,
+                        remaining: ps[i].computation_time - done,
+                    });
+                }
+            }
+            if round == tmax {
+                break;
+            }
+
+            for (i, p) in ps.iter().enumerate() {
+                let release = p.offset + p.jitter;
+                if round >= release && (round - release) % p.period_length == 0 {
+                    self.spawn(i, jobs[i](released[i]));
+                    released[i] += 1;
+                    deadlines[i] = round - p.jitter + p.deadline;
+                }
+            }
+
+            self.step();
+        }
+
+        (self.schedule, misses)
+    }
+}
+
+// shared between the coroutines, so they can prove that they really ran in that order
+type Log = Rc<RefCell<Vec<(usize, u64)>>>;
+
+// n units of real work: each resume computes the next fibonacci number (mod 2^64) and logs it
+fn fibonacci_task(id: usize, n: usize, log: &Log) -> Task {
+    let log = log.clone();
+    Box::pin(move || {
+        let (mut a, mut b) = (0_u64, 1_u64);
+        for unit in 0..n {
+            if unit > 0 {
+                yield;
+            }
+            (a, b) = (b, a.wrapping_add(b));
+            log.borrow_mut().push((id, a));
+        }
+    })
+}
+
+fn assert_log_matches(schedule: &Schedule, log: &Log) {
+    assert_eq!(
+        schedule.iter().flatten().copied().collect_vec(),
+        log.borrow().iter().map(|(id, _)| *id).collect_vec(),
+        "coroutines ran in a different order than the runtime recorded"
+    );
+}
+
+pub fn test_green_threads() {
+    println!("\n## GREEN THREADS\n");
+
+    println!("\n### Round Robin, Altklausur SS15\n");
+    let v = vec![
+        Process::new(1, 6),
+        Process::new(4, 2),
+        Process::new(2, 4),
+        Process::new(9, 3),
+        Process::new(8, 4),
+    ];
+    let quantum = 3;
+
+    let log: Log = Rc::default();
+    let bodies = v
+        .iter()
+        .enumerate()
+        .map(|(i, p)| fibonacci_task(i, p.computation_time, &log))
+        .collect_vec();
+    let schedule = GreenRuntime::new(RoundRobin::new(quantum), v.len()).run_processes(&v, bodies);
+    assert_log_matches(&schedule, &log);
+    assert_eq!(*schedule, *round_robin(v.clone().into_iter(), quantum));
+    println!("coroutine log: {:?}", log.borrow());
+    schedule_to_text_diagram(&v, schedule);
+    println!("matches the simulated schedule");
+
+    println!("\n### Rate Monotonic, Altklausur SS15\n");
+    let processes = vec![
+        RealtimeProcess::new(1, 6),
+        RealtimeProcess::new(1, 3),
+        RealtimeProcess::new(3, 18),
+        RealtimeProcess::new(2, 9),
+    ];
+    run_realtime_and_compare(&processes, |p| p.period_length);
+
+    println!("\n### Deadline Monotonic vs Rate Monotonic, constrained deadline\n");
+    let processes = vec![
+        RealtimeProcess::new(2, 5),
+        RealtimeProcess {
+            deadline: 2,
+            ..RealtimeProcess::new(2, 6)
+        },
+    ];
+    println!("RM:");
+    run_realtime_and_compare(&processes, |p| p.period_length);
+    println!("DM:");
+    run_realtime_and_compare(&processes, |p| p.deadline);
+}
+
+fn run_realtime_and_compare(
+    processes: &[RealtimeProcess],
+    priority: impl Fn(&RealtimeProcess) -> usize,
+) {
+    let log: Log = Rc::default();
+    let jobs = processes
+        .iter()
+        .enumerate()
+        .map(|(i, p)| {
+            let (log, n) = (log.clone(), p.computation_time);
+            Box::new(move |_job| fibonacci_task(i, n, &log)) as Box<dyn Fn(usize) -> Task>
+        })
+        .collect_vec();
+    let runtime = GreenRuntime::new(
+        FixedPriority::new(processes.iter().map(&priority).collect()),
+        processes.len(),
+    );
+    let (schedule, misses) = runtime.run_realtime(processes, jobs);
+    assert_log_matches(&schedule, &log);
+
+    let RealtimeEvaluation::Simulated {
+        schedule: simulated,
+        misses: simulated_misses,
+    } = super::fixed_priority(processes.iter().copied(), priority)
+    else {
+        unreachable!("hyperperiod fits, the runtime just ran it");
+    };
+    assert_eq!(*schedule, *simulated);
+    assert_eq!(
+        misses
+            .iter()
+            .map(|m| (m.deadline, m.process, m.remaining))
+            .sorted()
+            .collect_vec(),
+        simulated_misses
+            .iter()
+            .map(|m| (m.deadline, m.process, m.remaining))
+            .sorted()
+            .collect_vec()
+    );
+
+    schedule_to_text_diagram(processes, schedule);
+    for miss in misses {
+        println!(
+            "Process {} got killed at its deadline {}, remaining computation time {}",
+            miss.process, miss.deadline, miss.remaining
+        );
+    }
+    println!("matches the simulated schedule");
+}
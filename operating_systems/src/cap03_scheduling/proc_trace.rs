@@ -0,0 +1,299 @@
+use std::{
+    fs, io,
+    process::{Child, Command},
+    thread,
+    time::Duration,
+};
+
+use itertools::Itertools;
+
+use super::{round_robin, schedule_to_text_diagram, Process, Schedule};
+
+// samples /proc/<pid>/stat and /proc/<pid>/schedstat, turns what the kernel did into `Process`es
+// and replays them through the simulated schedulers. linux only.
+// caveats: the kernel has more than one cpu and real processes also sleep, the simulation
+// assumes one cpu and purely cpu bound processes. schedstat only covers the main thread.
+
+// USER_HZ, the unit of the times in /proc/<pid>/stat. 100 on every common architecture
+const CLOCK_TICKS_PER_SEC: u64 = 100;
+
+#[derive(Debug, Clone, Copy)]
+struct ProcSample {
+    // since the start of the sampling window
+    at: Duration,
+    state: char,
+    // in clock ticks since boot
+    start_time: u64,
+    // from schedstat, cumulative since the process started
+    run_ns: u64,
+    wait_ns: u64,
+    timeslices: u64,
+}
+
+#[derive(Debug, Clone)]
+struct ProcTrace {
+    pid: u32,
+    comm: String,
+    samples: Vec<ProcSample>,
+    // vanished or became a zombie while we were watching
+    exited: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct KernelObserved {
+    run: Duration,
+    // runnable, but not running
+    wait: Duration,
+    timeslices: u64,
+    // None if it was still alive when we stopped sampling
+    exited_after: Option<Duration>,
+}
+
+fn invalid(what: &str, content: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("could not parse {}: {:?}", what, content),
+    )
+}
+
+fn uptime() -> io::Result<Duration> {
+    let content = fs::read_to_string("/proc/uptime")?;
+    let seconds: f64 = content
+        .split_whitespace()
+        .next()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| invalid("/proc/uptime", &content))?;
+    Ok(Duration::from_secs_f64(seconds))
+}
+
+fn read_sample(pid: u32, at: Duration) -> io::Result<(String, ProcSample)> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid))?;
+    // comm may contain spaces and parens itself, so split at the last paren
+    let (head, tail) = stat
+        .rsplit_once(')')
+        .ok_or_else(|| invalid("stat", &stat))?;
+    let comm = head
+        .split_once('(')
+        .ok_or_else(|| invalid("stat", &stat))?
+        .1
+        .to_owned();
+    // fields after comm, the first one is field 3 (state) in proc(5)
+    let fields = tail.split_whitespace().collect_vec();
+    let field = |n: usize| -> io::Result<u64> {
+        fields
+            .get(n - 3)
+            .and_then(|f| f.parse().ok())
+            .ok_or_else(|| invalid("stat", &stat))
+    };
+    let state = fields
+        .first()
+        .and_then(|s| s.chars().next())
+        .ok_or_else(|| invalid("stat", &stat))?;
+    let start_time = field(22)?;
+
+    let schedstat = fs::read_to_string(format!("/proc/{}/schedstat", pid))?;
+    let (run_ns, wait_ns, timeslices) = schedstat
+        .split_whitespace()
+        .map(|f| f.parse::<u64>())
+        .collect_tuple()
+        .and_then(|(a, b, c)| Some((a.ok()?, b.ok()?, c.ok()?)))
+        .ok_or_else(|| invalid("schedstat", &schedstat))?;
+
+    Ok((
+        comm,
+        ProcSample {
+            at,
+            state,
+            start_time,
+            run_ns,
+            wait_ns,
+            timeslices,
+        },
+    ))
+}
+
+// samples until every process is gone or `max` is over.
+// `window_start` is the uptime the window starts at, the start times of the processes are relative to it
+fn collect(
+    pids: &[u32],
+    window_start: Duration,
+    interval: Duration,
+    max: Duration,
+) -> io::Result<Vec<ProcTrace>> {
+    let mut traces = pids
+        .iter()
+        .map(|&pid| ProcTrace {
+            pid,
+            comm: String::new(),
+            samples: Vec::new(),
+            exited: false,
+        })
+        .collect_vec();
+
+    loop {
+        let at = uptime()?.saturating_sub(window_start);
+        for trace in traces.iter_mut().filter(|t| !t.exited) {
+            match read_sample(trace.pid, at) {
+                Ok((comm, sample)) => {
+                    trace.comm = comm;
+                    trace.samples.push(sample);
+                    // zombies still have their stat, but nothing changes anymore
+                    trace.exited = sample.state == 'Z';
+                }
+                // vanished (or never existed)
+                Err(_) => trace.exited = true,
+            }
+        }
+        if traces.iter().all(|t| t.exited) || at >= max {
+            break;
+        }
+        thread::sleep(interval);
+    }
+
+    Ok(traces)
+}
+
+impl ProcTrace {
+    fn started_at(&self, window_start: Duration) -> Option<Duration> {
+        let first = self.samples.first()?;
+        Some(
+            Duration::from_nanos(first.start_time * 1_000_000_000 / CLOCK_TICKS_PER_SEC)
+                .saturating_sub(window_start),
+        )
+    }
+
+    // what the kernel did during the sampling window
+    fn observed(&self, window_start: Duration) -> Option<KernelObserved> {
+        let (first, last) = (self.samples.first()?, self.samples.last()?);
+        // started while we were watching: count everything since its start, not since the first sample
+        let (run_before, wait_before, slices_before) =
+            if self.started_at(window_start)? > Duration::ZERO {
+                (0, 0, 0)
+            } else {
+                (first.run_ns, first.wait_ns, first.timeslices)
+            };
+        Some(KernelObserved {
+            run: Duration::from_nanos(last.run_ns - run_before),
+            wait: Duration::from_nanos(last.wait_ns - wait_before),
+            timeslices: last.timeslices - slices_before,
+            // only as exact as the sampling interval
+            exited_after: self.exited.then(|| {
+                last.at
+                    .saturating_sub(self.started_at(window_start).unwrap_or_default())
+            }),
+        })
+    }
+
+    // one round of the simulation == `tick` of real time
+    fn to_process(&self, window_start: Duration, tick: Duration) -> Option<Process> {
+        let observed = self.observed(window_start)?;
+        let arrival = self.started_at(window_start)?.as_nanos() / tick.as_nanos();
+        let computation_time = observed.run.as_nanos().div_ceil(tick.as_nanos()).max(1);
+        Some(Process::new(arrival as usize, computation_time as usize))
+    }
+}
+
+// last round a process ran in, +1
+fn completion_times(count: usize, s: &Schedule) -> Vec<Option<usize>> {
+    (0..count)
+        .map(|i| s.iter().rposition(|x| *x == Some(i)).map(|r| r + 1))
+        .collect_vec()
+}
+
+fn spawn_busy_loop(iterations: usize) -> io::Result<Child> {
+    Command::new("sh")
+        .arg("-c")
+        .arg(format!(
+            "i=0; while [ $i -lt {} ]; do i=$((i+1)); done",
+            iterations
+        ))
+        .spawn()
+}
+
+// `args`: pids to watch. without any, a few staggered shell busy loops get spawned and watched
+pub fn replay(args: &[String]) -> io::Result<()> {
+    println!("\n## REPLAY OF /proc TRACES\n");
+
+    let tick = Duration::from_millis(10);
+    let quantum = 3;
+
+    let window_start = uptime()?;
+    let mut children = Vec::new();
+    let pids = if args.is_empty() {
+        for iterations in [300_000, 100_000, 200_000, 50_000] {
+            children.push(spawn_busy_loop(iterations)?);
+            thread::sleep(Duration::from_millis(30));
+        }
+        children.iter().map(Child::id).collect_vec()
+    } else {
+        args.iter()
+            .map(|a| {
+                a.parse::<u32>()
+                    .map_err(|e| invalid(&format!("pid ({})", e), a))
+            })
+            .try_collect()?
+    };
+
+    // reap our own children in the background, otherwise they stay zombies
+    let reaper = thread::spawn(move || {
+        for mut child in children {
+            let _ = child.wait();
+        }
+    });
+    let traces = collect(&pids, window_start, tick / 2, Duration::from_secs(30))?;
+    let _ = reaper.join();
+
+    let traces = traces
+        .into_iter()
+        .filter(|t| !t.samples.is_empty())
+        .collect_vec();
+    let processes = traces
+        .iter()
+        .filter_map(|t| t.to_process(window_start, tick))
+        .collect_vec();
+    for (i, (trace, p)) in traces.iter().zip(&processes).enumerate() {
+        println!(
+            "Process {}: pid {} ({}), {} samples -> {:?}",
+            i,
+            trace.pid,
+            trace.comm,
+            trace.samples.len(),
+            p
+        );
+    }
+    if processes.is_empty() {
+        println!("nothing to replay");
+        return Ok(());
+    }
+
+    println!("\n### Round Robin, quantum {} x {:?}\n", quantum, tick);
+    let schedule = round_robin(processes.clone().into_iter(), quantum);
+    let completions = completion_times(processes.len(), &schedule);
+
+    println!(
+        "{:<10} {:>14} {:>14} {:>14} {:>14} {:>10}",
+        "", "sim. wait", "kernel wait", "sim. turnar.", "kernel turnar.", "timeslices"
+    );
+    for (i, (trace, p)) in traces.iter().zip(&processes).enumerate() {
+        let observed = trace
+            .observed(window_start)
+            .expect("had samples, so it got converted");
+        let turnaround = completions[i].map(|c| tick * (c - p.arrival) as u32);
+        let wait = turnaround.map(|t| t.saturating_sub(tick * p.computation_time as u32));
+        println!(
+            "Process {:<2} {:>14} {:>14} {:>14} {:>14} {:>10}",
+            i,
+            format!("{:.0?}", wait.unwrap_or_default()),
+            format!("{:.0?}", observed.wait),
+            format!("{:.0?}", turnaround.unwrap_or_default()),
+            observed
+                .exited_after
+                .map(|t| format!("{:.0?}", t))
+                .unwrap_or_else(|| "still running".to_owned()),
+            observed.timeslices
+        );
+    }
+    schedule_to_text_diagram(&processes, schedule);
+
+    Ok(())
+}
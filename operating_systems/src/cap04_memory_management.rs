@@ -0,0 +1,500 @@
+use itertools::Itertools;
+
+pub mod belady;
+
+// page replacement: which page leaves memory on a page fault when every frame is taken.
+// a policy only picks the victim, `simulate` does the rest: hits, loading into free frames first,
+// the referenced and modified bits, and counting faults and write-backs of modified pages
+
+pub type Page = usize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reference {
+    pub page: Page,
+    pub write: bool,
+}
+
+// "7 0 1 2w 0", a w marks a write
+pub fn parse_references(s: &str) -> Result<Vec<Reference>, String> {
+    s.split_whitespace()
+        .map(|r| {
+            let (page, write) = match r.strip_suffix('w') {
+                Some(page) => (page, true),
+                None => (r, false),
+            };
+            let page = page
+                .parse()
+                .map_err(|_| format!("{:?} is no page number with an optional w", r))?;
+            Ok(Reference { page, write })
+        })
+        .collect()
+}
+
+pub fn reads(pages: &[Page]) -> Vec<Reference> {
+    pages
+        .iter()
+        .map(|&page| Reference { page, write: false })
+        .collect_vec()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    pub page: Page,
+    // the R and M bits the MMU sets
+    pub referenced: bool,
+    pub modified: bool,
+    // FIFO order, second chance moves a page to the back by giving it the next number
+    pub loaded_at: usize,
+    // step
+    pub last_used: usize,
+}
+
+// the frames, and the hand of the clock
+#[derive(Debug, Clone, Default)]
+pub struct Memory {
+    pub frames: Vec<Option<Frame>>,
+    pub hand: usize,
+}
+
+impl Memory {
+    fn frame(&self, i: usize) -> &Frame {
+        self.frames[i]
+            .as_ref()
+            .expect("only asked when memory is full")
+    }
+
+    // the `loaded_at` behind every page so far
+    fn next_in_line(&self) -> usize {
+        self.frames
+            .iter()
+            .flatten()
+            .map(|f| f.loaded_at + 1)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+pub trait PageReplacement {
+    fn name(&self) -> String;
+    // the frame to evict, on a fault while every frame is taken. `refs[step]` faulted
+    fn victim(&self, memory: &mut Memory, refs: &[Reference], step: usize) -> usize;
+    // before every reference, like a timer interrupt
+    fn tick(&self, _memory: &mut Memory, _step: usize) {}
+}
+
+pub struct Fifo;
+
+impl PageReplacement for Fifo {
+    fn name(&self) -> String {
+        "FIFO".to_owned()
+    }
+
+    fn victim(&self, memory: &mut Memory, _refs: &[Reference], _step: usize) -> usize {
+        (0..memory.frames.len())
+            .min_by_key(|&i| memory.frame(i).loaded_at)
+            .expect("at least one frame")
+    }
+}
+
+pub struct Lru;
+
+impl PageReplacement for Lru {
+    fn name(&self) -> String {
+        "LRU".to_owned()
+    }
+
+    fn victim(&self, memory: &mut Memory, _refs: &[Reference], _step: usize) -> usize {
+        (0..memory.frames.len())
+            .min_by_key(|&i| memory.frame(i).last_used)
+            .expect("at least one frame")
+    }
+}
+
+// Belady's: the page used again furthest in the future, or never. needs the whole reference
+// string up front, so only good as the lower bound for the others
+pub struct Optimal;
+
+impl PageReplacement for Optimal {
+    fn name(&self) -> String {
+        "OPT".to_owned()
+    }
+
+    fn victim(&self, memory: &mut Memory, refs: &[Reference], step: usize) -> usize {
+        let next_use = |page| {
+            refs[step + 1..]
+                .iter()
+                .position(|r| r.page == page)
+                .unwrap_or(usize::MAX)
+        };
+        // among pages never used again the least recently used one. not the first frame, with
+        // more frames the pages sit elsewhere and OPT would stop being a stack algorithm
+        (0..memory.frames.len())
+            .max_by_key(|&i| {
+                let frame = memory.frame(i);
+                (next_use(frame.page), usize::MAX - frame.last_used)
+            })
+            .expect("at least one frame")
+    }
+}
+
+// FIFO, but a page referenced since it got to the front is moved to the back instead, with its
+// R bit cleared
+pub struct SecondChance;
+
+impl PageReplacement for SecondChance {
+    fn name(&self) -> String {
+        "Second Chance".to_owned()
+    }
+
+    fn victim(&self, memory: &mut Memory, _refs: &[Reference], _step: usize) -> usize {
+        loop {
+            let oldest = (0..memory.frames.len())
+                .min_by_key(|&i| memory.frame(i).loaded_at)
+                .expect("at least one frame");
+            let next = memory.next_in_line();
+            let frame = memory.frames[oldest].as_mut().expect("memory is full");
+            if !frame.referenced {
+                return oldest;
+            }
+            frame.referenced = false;
+            frame.loaded_at = next;
+        }
+    }
+}
+
+// second chance without moving pages around: the frames form a ring, the hand points at the
+// oldest one
+pub struct Clock;
+
+impl PageReplacement for Clock {
+    fn name(&self) -> String {
+        "Clock".to_owned()
+    }
+
+    fn victim(&self, memory: &mut Memory, _refs: &[Reference], _step: usize) -> usize {
+        let n = memory.frames.len();
+        loop {
+            let hand = memory.hand;
+            memory.hand = (hand + 1) % n;
+            let frame = memory.frames[hand].as_mut().expect("memory is full");
+            if !frame.referenced {
+                return hand;
+            }
+            frame.referenced = false;
+        }
+    }
+}
+
+// not recently used: the lowest class of (R, M), 0 = neither, 3 = both, the first frame within
+// it. the R bits get cleared every `reset_interval` references, else all pages end up in R
+pub struct Nru {
+    pub reset_interval: usize,
+}
+
+impl PageReplacement for Nru {
+    fn name(&self) -> String {
+        format!("NRU({})", self.reset_interval)
+    }
+
+    fn victim(&self, memory: &mut Memory, _refs: &[Reference], _step: usize) -> usize {
+        (0..memory.frames.len())
+            .min_by_key(|&i| {
+                let frame = memory.frame(i);
+                2 * frame.referenced as usize + frame.modified as usize
+            })
+            .expect("at least one frame")
+    }
+
+    fn tick(&self, memory: &mut Memory, step: usize) {
+        if step > 0 && step.is_multiple_of(self.reset_interval) {
+            for frame in memory.frames.iter_mut().flatten() {
+                frame.referenced = false;
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Step {
+    pub reference: Reference,
+    pub fault: bool,
+    pub evicted: Option<Frame>,
+    // the frames after it
+    pub frames: Vec<Option<Frame>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Simulation {
+    pub steps: Vec<Step>,
+}
+
+impl Simulation {
+    pub fn faults(&self) -> usize {
+        self.steps.iter().filter(|s| s.fault).count()
+    }
+
+    // modified pages that had to go back to disk
+    pub fn write_backs(&self) -> usize {
+        self.steps
+            .iter()
+            .filter(|s| s.evicted.is_some_and(|f| f.modified))
+            .count()
+    }
+
+    pub fn victims(&self) -> Vec<Page> {
+        self.steps
+            .iter()
+            .filter_map(|s| s.evicted.map(|f| f.page))
+            .collect_vec()
+    }
+}
+
+pub fn simulate(
+    policy: &dyn PageReplacement,
+    refs: &[Reference],
+    frame_count: usize,
+) -> Simulation {
+    let mut steps = Vec::new();
+    run(
+        policy,
+        refs,
+        frame_count,
+        |reference, fault, evicted, memory| {
+            steps.push(Step {
+                reference: *reference,
+                fault,
+                evicted,
+                frames: memory.frames.clone(),
+            })
+        },
+    );
+    Simulation { steps }
+}
+
+// `simulate` without keeping every step, for searching through many reference strings
+pub fn count_faults(policy: &dyn PageReplacement, refs: &[Reference], frame_count: usize) -> usize {
+    let mut faults = 0;
+    run(policy, refs, frame_count, |_, fault, _, _| {
+        faults += fault as usize
+    });
+    faults
+}
+
+// `observe` gets every reference, whether it faulted, the page evicted for it and the memory after
+fn run(
+    policy: &dyn PageReplacement,
+    refs: &[Reference],
+    frame_count: usize,
+    mut observe: impl FnMut(&Reference, bool, Option<Frame>, &Memory),
+) {
+    assert!(frame_count > 0, "no frames");
+    let mut memory = Memory {
+        frames: vec![None; frame_count],
+        hand: 0,
+    };
+    for (step, reference) in refs.iter().enumerate() {
+        policy.tick(&mut memory, step);
+        let resident = memory
+            .frames
+            .iter()
+            .position(|f| f.is_some_and(|f| f.page == reference.page));
+        let (i, fault, evicted) = match resident {
+            Some(i) => (i, false, None),
+            None => match memory.frames.iter().position(Option::is_none) {
+                Some(free) => (free, true, None),
+                None => {
+                    let victim = policy.victim(&mut memory, refs, step);
+                    (victim, true, memory.frames[victim])
+                }
+            },
+        };
+        if fault {
+            memory.frames[i] = Some(Frame {
+                page: reference.page,
+                referenced: false,
+                modified: false,
+                loaded_at: memory.next_in_line(),
+                last_used: step,
+            });
+        }
+        let frame = memory.frames[i].as_mut().expect("just loaded");
+        frame.referenced = true;
+        frame.modified |= reference.write;
+        frame.last_used = step;
+        observe(reference, fault, evicted, &memory);
+    }
+}
+
+// one column per reference, one row per frame, like `schedule_to_text_diagram`. below, w marks
+// the writes, * the faults and # the faults that had to write a modified page back first.
+// pages up to z take one character, with bigger ones every column is as wide as the biggest
+pub fn frame_table_to_text_diagram(sim: &Simulation) {
+    let max_page = sim
+        .steps
+        .iter()
+        .map(|s| s.reference.page)
+        .max()
+        .unwrap_or(0);
+    let width = if max_page < 36 {
+        1
+    } else {
+        max_page.to_string().len() + 1
+    };
+    let page_cell = |page: Page| match std::char::from_digit(page as u32, 36) {
+        Some(c) if width == 1 => c.to_string(),
+        _ => format!("{:>w$}", page, w = width),
+    };
+    let print_row = |label: String, cells: Vec<String>| {
+        println!(
+            "{:<9}: {}",
+            label,
+            cells
+                .into_iter()
+                .enumerate()
+                .map(|(i, c)| if i % 5 == 0 { format!("|{}", c) } else { c })
+                .collect::<String>()
+        );
+    };
+    let row = |label: String, cells: Vec<char>| {
+        let cells = cells
+            .into_iter()
+            .map(|c| format!("{:>w$}", c, w = width))
+            .collect_vec();
+        print_row(label, cells)
+    };
+    print_row(
+        "Reference".to_owned(),
+        sim.steps
+            .iter()
+            .map(|s| page_cell(s.reference.page))
+            .collect_vec(),
+    );
+    let frame_count = sim.steps.first().map_or(0, |s| s.frames.len());
+    for i in 0..frame_count {
+        print_row(
+            format!("Frame {}", i),
+            sim.steps
+                .iter()
+                .map(|s| match s.frames[i] {
+                    Some(f) => page_cell(f.page),
+                    None => " ".repeat(width),
+                })
+                .collect_vec(),
+        );
+    }
+    if sim.steps.iter().any(|s| s.reference.write) {
+        row(
+            "Write".to_owned(),
+            sim.steps
+                .iter()
+                .map(|s| if s.reference.write { 'w' } else { ' ' })
+                .collect_vec(),
+        );
+    }
+    row(
+        "Fault".to_owned(),
+        sim.steps
+            .iter()
+            .map(|s| match (s.fault, s.evicted) {
+                (true, Some(f)) if f.modified => '#',
+                (true, _) => '*',
+                (false, _) => ' ',
+            })
+            .collect_vec(),
+    );
+    // print scale
+    let legend = format!(
+        "Step/5   : {}",
+        format!("|{}", " ".repeat(5 * width)).repeat(sim.steps.len().div_ceil(5))
+    );
+    println!("{}", "-".repeat(legend.len()));
+    println!("{}", legend);
+    println!("{} faults, {} write-backs", sim.faults(), sim.write_backs());
+}
+
+fn policies() -> Vec<Box<dyn PageReplacement>> {
+    vec![
+        Box::new(Fifo),
+        Box::new(Lru),
+        Box::new(Optimal),
+        Box::new(SecondChance),
+        Box::new(Clock),
+        Box::new(Nru { reset_interval: 4 }),
+    ]
+}
+
+pub fn test_page_replacement() {
+    println!("\n## PAGE REPLACEMENT\n");
+
+    // Silberschatz, 3 frames
+    let refs = reads(&[7, 0, 1, 2, 0, 3, 0, 4, 2, 3, 0, 3, 2, 1, 2, 0, 1, 7, 0, 1]);
+    let mut faults = Vec::new();
+    for policy in policies() {
+        println!("\n### {}, 3 frames\n", policy.name());
+        let sim = simulate(policy.as_ref(), &refs, 3);
+        frame_table_to_text_diagram(&sim);
+        faults.push(sim.faults());
+    }
+    assert_eq!(faults[..3], [15, 12, 9]);
+    // OPT is the lower bound
+    assert!(faults.iter().all(|f| *f >= faults[2]));
+    // the same policy, the clock only saves moving pages around
+    assert_eq!(
+        simulate(&SecondChance, &refs, 3).victims(),
+        simulate(&Clock, &refs, 3).victims()
+    );
+
+    println!("\n### NRU with writes, 3 frames\n");
+    // NRU prefers to drop a page it doesn't have to write back
+    let refs = parse_references("0w 1 2 3 0 1w 4 0 2w 3 1 4 0w 2 1 3").unwrap();
+    let write_backs = [
+        Box::new(Nru { reset_interval: 4 }) as Box<dyn PageReplacement>,
+        Box::new(Lru),
+    ]
+    .iter()
+    .map(|policy| {
+        let sim = simulate(policy.as_ref(), &refs, 3);
+        println!("{}:", policy.name());
+        frame_table_to_text_diagram(&sim);
+        sim.write_backs()
+    })
+    .collect_vec();
+    assert!(write_backs[0] < write_backs[1]);
+
+    println!("\n### Page numbers past z, 2 frames\n");
+    let refs = parse_references("100 7 36w 100 1000 7").unwrap();
+    frame_table_to_text_diagram(&simulate(&Lru, &refs, 2));
+    let error = parse_references("1 2x 3").unwrap_err();
+    println!("{}", error);
+    assert!(error.contains("2x"));
+
+    println!("\n### Faults by frame count\n");
+    let refs = reads(&[1, 2, 3, 4, 1, 2, 5, 1, 2, 3, 4, 5]);
+    let counts = 1..=6;
+    println!(
+        "{:<14} {}",
+        "frames",
+        counts.clone().map(|n| format!("{:>3}", n)).join("")
+    );
+    for policy in policies() {
+        let faults = counts
+            .clone()
+            .map(|n| simulate(policy.as_ref(), &refs, n).faults())
+            .collect_vec();
+        println!(
+            "{:<14} {}",
+            policy.name(),
+            faults.iter().map(|f| format!("{:>3}", f)).join("")
+        );
+        // never more than one fault per reference, never less than one per distinct page
+        assert!(faults.iter().all(|f| (5..=refs.len()).contains(f)));
+    }
+    // the classic anomaly: FIFO with 4 frames faults more often than with 3
+    assert_eq!(
+        (
+            simulate(&Fifo, &refs, 3).faults(),
+            simulate(&Fifo, &refs, 4).faults()
+        ),
+        (9, 10)
+    );
+}
@@ -0,0 +1,326 @@
+use itertools::Itertools;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use super::{
+    count_faults, frame_table_to_text_diagram, reads, run, simulate, Clock, Fifo, Lru, Nru,
+    Optimal, Page, PageReplacement, SecondChance,
+};
+
+// Belady's anomaly: more frames, but more page faults. it can't happen to a stack algorithm, one
+// where after every reference the pages in memory with n frames are in memory with n + 1 frames
+// as well: then every fault with n + 1 frames is one with n frames too.
+// the searches go through reference strings, exhaustively for short ones over a few pages or
+// randomly with a seed. every policy here only compares page numbers, so renaming the pages
+// changes nothing. the exhaustive ones only look at strings whose pages show up in the order
+// 0, 1, 2, ..., one out of every renaming
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Anomaly {
+    pub refs: Vec<Page>,
+    // faults with `frames` and with `frames + 1`
+    pub frames: usize,
+    pub faults: (usize, usize),
+}
+
+// a reference string on which the pages with `frames` aren't all there with `frames + 1`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InclusionViolation {
+    pub refs: Vec<Page>,
+    pub frames: usize,
+    // after the reference at `step`
+    pub step: usize,
+    pub missing: Vec<Page>,
+}
+
+// calls `f` with every string of `len` references over exactly `pages` pages, up to renaming, in
+// lexicographic order. stops when `f` says so
+fn for_each_string(len: usize, pages: usize, f: &mut impl FnMut(&[Page]) -> bool) {
+    fn extend(
+        refs: &mut Vec<Page>,
+        len: usize,
+        pages: usize,
+        used: usize,
+        f: &mut impl FnMut(&[Page]) -> bool,
+    ) -> bool {
+        if refs.len() == len {
+            return f(refs);
+        }
+        // a page seen before, or the next new one. as long as the rest can still bring in the
+        // pages missing
+        for page in 0..(used + 1).min(pages) {
+            if used.max(page + 1) + (len - refs.len() - 1) < pages {
+                continue;
+            }
+            refs.push(page);
+            let go_on = extend(refs, len, pages, used.max(page + 1), f);
+            refs.pop();
+            if !go_on {
+                return false;
+            }
+        }
+        true
+    }
+    extend(&mut Vec::with_capacity(len), len, pages, 0, f);
+}
+
+// the fewest frames for which one more frame means more faults. with one frame every reference to
+// another page than the last faults, and with as many frames as pages only the first ones do, so
+// neither can be beaten
+pub fn anomaly_in(
+    policy: &dyn PageReplacement,
+    refs: &[Page],
+    max_frames: usize,
+) -> Option<Anomaly> {
+    let references = reads(refs);
+    let distinct = refs.iter().unique().count();
+    let frames = 2..=max_frames.min(distinct.saturating_sub(2));
+    if frames.is_empty() {
+        return None;
+    }
+    let faults = (*frames.start()..=*frames.end() + 1)
+        .map(|n| count_faults(policy, &references, n))
+        .collect_vec();
+    faults
+        .iter()
+        .tuple_windows()
+        .position(|(with_n, with_more)| with_more > with_n)
+        .map(|i| Anomaly {
+            refs: refs.to_vec(),
+            frames: i + 2,
+            faults: (faults[i], faults[i + 1]),
+        })
+}
+
+// the shortest anomaly over up to `pages` pages with up to `max_frames + 1` frames, among the shortest
+// the one with the fewest pages, then the first. and how many strings that took
+pub fn find_minimal_anomaly(
+    policy: &dyn PageReplacement,
+    pages: usize,
+    max_len: usize,
+    max_frames: usize,
+) -> (Option<Anomaly>, usize) {
+    let mut searched = 0;
+    for (len, pages) in (1..=max_len).cartesian_product(1..=pages) {
+        let mut found = None;
+        for_each_string(len, pages, &mut |refs| {
+            searched += 1;
+            found = anomaly_in(policy, refs, max_frames);
+            found.is_none()
+        });
+        if found.is_some() {
+            return (found, searched);
+        }
+    }
+    (None, searched)
+}
+
+// pages renamed in the order they show up
+fn canonical(refs: &[Page]) -> Vec<Page> {
+    let order = refs.iter().unique().copied().collect_vec();
+    refs.iter()
+        .map(|page| order.iter().position(|p| p == page).expect("listed"))
+        .collect_vec()
+}
+
+// drops references as long as it stays an anomaly. no reference can go afterwards, but a shorter
+// anomaly may still exist
+pub fn shrink(policy: &dyn PageReplacement, anomaly: Anomaly, max_frames: usize) -> Anomaly {
+    let mut anomaly = anomaly;
+    'shrinking: loop {
+        for i in 0..anomaly.refs.len() {
+            let mut refs = anomaly.refs.clone();
+            refs.remove(i);
+            if let Some(smaller) = anomaly_in(policy, &refs, max_frames) {
+                anomaly = smaller;
+                continue 'shrinking;
+            }
+        }
+        anomaly.refs = canonical(&anomaly.refs);
+        return anomaly;
+    }
+}
+
+// the first anomaly in `tries` random strings, shrunk. and how many strings that took
+pub fn find_random_anomaly(
+    policy: &dyn PageReplacement,
+    pages: usize,
+    len: usize,
+    max_frames: usize,
+    tries: usize,
+    seed: u64,
+) -> (Option<Anomaly>, usize) {
+    let mut rng = StdRng::seed_from_u64(seed);
+    for tried in 1..=tries {
+        let refs = (0..len).map(|_| rng.gen_range(0..pages)).collect_vec();
+        if let Some(anomaly) = anomaly_in(policy, &refs, max_frames) {
+            return (Some(shrink(policy, anomaly, max_frames)), tried);
+        }
+    }
+    (None, tries)
+}
+
+// bit p for page p, after every reference
+fn resident_sets(policy: &dyn PageReplacement, refs: &[Page], frames: usize) -> Vec<u64> {
+    let mut sets = Vec::with_capacity(refs.len());
+    run(policy, &reads(refs), frames, |_, _, _, memory| {
+        sets.push(
+            memory
+                .frames
+                .iter()
+                .flatten()
+                .fold(0, |set, frame| set | 1 << frame.page),
+        )
+    });
+    sets
+}
+
+// the inclusion property for every string up to `max_len` over `pages` pages and every pair of
+// frame counts up to `max_frames + 1`. Ok with the number of strings, or the shortest violation.
+// within these bounds a proof by exhaustion, beyond them only evidence
+pub fn check_stack_property(
+    policy: &dyn PageReplacement,
+    pages: usize,
+    max_len: usize,
+    max_frames: usize,
+) -> Result<usize, InclusionViolation> {
+    assert!(pages <= 64, "resident sets are bit sets");
+    let mut checked = 0;
+    for (len, pages) in (1..=max_len).cartesian_product(1..=pages) {
+        let mut violation = None;
+        for_each_string(len, pages, &mut |refs| {
+            checked += 1;
+            let sets = (1..=max_frames + 1)
+                .map(|n| resident_sets(policy, refs, n))
+                .collect_vec();
+            // every step, not only the last: OPT looks ahead, so a prefix isn't the same
+            for (n, step) in (0..max_frames).cartesian_product(0..len) {
+                let (smaller, larger) = (sets[n][step], sets[n + 1][step]);
+                if smaller & !larger != 0 {
+                    violation = Some(InclusionViolation {
+                        refs: refs.to_vec(),
+                        frames: n + 1,
+                        step,
+                        missing: (0..pages)
+                            .filter(|p| smaller & !larger & 1 << p != 0)
+                            .collect_vec(),
+                    });
+                    return false;
+                }
+            }
+            true
+        });
+        if let Some(violation) = violation {
+            return Err(violation);
+        }
+    }
+    Ok(checked)
+}
+
+fn refs_to_string(refs: &[Page]) -> String {
+    refs.iter().join(" ")
+}
+
+pub fn test_belady() {
+    println!("\n## BELADY'S ANOMALY\n");
+
+    // `max_frames` is the most that get one more frame, so up to `max_frames + 1` get compared
+    let (pages, max_len, max_frames) = (5, 12, 4);
+    println!(
+        "### Shortest FIFO anomaly, up to {} references over {} pages, 1 to {} frames\n",
+        max_len,
+        pages,
+        max_frames + 1
+    );
+    let (minimal, searched) = find_minimal_anomaly(&Fifo, pages, max_len, max_frames);
+    let minimal = minimal.expect("Belady's own string has 12 references");
+    println!(
+        "{} after {} strings: {} faults with {} frames, {} with {}",
+        refs_to_string(&minimal.refs),
+        searched,
+        minimal.faults.0,
+        minimal.frames,
+        minimal.faults.1,
+        minimal.frames + 1
+    );
+    for frames in [minimal.frames, minimal.frames + 1] {
+        println!("\n{} frames:", frames);
+        frame_table_to_text_diagram(&simulate(&Fifo, &reads(&minimal.refs), frames));
+    }
+    // nothing shorter than Belady's own, and it's the first of that length
+    let belady = [1, 2, 3, 4, 1, 2, 5, 1, 2, 3, 4, 5];
+    assert_eq!(minimal.refs, canonical(&belady));
+    assert_eq!((minimal.frames, minimal.faults), (3, (9, 10)));
+
+    println!("\n### Random search, seeded\n");
+    let (found, tried) = find_random_anomaly(&Fifo, 6, 30, 6, 100_000, 1);
+    let found = found.expect("anomalies aren't that rare");
+    println!(
+        "after {} strings, shrunk: {} ({} faults with {} frames, {} with {})",
+        tried,
+        refs_to_string(&found.refs),
+        found.faults.0,
+        found.frames,
+        found.faults.1,
+        found.frames + 1
+    );
+    assert!(found.refs.len() >= minimal.refs.len());
+    // same seed, same result
+    assert_eq!(
+        find_random_anomaly(&Fifo, 6, 30, 6, 100_000, 1),
+        (Some(found), tried)
+    );
+
+    println!("\n### Which policies are stack algorithms\n");
+    let (pages, max_len, max_frames) = (4, 9, 3);
+    println!(
+        "every string of up to {} references over {} pages, 1 to {} frames\n",
+        max_len,
+        pages,
+        max_frames + 1
+    );
+    let policies: Vec<(Box<dyn PageReplacement>, bool)> = vec![
+        (Box::new(Fifo), false),
+        (Box::new(Lru), true),
+        (Box::new(Optimal), true),
+        (Box::new(SecondChance), false),
+        (Box::new(Clock), false),
+        (Box::new(Nru { reset_interval: 4 }), false),
+    ];
+    for (policy, is_stack) in &policies {
+        let verdict = match check_stack_property(policy.as_ref(), pages, max_len, max_frames) {
+            Ok(checked) => format!("stack, inclusion holds on all {} strings", checked),
+            Err(v) => {
+                let anomaly = find_minimal_anomaly(policy.as_ref(), pages, max_len, max_frames)
+                    .0
+                    .map_or("none found".to_owned(), |a| {
+                        format!(
+                            "{} ({} vs {} faults)",
+                            refs_to_string(&a.refs),
+                            a.faults.0,
+                            a.faults.1
+                        )
+                    });
+                format!(
+                    "not stack, after {} page {:?} is in {} frames, not in {}. anomaly: {}",
+                    refs_to_string(&v.refs[..=v.step]),
+                    v.missing,
+                    v.frames,
+                    v.frames + 1,
+                    anomaly
+                )
+            }
+        };
+        println!("{:<14} {}", policy.name(), verdict);
+        assert_eq!(
+            check_stack_property(policy.as_ref(), pages, max_len, max_frames).is_ok(),
+            *is_stack
+        );
+        // a stack algorithm has no anomaly
+        if *is_stack {
+            assert_eq!(
+                find_minimal_anomaly(policy.as_ref(), pages, max_len, max_frames).0,
+                None
+            );
+        }
+    }
+}
@@ -0,0 +1,617 @@
+use itertools::Itertools;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Up,
+    Down,
+}
+
+impl Direction {
+    fn reversed(self) -> Self {
+        match self {
+            Direction::Up => Direction::Down,
+            Direction::Down => Direction::Up,
+        }
+    }
+}
+
+// every cylinder the head stops at, starting with its initial position.
+// edges the head has to touch (SCAN, C-SCAN) are stops as well
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeadMovement(pub Vec<usize>);
+
+impl HeadMovement {
+    pub fn jumps(&self) -> Vec<usize> {
+        self.0
+            .iter()
+            .tuple_windows()
+            .map(|(c1, c2)| usize::abs_diff(*c1, *c2))
+            .collect_vec()
+    }
+
+    pub fn total_seek_distance(&self) -> usize {
+        self.jumps().iter().sum()
+    }
+}
+
+// what every disk scheduling exercise gives besides the requests
+#[derive(Debug, Clone, Copy)]
+pub struct DiskParameters {
+    pub min_cylinder: usize,
+    pub max_cylinder: usize,
+    // initial position and direction of the head
+    pub head: usize,
+    pub direction: Direction,
+}
+
+impl DiskParameters {
+    fn check(&self, requests: &[usize]) {
+        let cylinders = self.min_cylinder..=self.max_cylinder;
+        assert!(
+            cylinders.contains(&self.head),
+            "head {} is not on the disk {:?}",
+            self.head,
+            cylinders
+        );
+        if let Some(c) = requests.iter().find(|c| !cylinders.contains(c)) {
+            panic!("request {} is not on the disk {:?}", c, cylinders);
+        }
+    }
+
+    fn edge(&self, direction: Direction) -> usize {
+        match direction {
+            Direction::Up => self.max_cylinder,
+            Direction::Down => self.min_cylinder,
+        }
+    }
+}
+
+pub trait DiskScheduler {
+    fn name(&self) -> String;
+    // `requests` in arrival order
+    fn schedule(&self, disk: &DiskParameters, requests: &[usize]) -> HeadMovement;
+    // C-SCAN and C-LOOK jump back instead of reversing, the direction never changes
+    fn keeps_direction(&self) -> bool {
+        false
+    }
+}
+
+pub struct Fcfs;
+
+impl DiskScheduler for Fcfs {
+    fn name(&self) -> String {
+        "FCFS".to_owned()
+    }
+
+    fn schedule(&self, disk: &DiskParameters, requests: &[usize]) -> HeadMovement {
+        disk.check(requests);
+        HeadMovement(
+            std::iter::once(disk.head)
+                .chain(requests.iter().copied())
+                .collect(),
+        )
+    }
+}
+
+// shortest seek (time) first, ties go to the earlier request
+pub struct Sstf;
+
+impl DiskScheduler for Sstf {
+    fn name(&self) -> String {
+        "SSTF".to_owned()
+    }
+
+    fn schedule(&self, disk: &DiskParameters, requests: &[usize]) -> HeadMovement {
+        disk.check(requests);
+        let mut pending = requests.to_vec();
+        let mut positions = vec![disk.head];
+        let mut cur = disk.head;
+        while let Some(next) = pending.iter().position_min_by_key(|c| c.abs_diff(cur)) {
+            cur = pending.remove(next);
+            positions.push(cur);
+        }
+        HeadMovement(positions)
+    }
+}
+
+// goes all the way to the edge before reversing, if there is anything left behind the head
+pub struct Scan;
+
+// SCAN that only ever moves in the initial direction and jumps back to the other edge.
+// the jump back is counted as seek distance, the head moves after all
+pub struct CScan;
+
+// the elevator ("Aufzug"): SCAN that reverses at the last request instead of the edge
+pub struct Look;
+
+// LOOK that only ever moves in the initial direction and jumps back to the farthest pending request
+pub struct CLook;
+
+// the queue is cut into batches of n in arrival order, each batch is done with SCAN before the
+// next one is looked at. so new requests can't starve old ones
+pub struct NStepScan(pub usize);
+
+// requests at or in front of the head in `direction`, sorted in the order the head reaches them
+fn ahead(cur: usize, direction: Direction, pending: &[usize]) -> Vec<usize> {
+    match direction {
+        Direction::Up => pending
+            .iter()
+            .filter(|c| **c >= cur)
+            .copied()
+            .sorted()
+            .collect(),
+        Direction::Down => pending
+            .iter()
+            .filter(|c| **c <= cur)
+            .copied()
+            .sorted_by(|a, b| b.cmp(a))
+            .collect(),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Sweep {
+    // SCAN / LOOK: reverse, at the edge if `to_edge`
+    Reverse { to_edge: bool },
+    // C-SCAN / C-LOOK: jump to the other end, over the edges if `to_edge`
+    Circular { to_edge: bool },
+}
+
+// the common part of all elevator variants, starting at `head` in `direction`.
+// returns the stops and the direction the head ends in
+fn sweep(
+    disk: &DiskParameters,
+    head: usize,
+    mut direction: Direction,
+    requests: &[usize],
+    kind: Sweep,
+) -> (Vec<usize>, Direction) {
+    let mut pending = requests.to_vec();
+    let mut positions = Vec::new();
+    let mut cur = head;
+    while !pending.is_empty() {
+        for c in ahead(cur, direction, &pending) {
+            let served = pending
+                .iter()
+                .position(|p| *p == c)
+                .expect("came from pending");
+            pending.remove(served);
+            positions.push(c);
+            cur = c;
+        }
+        if pending.is_empty() {
+            break;
+        }
+
+        let edge = disk.edge(direction);
+        match kind {
+            Sweep::Reverse { to_edge } => {
+                if to_edge && cur != edge {
+                    positions.push(edge);
+                    cur = edge;
+                }
+                direction = direction.reversed();
+            }
+            Sweep::Circular { to_edge } => {
+                if to_edge {
+                    if cur != edge {
+                        positions.push(edge);
+                    }
+                    cur = disk.edge(direction.reversed());
+                    // a request right at the other edge is served by the next sweep
+                    if !pending.contains(&cur) {
+                        positions.push(cur);
+                    }
+                } else {
+                    // gets served by the next sweep
+                    cur = *match direction {
+                        Direction::Up => pending.iter().min(),
+                        Direction::Down => pending.iter().max(),
+                    }
+                    .expect("pending isn't empty");
+                }
+            }
+        }
+    }
+
+    (positions, direction)
+}
+
+fn sweep_all(disk: &DiskParameters, requests: &[usize], kind: Sweep) -> HeadMovement {
+    disk.check(requests);
+    let (stops, _) = sweep(disk, disk.head, disk.direction, requests, kind);
+    HeadMovement(std::iter::once(disk.head).chain(stops).collect())
+}
+
+impl DiskScheduler for Scan {
+    fn name(&self) -> String {
+        "SCAN".to_owned()
+    }
+
+    fn schedule(&self, disk: &DiskParameters, requests: &[usize]) -> HeadMovement {
+        sweep_all(disk, requests, Sweep::Reverse { to_edge: true })
+    }
+}
+
+impl DiskScheduler for CScan {
+    fn name(&self) -> String {
+        "C-SCAN".to_owned()
+    }
+
+    fn schedule(&self, disk: &DiskParameters, requests: &[usize]) -> HeadMovement {
+        sweep_all(disk, requests, Sweep::Circular { to_edge: true })
+    }
+
+    fn keeps_direction(&self) -> bool {
+        true
+    }
+}
+
+impl DiskScheduler for Look {
+    fn name(&self) -> String {
+        "LOOK".to_owned()
+    }
+
+    fn schedule(&self, disk: &DiskParameters, requests: &[usize]) -> HeadMovement {
+        sweep_all(disk, requests, Sweep::Reverse { to_edge: false })
+    }
+}
+
+impl DiskScheduler for CLook {
+    fn name(&self) -> String {
+        "C-LOOK".to_owned()
+    }
+
+    fn schedule(&self, disk: &DiskParameters, requests: &[usize]) -> HeadMovement {
+        sweep_all(disk, requests, Sweep::Circular { to_edge: false })
+    }
+
+    fn keeps_direction(&self) -> bool {
+        true
+    }
+}
+
+impl DiskScheduler for NStepScan {
+    fn name(&self) -> String {
+        format!("{}-step SCAN", self.0)
+    }
+
+    fn schedule(&self, disk: &DiskParameters, requests: &[usize]) -> HeadMovement {
+        assert!(self.0 > 0, "batches need at least one request");
+        disk.check(requests);
+        let mut positions = vec![disk.head];
+        let mut direction = disk.direction;
+        for batch in requests.chunks(self.0) {
+            let cur = *positions.last().expect("starts with the head");
+            let (stops, new_direction) = sweep(
+                disk,
+                cur,
+                direction,
+                batch,
+                Sweep::Reverse { to_edge: true },
+            );
+            positions.extend(stops);
+            direction = new_direction;
+        }
+        HeadMovement(positions)
+    }
+}
+
+// all times in ms
+#[derive(Debug, Clone, Copy)]
+pub struct TimedRequest {
+    pub arrival: f64,
+    pub cylinder: usize,
+    pub sector: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DiskModel {
+    // every seek of at least one cylinder pays this
+    pub settle_time: f64,
+    // short seeks are dominated by accelerating the arm (~sqrt), long ones by coasting (~linear)
+    pub seek_sqrt: f64,
+    pub seek_linear: f64,
+    pub rpm: f64,
+    pub sectors_per_track: usize,
+    pub sectors_per_request: usize,
+}
+
+impl DiskModel {
+    pub fn seek_time(&self, distance: usize) -> f64 {
+        if distance == 0 {
+            return 0.0;
+        }
+        let distance = distance as f64;
+        self.settle_time + self.seek_sqrt * distance.sqrt() + self.seek_linear * distance
+    }
+
+    pub fn rotation_time(&self) -> f64 {
+        60_000.0 / self.rpm
+    }
+
+    // the platter started at sector 0 at time 0 and never stops
+    pub fn rotational_latency(&self, at: f64, sector: usize) -> f64 {
+        let under_head = (at / self.rotation_time()).fract();
+        let target = sector as f64 / self.sectors_per_track as f64;
+        (target - under_head).rem_euclid(1.0) * self.rotation_time()
+    }
+
+    pub fn transfer_time(&self) -> f64 {
+        self.sectors_per_request as f64 / self.sectors_per_track as f64 * self.rotation_time()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ServedRequest {
+    // index into the requests
+    pub request: usize,
+    pub start: f64,
+    pub seek: f64,
+    pub rotation: f64,
+    pub transfer: f64,
+    pub finish: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct TimedRun {
+    // in the order they were served
+    pub served: Vec<ServedRequest>,
+    // (time, cylinder) whenever the head arrives somewhere, edges included
+    pub head_trace: Vec<(f64, usize)>,
+}
+
+impl TimedRun {
+    pub fn response_times(&self, requests: &[TimedRequest]) -> Vec<f64> {
+        self.served
+            .iter()
+            .map(|s| s.finish - requests[s.request].arrival)
+            .collect_vec()
+    }
+
+    // mean, variance, max
+    pub fn response_stats(&self, requests: &[TimedRequest]) -> (f64, f64, f64) {
+        let times = self.response_times(requests);
+        let n = times.len() as f64;
+        let mean = times.iter().sum::<f64>() / n;
+        let variance = times.iter().map(|t| (t - mean).powi(2)).sum::<f64>() / n;
+        let max = times.iter().copied().fold(0.0, f64::max);
+        (mean, variance, max)
+    }
+}
+
+// online version of any `DiskScheduler`: whenever the disk is free, the scheduler plans over the
+// requests that have arrived so far and the head takes that plan up to its first request.
+// reversing at an edge on the way is paid as one longer seek.
+// N-step SCAN cuts its batches anew each time, so there it is only an approximation
+pub fn simulate_timed(
+    scheduler: &dyn DiskScheduler,
+    disk: &DiskParameters,
+    model: &DiskModel,
+    requests: &[TimedRequest],
+) -> TimedRun {
+    disk.check(&requests.iter().map(|r| r.cylinder).collect_vec());
+    let mut arrivals = (0..requests.len())
+        .sorted_by(|a, b| requests[*a].arrival.total_cmp(&requests[*b].arrival))
+        .collect::<std::collections::VecDeque<_>>();
+
+    let mut now = 0.0;
+    let (mut head, mut direction) = (disk.head, disk.direction);
+    let mut pending: Vec<usize> = Vec::new();
+    let mut run = TimedRun {
+        served: Vec::new(),
+        head_trace: vec![(now, head)],
+    };
+    while !(arrivals.is_empty() && pending.is_empty()) {
+        while arrivals
+            .front()
+            .is_some_and(|r| requests[*r].arrival <= now)
+        {
+            pending.extend(arrivals.pop_front());
+        }
+        if pending.is_empty() {
+            // idle until the next one comes in
+            now = requests[*arrivals.front().expect("not done yet")].arrival;
+            continue;
+        }
+
+        let plan = scheduler.schedule(
+            &DiskParameters {
+                head,
+                direction,
+                ..*disk
+            },
+            &pending.iter().map(|r| requests[*r].cylinder).collect_vec(),
+        );
+        let stops = &plan.0[1..];
+        let target = stops
+            .iter()
+            .position(|c| pending.iter().any(|r| requests[*r].cylinder == *c))
+            .expect("a plan serves every request");
+        let path = &plan.0[..=target + 1];
+        let cylinder = path[target + 1];
+        // duplicates: the oldest one first
+        let request = pending.remove(
+            pending
+                .iter()
+                .position(|r| requests[*r].cylinder == cylinder)
+                .expect("just found it"),
+        );
+
+        // the head sat still while the last request was transferred
+        if run.head_trace.last().map(|(t, _)| *t) != Some(now) {
+            run.head_trace.push((now, head));
+        }
+        let distance = HeadMovement(path.to_vec()).total_seek_distance();
+        let seek = model.seek_time(distance);
+        // for the trace the seek time is spread evenly over the legs of the path
+        let mut at = now;
+        for (from, to) in path.iter().tuple_windows() {
+            at += seek * from.abs_diff(*to) as f64 / distance.max(1) as f64;
+            run.head_trace.push((at, *to));
+        }
+        let rotation = model.rotational_latency(now + seek, requests[request].sector);
+        let transfer = model.transfer_time();
+        run.served.push(ServedRequest {
+            request,
+            start: now,
+            seek,
+            rotation,
+            transfer,
+            finish: now + seek + rotation + transfer,
+        });
+
+        if !scheduler.keeps_direction() {
+            if let Some((from, to)) = path.iter().tuple_windows().filter(|(f, t)| f != t).last() {
+                direction = if to > from {
+                    Direction::Up
+                } else {
+                    Direction::Down
+                };
+            }
+        }
+        head = cylinder;
+        now += seek + rotation + transfer;
+    }
+
+    run
+}
+
+// mostly around the middle of the disk, some far out at the edges. those are the ones SSTF starves
+pub fn clustered_workload(
+    seed: u64,
+    count: usize,
+    mean_interarrival: f64,
+    disk: &DiskParameters,
+    model: &DiskModel,
+) -> Vec<TimedRequest> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let middle = (disk.min_cylinder + disk.max_cylinder) / 2;
+    let spread = (disk.max_cylinder - disk.min_cylinder) / 10;
+    let mut arrival = 0.0;
+    (0..count)
+        .map(|_| {
+            // exponential inter-arrival times
+            arrival += -mean_interarrival * (1.0 - rng.gen::<f64>()).ln();
+            let cylinder = if rng.gen_bool(0.1) {
+                rng.gen_range(disk.min_cylinder..=disk.max_cylinder)
+            } else {
+                rng.gen_range(middle - spread..=middle + spread)
+            };
+            TimedRequest {
+                arrival,
+                cylinder,
+                sector: rng.gen_range(0..model.sectors_per_track),
+            }
+        })
+        .collect_vec()
+}
+
+pub fn test_disk_scheduling() {
+    println!("\n## DISK SCHEDULING\n");
+
+    let schedulers: Vec<Box<dyn DiskScheduler>> = vec![
+        Box::new(Fcfs),
+        Box::new(Sstf),
+        Box::new(Scan),
+        Box::new(CScan),
+        Box::new(Look),
+        Box::new(CLook),
+        Box::new(NStepScan(3)),
+    ];
+    let print_all = |disk: &DiskParameters, requests: &[usize]| {
+        for scheduler in &schedulers {
+            let movement = scheduler.schedule(disk, requests);
+            println!(
+                "{:<12} {:>4} {:?}",
+                scheduler.name(),
+                movement.total_seek_distance(),
+                movement.0
+            );
+        }
+    };
+
+    println!("\n### Probeklausur Aufgabe 4, cylinders 0..=50, head at 11 going up\n");
+    let requests = vec![2, 38, 19, 34, 9, 12, 40, 50];
+    let mut disk = DiskParameters {
+        min_cylinder: 0,
+        max_cylinder: 50,
+        head: 11,
+        direction: Direction::Up,
+    };
+    print_all(&disk, &requests);
+
+    println!("\n### same, going down\n");
+    disk.direction = Direction::Down;
+    print_all(&disk, &requests);
+
+    println!("\n### Silberschatz, cylinders 0..=199, head at 53 going down\n");
+    let requests = vec![98, 183, 37, 122, 14, 124, 65, 67];
+    let disk = DiskParameters {
+        min_cylinder: 0,
+        max_cylinder: 199,
+        head: 53,
+        direction: Direction::Down,
+    };
+    print_all(&disk, &requests);
+    assert_eq!(Fcfs.schedule(&disk, &requests).total_seek_distance(), 640);
+    assert_eq!(Sstf.schedule(&disk, &requests).total_seek_distance(), 236);
+    assert_eq!(Scan.schedule(&disk, &requests).total_seek_distance(), 236);
+}
+
+pub fn test_timed_disk_scheduling() {
+    println!("\n## DISK SCHEDULING WITH ARRIVALS\n");
+
+    let disk = DiskParameters {
+        min_cylinder: 0,
+        max_cylinder: 9999,
+        head: 5000,
+        direction: Direction::Up,
+    };
+    let model = DiskModel {
+        settle_time: 0.5,
+        seek_sqrt: 0.05,
+        seek_linear: 0.0005,
+        rpm: 7200.0,
+        sectors_per_track: 500,
+        sectors_per_request: 8,
+    };
+    println!(
+        "seek 1: {:.2}ms, seek 1000: {:.2}ms, full stroke: {:.2}ms, rotation: {:.2}ms, transfer: {:.3}ms",
+        model.seek_time(1),
+        model.seek_time(1000),
+        model.seek_time(disk.max_cylinder),
+        model.rotation_time(),
+        model.transfer_time()
+    );
+
+    println!("\n### 2000 clustered requests, 7ms apart on average\n");
+    let requests = clustered_workload(42, 2000, 7.0, &disk, &model);
+    let schedulers: Vec<Box<dyn DiskScheduler>> = vec![
+        Box::new(Fcfs),
+        Box::new(Sstf),
+        Box::new(Scan),
+        Box::new(CScan),
+        Box::new(Look),
+        Box::new(CLook),
+        Box::new(NStepScan(8)),
+    ];
+    println!(
+        "{:<12} {:>10} {:>12} {:>10} {:>10} {:>10}",
+        "", "mean [ms]", "variance", "std dev", "max [ms]", "done [s]"
+    );
+    for scheduler in schedulers {
+        let run = simulate_timed(scheduler.as_ref(), &disk, &model, &requests);
+        let (mean, variance, max) = run.response_stats(&requests);
+        println!(
+            "{:<12} {:>10.2} {:>12.2} {:>10.2} {:>10.2} {:>10.2}",
+            scheduler.name(),
+            mean,
+            variance,
+            variance.sqrt(),
+            max,
+            run.served.last().map(|s| s.finish).unwrap_or_default() / 1000.0
+        );
+    }
+}
@@ -0,0 +1,17 @@
+// block devices, how files get their blocks and how the filesystem keeps track of them
+
+pub mod allocation;
+pub mod block_device;
+pub mod buffer_cache;
+pub mod ext2_image;
+pub mod fat_image;
+pub mod filesystem;
+pub mod free_space;
+pub mod fsck;
+pub mod inode;
+pub mod journal;
+pub mod lfs;
+pub mod open_files;
+pub mod permissions;
+pub mod raid;
+pub mod ssd;
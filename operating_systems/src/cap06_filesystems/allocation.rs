@@ -0,0 +1,334 @@
+use std::cmp::Reverse;
+
+use itertools::Itertools;
+
+use super::block_device::{BlockId, FreeSpaceMap};
+
+// what a file of some size got from an allocation strategy
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileAllocation {
+    // in file order
+    pub data: Vec<BlockId>,
+    // index blocks, in the order they are chained
+    pub metadata: Vec<BlockId>,
+    // allocated for data, but not used by the file: the rest of the last block
+    pub internal_fragmentation: usize,
+    // space the bookkeeping needs: next pointers, index blocks, FAT entries
+    pub metadata_bytes: usize,
+}
+
+pub trait AllocationStrategy {
+    fn name(&self) -> String;
+    // marks the blocks as used. None if the file doesn't fit, the map is unchanged then
+    fn allocate(
+        &mut self,
+        free: &mut FreeSpaceMap,
+        block_size: usize,
+        file_size: usize,
+    ) -> Option<FileAllocation>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fit {
+    // first hole that is big enough
+    First,
+    // smallest hole that is big enough
+    Best,
+    // biggest hole
+    Worst,
+}
+
+pub struct Contiguous(pub Fit);
+
+// every block ends with a pointer to the next one, the directory entry points to the first
+pub struct Linked {
+    pub pointer_size: usize,
+}
+
+// one index block with a pointer to every data block. if they don't fit into one, the last
+// pointer of an index block points to the next index block (the "linked scheme")
+pub struct Indexed {
+    pub pointer_size: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FatEntry {
+    Free,
+    Next(BlockId),
+    EndOfChain,
+}
+
+// linked allocation with the pointers pulled out of the blocks into one table for the whole disk.
+// the table itself sits in reserved blocks at the start of the partition, which aren't modelled
+pub struct Fat {
+    pub entry_size: usize,
+    pub table: Vec<FatEntry>,
+}
+
+impl Fat {
+    pub fn new(block_count: usize, entry_size: usize) -> Self {
+        Fat {
+            entry_size,
+            table: vec![FatEntry::Free; block_count],
+        }
+    }
+
+    // what reading the file from its first block walks through
+    pub fn chain(&self, start: BlockId) -> Vec<BlockId> {
+        let mut blocks = vec![start];
+        loop {
+            match self.table[*blocks.last().unwrap()] {
+                FatEntry::Next(next) => blocks.push(next),
+                FatEntry::EndOfChain => return blocks,
+                FatEntry::Free => panic!("chain from {} runs into a free block", start),
+            }
+        }
+    }
+}
+
+fn blocks_needed(size: usize, per_block: usize) -> usize {
+    size.div_ceil(per_block)
+}
+
+impl AllocationStrategy for Contiguous {
+    fn name(&self) -> String {
+        format!("contiguous, {:?} fit", self.0)
+    }
+
+    fn allocate(
+        &mut self,
+        free: &mut FreeSpaceMap,
+        block_size: usize,
+        file_size: usize,
+    ) -> Option<FileAllocation> {
+        let n = blocks_needed(file_size, block_size);
+        let holes = free.free_runs().into_iter().filter(|(_, len)| *len >= n);
+        // ties go to the lower block
+        let (start, _) = match self.0 {
+            Fit::First => holes.min_by_key(|(start, _)| *start),
+            Fit::Best => holes.min_by_key(|(_, len)| *len),
+            Fit::Worst => holes.min_by_key(|(_, len)| Reverse(*len)),
+        }
+        .or((n == 0).then_some((0, 0)))?;
+
+        let data = (start..start + n).collect_vec();
+        data.iter().for_each(|b| free.mark_used(*b));
+        Some(FileAllocation {
+            data,
+            metadata: Vec::new(),
+            internal_fragmentation: n * block_size - file_size,
+            metadata_bytes: 0,
+        })
+    }
+}
+
+impl AllocationStrategy for Linked {
+    fn name(&self) -> String {
+        format!("linked, {}B pointers", self.pointer_size)
+    }
+
+    fn allocate(
+        &mut self,
+        free: &mut FreeSpaceMap,
+        block_size: usize,
+        file_size: usize,
+    ) -> Option<FileAllocation> {
+        assert!(self.pointer_size < block_size, "no room for data");
+        let payload = block_size - self.pointer_size;
+        let n = blocks_needed(file_size, payload);
+
+        let data = free.first_free(n)?;
+        data.iter().for_each(|b| free.mark_used(*b));
+        Some(FileAllocation {
+            data,
+            metadata: Vec::new(),
+            internal_fragmentation: n * payload - file_size,
+            metadata_bytes: n * self.pointer_size,
+        })
+    }
+}
+
+impl AllocationStrategy for Indexed {
+    fn name(&self) -> String {
+        format!("indexed, {}B pointers", self.pointer_size)
+    }
+
+    fn allocate(
+        &mut self,
+        free: &mut FreeSpaceMap,
+        block_size: usize,
+        file_size: usize,
+    ) -> Option<FileAllocation> {
+        let pointers_per_block = block_size / self.pointer_size;
+        assert!(pointers_per_block >= 2, "index blocks can't be chained");
+        let n = blocks_needed(file_size, block_size);
+        // every index block but the last one gives up a pointer for the chain.
+        // even an empty file has its index block
+        let index_blocks = blocks_needed(n.saturating_sub(1), pointers_per_block - 1).max(1);
+
+        let mut blocks = free.first_free(index_blocks + n)?;
+        blocks.iter().for_each(|b| free.mark_used(*b));
+        let data = blocks.split_off(index_blocks);
+        Some(FileAllocation {
+            data,
+            metadata: blocks,
+            internal_fragmentation: n * block_size - file_size,
+            metadata_bytes: index_blocks * block_size,
+        })
+    }
+}
+
+impl AllocationStrategy for Fat {
+    fn name(&self) -> String {
+        format!("FAT, {}B entries", self.entry_size)
+    }
+
+    fn allocate(
+        &mut self,
+        free: &mut FreeSpaceMap,
+        block_size: usize,
+        file_size: usize,
+    ) -> Option<FileAllocation> {
+        assert_eq!(
+            self.table.len(),
+            free.block_count(),
+            "FAT and disk differ in size"
+        );
+        let n = blocks_needed(file_size, block_size);
+
+        let data = free.first_free(n)?;
+        data.iter().for_each(|b| free.mark_used(*b));
+        for (b, next) in data.iter().zip(data.iter().skip(1)) {
+            self.table[*b] = FatEntry::Next(*next);
+        }
+        if let Some(last) = data.last() {
+            self.table[*last] = FatEntry::EndOfChain;
+        }
+        Some(FileAllocation {
+            data,
+            metadata: Vec::new(),
+            internal_fragmentation: n * block_size - file_size,
+            metadata_bytes: n * self.entry_size,
+        })
+    }
+}
+
+// one char per block: '#' was in use before, '.' is free, files are 'A', 'B', ... and their index
+// blocks 'a', 'b', ...
+fn disk_map(before: &FreeSpaceMap, allocations: &[Option<FileAllocation>]) -> String {
+    let mut map = (0..before.block_count())
+        .map(|b| if before.is_free(b) { '.' } else { '#' })
+        .collect_vec();
+    for (file, allocation) in allocations.iter().enumerate() {
+        let Some(allocation) = allocation else {
+            continue;
+        };
+        let letter = (b'A' + file as u8) as char;
+        allocation.data.iter().for_each(|b| map[*b] = letter);
+        allocation
+            .metadata
+            .iter()
+            .for_each(|b| map[*b] = letter.to_ascii_lowercase());
+    }
+    map.into_iter().collect()
+}
+
+fn allocate_all(
+    strategy: &mut dyn AllocationStrategy,
+    disk: &FreeSpaceMap,
+    block_size: usize,
+    file_sizes: &[usize],
+) -> Vec<Option<FileAllocation>> {
+    let mut free = disk.clone();
+    let allocations = file_sizes
+        .iter()
+        .map(|size| strategy.allocate(&mut free, block_size, *size))
+        .collect_vec();
+
+    println!("{}:", strategy.name());
+    println!("  |{}|", disk_map(disk, &allocations));
+    for (file, (size, allocation)) in file_sizes.iter().zip(&allocations).enumerate() {
+        let letter = (b'A' + file as u8) as char;
+        match allocation {
+            Some(a) => println!(
+                "  {} ({}B): blocks {:?}, index blocks {:?}, internal fragmentation {}B, metadata {}B",
+                letter, size, a.data, a.metadata, a.internal_fragmentation, a.metadata_bytes
+            ),
+            None => println!(
+                "  {} ({}B): doesn't fit, {} blocks are free",
+                letter,
+                size,
+                free.free_count()
+            ),
+        }
+    }
+    allocations
+}
+
+pub fn test_allocation() {
+    println!("\n## BLOCK ALLOCATION\n");
+
+    let block_size = 1024;
+    // holes of 3, 6, 2 and 4 blocks
+    let used = [3, 10, 11, 14, 19];
+    let disk = FreeSpaceMap::with_used(20, |b| used.contains(&b));
+    let file_sizes = [1800, 3500, 2500, 5000];
+
+    println!("\n### Contiguous\n");
+    let fits = [Fit::First, Fit::Best, Fit::Worst].map(|fit| {
+        let allocations = allocate_all(&mut Contiguous(fit), &disk, block_size, &file_sizes);
+        allocations.iter().map(Option::is_some).collect_vec()
+    });
+    // enough free blocks for D every time, but only best fit leaves a hole that is big enough
+    assert_eq!(fits[0], [true, true, true, false]);
+    assert_eq!(fits[1], [true, true, true, true]);
+    assert_eq!(fits[2], [true, true, true, false]);
+
+    println!("\n### Non-contiguous\n");
+    let mut strategies: Vec<Box<dyn AllocationStrategy>> = vec![
+        Box::new(Linked { pointer_size: 4 }),
+        Box::new(Indexed { pointer_size: 4 }),
+        Box::new(Fat::new(disk.block_count(), 2)),
+    ];
+    let fits = strategies
+        .iter_mut()
+        .map(|strategy| {
+            let allocations = allocate_all(strategy.as_mut(), &disk, block_size, &file_sizes);
+            allocations.iter().all(Option::is_some)
+        })
+        .collect_vec();
+    // no external fragmentation anymore, but the index blocks take up three of the free blocks
+    assert_eq!(fits, [true, false, true]);
+    // the pointers in every block cost D a sixth block
+    let linked = Linked { pointer_size: 4 }.allocate(&mut FreeSpaceMap::new(8), block_size, 5120);
+    assert_eq!(linked.map(|a| a.data.len()), Some(6));
+
+    println!("\n### Indexed, chained index blocks\n");
+    // 8 pointers per index block, 7 of them for data in all but the last one: 7 + 7 + 2
+    let allocations = allocate_all(
+        &mut Indexed { pointer_size: 128 },
+        &FreeSpaceMap::new(24),
+        block_size,
+        &[16 * 1024, 1],
+    );
+    let big = allocations[0].as_ref().unwrap();
+    assert_eq!(big.metadata, [0, 1, 2]);
+    assert_eq!(big.data.len(), 16);
+
+    println!("\n### Aufgabe 3.2 with a FAT\n");
+    let block_size = 4 * 1024;
+    let disk = FreeSpaceMap::with_used(400, |b| b < 301 || b % 2 == 1);
+    let mut fat = Fat::new(disk.block_count(), 4);
+    let allocation = fat
+        .allocate(&mut disk.clone(), block_size, 33 * 1024)
+        .expect("enough free blocks");
+    println!(
+        "blocks {:?}, chain from the FAT {:?}, internal fragmentation {}KiB",
+        allocation.data,
+        fat.chain(allocation.data[0]),
+        allocation.internal_fragmentation / 1024
+    );
+    assert_eq!(allocation.data, (302..=318).step_by(2).collect_vec());
+    assert_eq!(fat.chain(302), allocation.data);
+    assert_eq!(allocation.internal_fragmentation, 3 * 1024);
+}
@@ -0,0 +1,141 @@
+use itertools::Itertools;
+
+pub type BlockId = usize;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IoStats {
+    pub reads: usize,
+    pub writes: usize,
+}
+
+impl IoStats {
+    pub fn total(&self) -> usize {
+        self.reads + self.writes
+    }
+}
+
+// everything the filesystem layers see of a disk: fixed size blocks, read and written as a whole
+pub trait BlockDevice {
+    fn block_size(&self) -> usize;
+    fn block_count(&self) -> usize;
+    fn read_block(&mut self, id: BlockId) -> Vec<u8>;
+    // `data` is at most one block, the rest of the block is zeroed
+    fn write_block(&mut self, id: BlockId, data: &[u8]);
+    // since the device got created
+    fn stats(&self) -> IoStats;
+}
+
+#[derive(Debug, Clone)]
+pub struct MemoryDisk {
+    block_size: usize,
+    blocks: Vec<Vec<u8>>,
+    stats: IoStats,
+}
+
+impl MemoryDisk {
+    pub fn new(block_size: usize, block_count: usize) -> Self {
+        assert!(block_size > 0, "blocks need a size");
+        MemoryDisk {
+            block_size,
+            blocks: vec![vec![0; block_size]; block_count],
+            stats: IoStats::default(),
+        }
+    }
+}
+
+impl BlockDevice for MemoryDisk {
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn block_count(&self) -> usize {
+        self.blocks.len()
+    }
+
+    fn read_block(&mut self, id: BlockId) -> Vec<u8> {
+        assert!(id < self.blocks.len(), "block {} is not on the disk", id);
+        self.stats.reads += 1;
+        self.blocks[id].clone()
+    }
+
+    fn write_block(&mut self, id: BlockId, data: &[u8]) {
+        assert!(id < self.blocks.len(), "block {} is not on the disk", id);
+        assert!(
+            data.len() <= self.block_size,
+            "{} bytes don't fit into a block of {}",
+            data.len(),
+            self.block_size
+        );
+        self.stats.writes += 1;
+        let block = &mut self.blocks[id];
+        block[..data.len()].copy_from_slice(data);
+        block[data.len()..].fill(0);
+    }
+
+    fn stats(&self) -> IoStats {
+        self.stats
+    }
+}
+
+// one bit per block, true == in use
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FreeSpaceMap(Vec<bool>);
+
+impl FreeSpaceMap {
+    pub fn new(block_count: usize) -> Self {
+        FreeSpaceMap(vec![false; block_count])
+    }
+
+    // e.g. `FreeSpaceMap::with_used(400, |i| i < 301 || i % 2 == 1)` for Aufgabe 3.2
+    pub fn with_used(block_count: usize, used: impl Fn(BlockId) -> bool) -> Self {
+        FreeSpaceMap((0..block_count).map(used).collect())
+    }
+
+    pub fn block_count(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_free(&self, block: BlockId) -> bool {
+        !self.0[block]
+    }
+
+    pub fn free_count(&self) -> usize {
+        self.0.iter().filter(|used| !**used).count()
+    }
+
+    pub fn mark_used(&mut self, block: BlockId) {
+        assert!(!self.0[block], "block {} is already in use", block);
+        self.0[block] = true;
+    }
+
+    pub fn free(&mut self, block: BlockId) {
+        assert!(self.0[block], "block {} is already free", block);
+        self.0[block] = false;
+    }
+
+    // the first `n` free blocks in ascending order, None if there aren't enough
+    pub fn first_free(&self, n: usize) -> Option<Vec<BlockId>> {
+        let blocks = (0..self.0.len())
+            .filter(|b| self.is_free(*b))
+            .take(n)
+            .collect_vec();
+        (blocks.len() == n).then_some(blocks)
+    }
+
+    // maximal runs of free blocks as (first block, length), ascending
+    pub fn free_runs(&self) -> Vec<(BlockId, usize)> {
+        let mut runs = Vec::new();
+        let mut start = None;
+        for (b, used) in self.0.iter().chain([&true]).enumerate() {
+            match (start, used) {
+                (None, false) => start = Some(b),
+                (Some(s), true) => {
+                    runs.push((s, b - s));
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+        runs
+    }
+}
@@ -0,0 +1,533 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use itertools::Itertools;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use super::{
+    block_device::{BlockDevice, BlockId, IoStats, MemoryDisk},
+    filesystem::{Filesystem, ROOT},
+    inode::InodeLayout,
+};
+
+// the buffer cache of a unix kernel: a fixed number of block buffers in front of the disk.
+// a write replaces the whole block, so a write miss doesn't read the block first.
+// with write-through every write goes to the disk right away, with write-back only when a dirty
+// buffer gets evicted or flushed. the periodic flush is what bdflush / the flusher threads do,
+// it limits how much a crash loses
+
+// which buffer to give up when a new block comes in. the policy only sees block numbers, the
+// cache keeps the data
+pub trait ReplacementPolicy {
+    fn name(&self) -> String;
+    // a hit
+    fn touched(&mut self, block: BlockId);
+    // a miss, after `evict` made room if the cache was full
+    fn inserted(&mut self, block: BlockId);
+    // removes the victim from the policy's bookkeeping
+    fn evict(&mut self) -> BlockId;
+}
+
+#[derive(Debug, Default)]
+pub struct Lru {
+    // least recently used first
+    order: VecDeque<BlockId>,
+}
+
+impl ReplacementPolicy for Lru {
+    fn name(&self) -> String {
+        "LRU".to_owned()
+    }
+
+    fn touched(&mut self, block: BlockId) {
+        if let Some(i) = self.order.iter().position(|b| *b == block) {
+            self.order.remove(i);
+        }
+        self.order.push_back(block);
+    }
+
+    fn inserted(&mut self, block: BlockId) {
+        self.order.push_back(block);
+    }
+
+    fn evict(&mut self) -> BlockId {
+        self.order.pop_front().expect("evict from an empty cache")
+    }
+}
+
+// second chance: a hand goes round the buffers, clears referenced bits and takes the first
+// buffer without one
+#[derive(Debug, Default)]
+pub struct Clock {
+    frames: Vec<(BlockId, bool)>,
+    hand: usize,
+    // the frame the last victim was in, the next block goes there
+    free_frame: Option<usize>,
+}
+
+impl ReplacementPolicy for Clock {
+    fn name(&self) -> String {
+        "Clock".to_owned()
+    }
+
+    fn touched(&mut self, block: BlockId) {
+        if let Some(frame) = self.frames.iter_mut().find(|(b, _)| *b == block) {
+            frame.1 = true;
+        }
+    }
+
+    fn inserted(&mut self, block: BlockId) {
+        match self.free_frame.take() {
+            Some(i) => self.frames[i] = (block, true),
+            None => self.frames.push((block, true)),
+        }
+    }
+
+    fn evict(&mut self) -> BlockId {
+        assert!(!self.frames.is_empty(), "evict from an empty cache");
+        loop {
+            let (block, referenced) = &mut self.frames[self.hand];
+            let victim = (!*referenced).then_some(*block);
+            *referenced = false;
+            let frame = self.hand;
+            self.hand = (self.hand + 1) % self.frames.len();
+            if let Some(victim) = victim {
+                self.free_frame = Some(frame);
+                return victim;
+            }
+        }
+    }
+}
+
+// the full 2Q of Johnson and Shasha: new blocks go into a small FIFO (A1in). what gets evicted
+// from there is remembered without data (A1out). only a block that comes back while remembered
+// goes into the main LRU queue (Am), so a scan that touches every block once can't flush the
+// blocks that are used over and over
+#[derive(Debug)]
+pub struct TwoQ {
+    a1in_size: usize,
+    a1out_size: usize,
+    a1in: VecDeque<BlockId>,
+    a1out: VecDeque<BlockId>,
+    am: Lru,
+}
+
+impl TwoQ {
+    // the paper's recommendation: a quarter of the buffers for A1in, ids of half as many for A1out
+    pub fn new(capacity: usize) -> Self {
+        TwoQ {
+            a1in_size: (capacity / 4).max(1),
+            a1out_size: (capacity / 2).max(1),
+            a1in: VecDeque::new(),
+            a1out: VecDeque::new(),
+            am: Lru::default(),
+        }
+    }
+}
+
+impl ReplacementPolicy for TwoQ {
+    fn name(&self) -> String {
+        "2Q".to_owned()
+    }
+
+    fn touched(&mut self, block: BlockId) {
+        // a hit in A1in doesn't count, it's probably the same burst of accesses
+        if !self.a1in.contains(&block) {
+            self.am.touched(block);
+        }
+    }
+
+    fn inserted(&mut self, block: BlockId) {
+        match self.a1out.iter().position(|b| *b == block) {
+            Some(i) => {
+                self.a1out.remove(i);
+                self.am.inserted(block);
+            }
+            None => self.a1in.push_back(block),
+        }
+    }
+
+    fn evict(&mut self) -> BlockId {
+        if self.a1in.len() > self.a1in_size || self.am.order.is_empty() {
+            let victim = self.a1in.pop_front().expect("evict from an empty cache");
+            self.a1out.push_back(victim);
+            if self.a1out.len() > self.a1out_size {
+                self.a1out.pop_front();
+            }
+            victim
+        } else {
+            self.am.evict()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WritePolicy {
+    WriteThrough,
+    // `flush_every`: all dirty buffers go to the disk every that many accesses. None == only on
+    // eviction and `sync`
+    WriteBack { flush_every: Option<usize> },
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub read_hits: usize,
+    pub read_misses: usize,
+    pub write_hits: usize,
+    pub write_misses: usize,
+    // disk writes of dirty buffers, by cause
+    pub evicted_dirty: usize,
+    pub flushed: usize,
+    pub max_dirty: usize,
+}
+
+impl CacheStats {
+    pub fn hit_ratio(&self) -> f64 {
+        let hits = self.read_hits + self.write_hits;
+        hits as f64 / (hits + self.read_misses + self.write_misses).max(1) as f64
+    }
+}
+
+struct Buffer {
+    data: Vec<u8>,
+    dirty: bool,
+}
+
+pub struct BufferCache<D: BlockDevice> {
+    device: D,
+    capacity: usize,
+    policy: Box<dyn ReplacementPolicy>,
+    write_policy: WritePolicy,
+    buffers: HashMap<BlockId, Buffer>,
+    // since the last periodic flush
+    accesses: usize,
+    stats: CacheStats,
+}
+
+impl<D: BlockDevice> BufferCache<D> {
+    pub fn new(
+        device: D,
+        capacity: usize,
+        policy: Box<dyn ReplacementPolicy>,
+        write_policy: WritePolicy,
+    ) -> Self {
+        assert!(capacity > 0, "a cache needs buffers");
+        BufferCache {
+            device,
+            capacity,
+            policy,
+            write_policy,
+            buffers: HashMap::new(),
+            accesses: 0,
+            stats: CacheStats::default(),
+        }
+    }
+
+    pub fn name(&self) -> String {
+        format!(
+            "{}, {}",
+            self.policy.name(),
+            match self.write_policy {
+                WritePolicy::WriteThrough => "write-through".to_owned(),
+                WritePolicy::WriteBack { flush_every: None } => "write-back".to_owned(),
+                WritePolicy::WriteBack {
+                    flush_every: Some(n),
+                } => format!("write-back, flush every {}", n),
+            }
+        )
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    pub fn dirty_count(&self) -> usize {
+        self.buffers.values().filter(|b| b.dirty).count()
+    }
+
+    pub fn device(&self) -> &D {
+        &self.device
+    }
+
+    // every dirty buffer to the disk, they stay cached
+    pub fn sync(&mut self) {
+        let dirty = self
+            .buffers
+            .iter()
+            .filter(|(_, b)| b.dirty)
+            .map(|(id, _)| *id)
+            .sorted()
+            .collect_vec();
+        for id in dirty {
+            let buffer = self.buffers.get_mut(&id).unwrap();
+            buffer.dirty = false;
+            self.device.write_block(id, &buffer.data);
+            self.stats.flushed += 1;
+        }
+    }
+
+    // synced first, like an unmount
+    pub fn into_inner(mut self) -> D {
+        self.sync();
+        self.device
+    }
+
+    // counts the access and flushes when it's time
+    fn tick(&mut self) {
+        self.accesses += 1;
+        if let WritePolicy::WriteBack {
+            flush_every: Some(n),
+        } = self.write_policy
+        {
+            if self.accesses == n {
+                self.accesses = 0;
+                self.sync();
+            }
+        }
+    }
+
+    fn make_room(&mut self) {
+        if self.buffers.len() < self.capacity {
+            return;
+        }
+        let victim = self.policy.evict();
+        let buffer = self
+            .buffers
+            .remove(&victim)
+            .expect("the policy only knows cached blocks");
+        if buffer.dirty {
+            self.device.write_block(victim, &buffer.data);
+            self.stats.evicted_dirty += 1;
+        }
+    }
+}
+
+impl<D: BlockDevice> BlockDevice for BufferCache<D> {
+    fn block_size(&self) -> usize {
+        self.device.block_size()
+    }
+
+    fn block_count(&self) -> usize {
+        self.device.block_count()
+    }
+
+    fn read_block(&mut self, id: BlockId) -> Vec<u8> {
+        self.tick();
+        if let Some(buffer) = self.buffers.get(&id) {
+            self.stats.read_hits += 1;
+            self.policy.touched(id);
+            return buffer.data.clone();
+        }
+        self.stats.read_misses += 1;
+        self.make_room();
+        let data = self.device.read_block(id);
+        self.buffers.insert(
+            id,
+            Buffer {
+                data: data.clone(),
+                dirty: false,
+            },
+        );
+        self.policy.inserted(id);
+        data
+    }
+
+    fn write_block(&mut self, id: BlockId, data: &[u8]) {
+        self.tick();
+        let mut block = data.to_vec();
+        block.resize(self.block_size(), 0);
+        let write_through = self.write_policy == WritePolicy::WriteThrough;
+        if write_through {
+            self.device.write_block(id, &block);
+        }
+        match self.buffers.get_mut(&id) {
+            Some(buffer) => {
+                self.stats.write_hits += 1;
+                buffer.data = block;
+                buffer.dirty |= !write_through;
+                self.policy.touched(id);
+            }
+            None => {
+                self.stats.write_misses += 1;
+                self.make_room();
+                self.buffers.insert(
+                    id,
+                    Buffer {
+                        data: block,
+                        dirty: !write_through,
+                    },
+                );
+                self.policy.inserted(id);
+            }
+        }
+        self.stats.max_dirty = self.stats.max_dirty.max(self.dirty_count());
+    }
+
+    // what actually reached the disk
+    fn stats(&self) -> IoStats {
+        self.device.stats()
+    }
+}
+
+fn policies(capacity: usize) -> Vec<Box<dyn ReplacementPolicy>> {
+    vec![
+        Box::new(Lru::default()),
+        Box::new(Clock::default()),
+        Box::new(TwoQ::new(capacity)),
+    ]
+}
+
+// some directories and small files, everything read back twice, then half of it deleted
+fn filesystem_workload<D: BlockDevice>(fs: &mut Filesystem<D>) {
+    fs.mkdir(ROOT, "/home").unwrap();
+    for user in 0..4 {
+        fs.mkdir(ROOT, &format!("/home/u{}", user)).unwrap();
+        for file in 0..6 {
+            let path = format!("/home/u{}/f{}", user, file);
+            fs.create(ROOT, &path).unwrap();
+            fs.write(ROOT, &path, 0, &vec![user as u8; 1500 + 700 * file])
+                .unwrap();
+        }
+    }
+    for _ in 0..2 {
+        for user in 0..4 {
+            for file in 0..6 {
+                let path = format!("/home/u{}/f{}", user, file);
+                assert_eq!(
+                    fs.read(ROOT, &path, 0, usize::MAX).unwrap().len(),
+                    1500 + 700 * file
+                );
+            }
+        }
+    }
+    for user in 0..4 {
+        for file in (0..6).step_by(2) {
+            fs.unlink(ROOT, &format!("/home/u{}/f{}", user, file))
+                .unwrap();
+        }
+    }
+}
+
+fn print_header() {
+    println!(
+        "{:<34} {:>6} {:>6} {:>9} {:>6} {:>6} {:>6} {:>6}",
+        "cache", "hits", "misses", "hit ratio", "reads", "writes", "dirty", "max"
+    );
+}
+
+// `dirty`: still not on the disk at the end, lost in a crash
+fn print_row(name: &str, stats: &CacheStats, io: IoStats, dirty: usize) {
+    println!(
+        "{:<34} {:>6} {:>6} {:>8.1}% {:>6} {:>6} {:>6} {:>6}",
+        name,
+        stats.read_hits + stats.write_hits,
+        stats.read_misses + stats.write_misses,
+        stats.hit_ratio() * 100.0,
+        io.reads,
+        io.writes,
+        dirty,
+        stats.max_dirty
+    );
+}
+
+pub fn test_buffer_cache() {
+    println!("\n## BUFFER CACHE\n");
+
+    let layout = InodeLayout {
+        block_size: 512,
+        pointer_size: 4,
+        direct: 4,
+        indirect: [1, 1, 0],
+    };
+    let capacity = 32;
+
+    println!("\n### Filesystem operations, {} buffers\n", capacity);
+    print_header();
+    let image = Filesystem::format(MemoryDisk::new(512, 1024), layout, 64).into_device();
+    let mut fs = Filesystem::mount(image.clone());
+    let ((), uncached) = fs.measure(filesystem_workload);
+    print_row("no cache", &CacheStats::default(), uncached, 0);
+    let write_policies = [
+        WritePolicy::WriteThrough,
+        WritePolicy::WriteBack { flush_every: None },
+        WritePolicy::WriteBack {
+            flush_every: Some(200),
+        },
+    ];
+    let mut results = HashMap::new();
+    for write_policy in write_policies {
+        for policy in policies(capacity) {
+            let cache = BufferCache::new(image.clone(), capacity, policy, write_policy);
+            let mut fs = Filesystem::mount(cache);
+            let ((), io) = fs.measure(filesystem_workload);
+            let cache = fs.into_device();
+            print_row(&cache.name(), &cache.cache_stats(), io, cache.dirty_count());
+            results.insert(cache.name(), (io, cache.dirty_count()));
+
+            // after the sync, the disk holds the same filesystem
+            let mut fs = Filesystem::mount(cache.into_inner());
+            assert_eq!(fs.read_dir(ROOT, "/home/u3").unwrap().len(), 2 + 3);
+            assert_eq!(fs.read(ROOT, "/home/u2/f5", 0, 10), Ok(vec![2; 10]));
+        }
+    }
+    let (through, through_dirty) = results["LRU, write-through"];
+    let (back, back_dirty) = results["LRU, write-back"];
+    let (periodic, _) = results["LRU, write-back, flush every 200"];
+    // the same writes reach the disk with write-through, but the reads are cached
+    assert_eq!(through.writes, uncached.writes);
+    assert!(through.reads < uncached.reads / 2);
+    assert_eq!(through_dirty, 0);
+    // write-back saves most writes of bitmaps, inodes and directories, but leaves dirty buffers
+    assert!(back.writes < through.writes / 2);
+    assert!(back_dirty > 0);
+    assert!(back.writes <= periodic.writes && periodic.writes <= through.writes);
+
+    println!(
+        "\n### Hot blocks and a scan, {} buffers, reads only\n",
+        capacity
+    );
+    // 24 hot blocks, read at random, in between a scan over 600 blocks that are read once
+    let mut rng = StdRng::seed_from_u64(43);
+    let mut scan = 100..700;
+    let references = (0..4000)
+        .filter_map(|i| match i % 3 {
+            0 => scan.next(),
+            _ => Some(rng.gen_range(0..24)),
+        })
+        .collect_vec();
+    print_header();
+    let disk = MemoryDisk::new(512, 1024);
+    let hit_ratios = policies(capacity)
+        .into_iter()
+        .map(|policy| {
+            let mut cache = BufferCache::new(
+                disk.clone(),
+                capacity,
+                policy,
+                WritePolicy::WriteBack { flush_every: None },
+            );
+            for block in &references {
+                cache.read_block(*block);
+            }
+            print_row(&cache.name(), &cache.cache_stats(), cache.stats(), 0);
+            cache.cache_stats().hit_ratio()
+        })
+        .collect_vec();
+    let [lru, clock, two_q] = hit_ratios[..] else {
+        unreachable!()
+    };
+    // the scan pushes the hot blocks out of LRU and Clock, 2Q keeps them in Am
+    assert!(two_q > lru && two_q > clock);
+
+    // with room for every block, only the first access to each one misses
+    let distinct = references.iter().collect::<HashSet<_>>().len();
+    let mut cache = BufferCache::new(
+        disk,
+        distinct,
+        Box::new(Lru::default()),
+        WritePolicy::WriteThrough,
+    );
+    for block in &references {
+        cache.read_block(*block);
+    }
+    assert_eq!(cache.cache_stats().read_misses, distinct);
+}
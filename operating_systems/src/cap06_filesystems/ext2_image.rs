@@ -0,0 +1,671 @@
+use std::{collections::HashSet, fs, io, path::Path, process::Command};
+
+use itertools::Itertools;
+
+use super::{
+    block_device::BlockId,
+    filesystem::get_uint,
+    inode::{BlockPath, InodeLayout, InodePointer},
+};
+
+// reads real ext2 images, e.g. from
+//   mke2fs -t ext2 -b 1024 -d some_dir disk.img 4096
+// the on-disk version of `inode::InodeLayout::ext2`. the disk is split into block groups, each
+// with its own block bitmap, inode bitmap and inode table, the group descriptors after the
+// superblock say where. read only, and only what the inode exercises care about: inodes, their
+// block pointers and directories. no extents, so no ext4
+
+const SUPERBLOCK_OFFSET: usize = 1024;
+const MAGIC: usize = 0xef53;
+const GROUP_DESCRIPTOR_SIZE: usize = 32;
+pub const ROOT_INODE: u32 = 2;
+// the only incompatible features that don't change how inodes and directories are read:
+// file types in directory entries and flexible placement of the bitmaps and inode tables
+const SUPPORTED_INCOMPAT: usize = 0x0002 | 0x0200;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ext2Superblock {
+    pub inode_count: usize,
+    pub block_count: usize,
+    pub free_blocks: usize,
+    pub free_inodes: usize,
+    // 1 with 1 KiB blocks, the superblock takes block 1 then. 0 otherwise
+    pub first_data_block: usize,
+    pub block_size: usize,
+    pub blocks_per_group: usize,
+    pub inodes_per_group: usize,
+    pub revision: usize,
+    pub inode_size: usize,
+    pub incompat_features: usize,
+    pub volume_name: String,
+}
+
+impl Ext2Superblock {
+    fn parse(bytes: &[u8]) -> io::Result<Self> {
+        let sb = bytes
+            .get(SUPERBLOCK_OFFSET..SUPERBLOCK_OFFSET + 1024)
+            .ok_or_else(|| invalid("too small for a superblock"))?;
+        if get_uint(sb, 56, 2) != MAGIC {
+            return Err(invalid("no ext2 magic number"));
+        }
+        let revision = get_uint(sb, 76, 4);
+        let log_block_size = get_uint(sb, 24, 4);
+        if log_block_size > 6 {
+            return Err(invalid(&format!("block size 1024 << {}", log_block_size)));
+        }
+        let sb = Ext2Superblock {
+            inode_count: get_uint(sb, 0, 4),
+            block_count: get_uint(sb, 4, 4),
+            free_blocks: get_uint(sb, 12, 4),
+            free_inodes: get_uint(sb, 16, 4),
+            first_data_block: get_uint(sb, 20, 4),
+            block_size: 1024 << log_block_size,
+            blocks_per_group: get_uint(sb, 32, 4),
+            inodes_per_group: get_uint(sb, 40, 4),
+            revision,
+            // revision 0 has fixed 128 byte inodes and no feature flags
+            inode_size: if revision == 0 {
+                128
+            } else {
+                get_uint(sb, 88, 2)
+            },
+            incompat_features: if revision == 0 {
+                0
+            } else {
+                get_uint(sb, 96, 4)
+            },
+            volume_name: if revision == 0 {
+                String::new()
+            } else {
+                String::from_utf8_lossy(&sb[120..136])
+                    .trim_end_matches('\0')
+                    .to_owned()
+            },
+        };
+        if sb.blocks_per_group == 0
+            || sb.inodes_per_group == 0
+            || sb.inode_size < 128
+            || sb.first_data_block >= sb.block_count
+        {
+            return Err(invalid(&format!("nonsense superblock {:?}", sb)));
+        }
+        // the group descriptors, in the block right after the superblock
+        let table_end = sb
+            .group_count()
+            .checked_mul(GROUP_DESCRIPTOR_SIZE)
+            .and_then(|size| sb.descriptor_table().checked_add(size));
+        if !matches!(table_end, Some(end) if end <= bytes.len()) {
+            return Err(invalid(&format!(
+                "{} group descriptors from byte {} on, but only {} bytes",
+                sb.group_count(),
+                sb.descriptor_table(),
+                bytes.len()
+            )));
+        }
+        let unsupported = sb.incompat_features & !SUPPORTED_INCOMPAT;
+        if unsupported != 0 {
+            return Err(invalid(&format!(
+                "unsupported features {:#x} (extents, a journal to recover, ...)",
+                unsupported
+            )));
+        }
+        Ok(sb)
+    }
+
+    pub fn group_count(&self) -> usize {
+        self.block_count
+            .checked_sub(self.first_data_block)
+            .map_or(0, |blocks| blocks.div_ceil(self.blocks_per_group))
+    }
+
+    fn descriptor_table(&self) -> usize {
+        (self.first_data_block + 1) * self.block_size
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GroupDescriptor {
+    pub block_bitmap: BlockId,
+    pub inode_bitmap: BlockId,
+    pub inode_table: BlockId,
+    pub free_blocks: usize,
+    pub free_inodes: usize,
+    pub directories: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ext2FileType {
+    File,
+    Directory,
+    Symlink,
+    // devices, pipes and sockets
+    Other,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ext2Inode {
+    pub mode: usize,
+    pub links: usize,
+    pub size: usize,
+    // in 512 byte units, indirect blocks included
+    pub sectors: usize,
+    // 12 direct, single, double and triple indirect
+    pub pointers: [BlockId; 15],
+}
+
+impl Ext2Inode {
+    pub fn file_type(&self) -> Ext2FileType {
+        match self.mode & 0xf000 {
+            0x8000 => Ext2FileType::File,
+            0x4000 => Ext2FileType::Directory,
+            0xa000 => Ext2FileType::Symlink,
+            _ => Ext2FileType::Other,
+        }
+    }
+
+    // a short symlink keeps its target where the pointers would be
+    fn is_fast_symlink(&self) -> bool {
+        self.file_type() == Ext2FileType::Symlink && self.sectors == 0
+    }
+}
+
+// where one block of a file is, and how it's reached from the inode
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MappedBlock {
+    pub file_block: usize,
+    // what `InodeLayout::locate` says
+    pub path: BlockPath,
+    // the indirect blocks on the way, the outermost first
+    pub indirect: Vec<BlockId>,
+    // None for a hole
+    pub block: Option<BlockId>,
+}
+
+pub struct Ext2Image {
+    pub sb: Ext2Superblock,
+    pub groups: Vec<GroupDescriptor>,
+    bytes: Vec<u8>,
+}
+
+fn invalid(what: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("not an ext2 image: {}", what),
+    )
+}
+
+impl Ext2Image {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::from_bytes(fs::read(path)?)
+    }
+
+    pub fn from_bytes(bytes: Vec<u8>) -> io::Result<Self> {
+        let sb = Ext2Superblock::parse(&bytes)?;
+        if bytes.len() < sb.block_count * sb.block_size {
+            return Err(invalid(&format!(
+                "{} bytes, but the superblock says {} blocks of {}B",
+                bytes.len(),
+                sb.block_count,
+                sb.block_size
+            )));
+        }
+        let mut image = Ext2Image {
+            sb,
+            groups: Vec::new(),
+            bytes,
+        };
+        let table = image.sb.descriptor_table();
+        image.groups = (0..image.sb.group_count())
+            .map(|g| {
+                let at = table + g * GROUP_DESCRIPTOR_SIZE;
+                let d = image
+                    .bytes
+                    .get(at..at + GROUP_DESCRIPTOR_SIZE)
+                    .ok_or_else(|| invalid(&format!("no room for group descriptor {}", g)))?;
+                Ok(GroupDescriptor {
+                    block_bitmap: get_uint(d, 0, 4),
+                    inode_bitmap: get_uint(d, 4, 4),
+                    inode_table: get_uint(d, 8, 4),
+                    free_blocks: get_uint(d, 12, 2),
+                    free_inodes: get_uint(d, 14, 2),
+                    directories: get_uint(d, 16, 2),
+                })
+            })
+            .collect::<io::Result<_>>()?;
+        Ok(image)
+    }
+
+    pub fn layout(&self) -> InodeLayout {
+        InodeLayout::ext2(self.sb.block_size)
+    }
+
+    pub fn block(&self, block: BlockId) -> io::Result<&[u8]> {
+        if block >= self.sb.block_count {
+            return Err(invalid(&format!("there is no block {}", block)));
+        }
+        let bs = self.sb.block_size;
+        Ok(&self.bytes[block * bs..(block + 1) * bs])
+    }
+
+    // inodes count from 1, group by group
+    pub fn inode(&self, inode: u32) -> io::Result<Ext2Inode> {
+        let index = (inode as usize)
+            .checked_sub(1)
+            .filter(|i| *i < self.sb.inode_count)
+            .ok_or_else(|| invalid(&format!("there is no inode {}", inode)))?;
+        // the superblock may count more inodes than its groups hold
+        let group = self
+            .groups
+            .get(index / self.sb.inodes_per_group)
+            .ok_or_else(|| invalid(&format!("inode {} is in no block group", inode)))?;
+        let offset = index % self.sb.inodes_per_group * self.sb.inode_size;
+        let block = group.inode_table + offset / self.sb.block_size;
+        let at = offset % self.sb.block_size;
+        let raw = &self.block(block)?[at..at + 128];
+        let mut pointers = [0; 15];
+        for (i, pointer) in pointers.iter_mut().enumerate() {
+            *pointer = get_uint(raw, 40 + 4 * i, 4);
+        }
+        let mut inode = Ext2Inode {
+            mode: get_uint(raw, 0, 2),
+            links: get_uint(raw, 26, 2),
+            size: get_uint(raw, 4, 4),
+            sectors: get_uint(raw, 28, 4),
+            pointers,
+        };
+        // the upper half is in the field for directory ACLs, for regular files
+        if inode.file_type() == Ext2FileType::File {
+            inode.size |= get_uint(raw, 108, 4) << 32;
+        }
+        Ok(inode)
+    }
+
+    // follows the real pointers down the path `InodeLayout::locate` computes
+    pub fn map_block(&self, inode: &Ext2Inode, file_block: usize) -> io::Result<MappedBlock> {
+        let path = self
+            .layout()
+            .locate(file_block)
+            .ok_or_else(|| invalid(&format!("file block {} beyond the max size", file_block)))?;
+        let slot = match path.pointer {
+            InodePointer::Direct(i) => i,
+            InodePointer::Indirect { level, .. } => 11 + level,
+        };
+        let mut indirect = Vec::new();
+        let mut block = inode.pointers[slot];
+        for index in &path.indices {
+            if block == 0 {
+                break;
+            }
+            indirect.push(block);
+            block = get_uint(self.block(block)?, index * 4, 4);
+        }
+        Ok(MappedBlock {
+            file_block,
+            path,
+            indirect,
+            block: (block != 0).then_some(block),
+        })
+    }
+
+    pub fn block_map(&self, inode: &Ext2Inode) -> io::Result<Vec<MappedBlock>> {
+        if inode.is_fast_symlink() {
+            return Ok(Vec::new());
+        }
+        (0..inode.size.div_ceil(self.sb.block_size))
+            .map(|b| self.map_block(inode, b))
+            .try_collect()
+    }
+
+    // every indirect block of the inode, each once
+    pub fn indirect_blocks(&self, inode: &Ext2Inode) -> io::Result<Vec<BlockId>> {
+        Ok(self
+            .block_map(inode)?
+            .into_iter()
+            .flat_map(|m| m.indirect)
+            .unique()
+            .collect_vec())
+    }
+
+    pub fn read(&self, inode: &Ext2Inode) -> io::Result<Vec<u8>> {
+        if inode.is_fast_symlink() {
+            return Ok(inode
+                .pointers
+                .iter()
+                .flat_map(|p| (*p as u32).to_le_bytes())
+                .take(inode.size)
+                .collect_vec());
+        }
+        let bs = self.sb.block_size;
+        let mut data = Vec::with_capacity(inode.size);
+        for mapped in self.block_map(inode)? {
+            match mapped.block {
+                Some(block) => data.extend_from_slice(self.block(block)?),
+                None => data.resize(data.len() + bs, 0),
+            }
+        }
+        data.truncate(inode.size);
+        Ok(data)
+    }
+
+    // name and inode, "." and ".." included
+    pub fn read_dir(&self, dir: &Ext2Inode) -> io::Result<Vec<(String, u32)>> {
+        if dir.file_type() != Ext2FileType::Directory {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "not a directory",
+            ));
+        }
+        let data = self.read(dir)?;
+        let mut entries = Vec::new();
+        // records never cross a block, the last one of a block takes the rest of it
+        let mut at = 0;
+        while at + 8 <= data.len() {
+            let inode = get_uint(&data, at, 4) as u32;
+            let record_len = get_uint(&data, at + 4, 2);
+            let name_len = data[at + 6] as usize;
+            if record_len < 8 || at + 8 + name_len > data.len() {
+                return Err(invalid(&format!("broken directory entry at byte {}", at)));
+            }
+            // inode 0 is a deleted entry
+            if inode != 0 {
+                let name = String::from_utf8_lossy(&data[at + 8..at + 8 + name_len]);
+                entries.push((name.into_owned(), inode));
+            }
+            at += record_len;
+        }
+        Ok(entries)
+    }
+
+    pub fn lookup(&self, path: &str) -> io::Result<u32> {
+        let mut cur = ROOT_INODE;
+        for name in path.split('/').filter(|c| !c.is_empty()) {
+            cur = self
+                .read_dir(&self.inode(cur)?)?
+                .into_iter()
+                .find(|(entry, _)| entry == name)
+                .ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::NotFound, format!("{} not found", path))
+                })?
+                .1;
+        }
+        Ok(cur)
+    }
+
+    // every file and directory below the root, with its path
+    pub fn walk(&self) -> io::Result<Vec<(String, u32)>> {
+        let mut found = Vec::new();
+        // a broken image could have directory loops
+        let mut visited = HashSet::from([ROOT_INODE]);
+        let mut pending = vec![(String::new(), ROOT_INODE)];
+        while let Some((path, dir)) = pending.pop() {
+            for (name, inode) in self.read_dir(&self.inode(dir)?)? {
+                if name == "." || name == ".." {
+                    continue;
+                }
+                let entry_path = format!("{}/{}", path, name);
+                found.push((entry_path.clone(), inode));
+                if self.inode(inode)?.file_type() == Ext2FileType::Directory {
+                    if !visited.insert(inode) {
+                        return Err(invalid(&format!("{} is a directory loop", entry_path)));
+                    }
+                    pending.push((entry_path, inode));
+                }
+            }
+        }
+        Ok(found)
+    }
+}
+
+fn range(first: usize, last: usize) -> String {
+    match first == last {
+        true => first.to_string(),
+        false => format!("{}-{}", first, last),
+    }
+}
+
+// "entries 0-24", "entry 0"
+fn plural(what: &str, first: usize, last: usize) -> String {
+    match (first == last, what.strip_suffix('y')) {
+        (true, _) => format!("{} {}", what, first),
+        (false, Some(stem)) => format!("{}ies {}", stem, range(first, last)),
+        (false, None) => format!("{}s {}", what, range(first, last)),
+    }
+}
+
+// same pointer kind, same indirect blocks on the way and next to each other on disk
+fn continues(run_end: &MappedBlock, next: &MappedBlock) -> bool {
+    let same_way = match (run_end.path.pointer, next.path.pointer) {
+        (InodePointer::Direct(_), InodePointer::Direct(_)) => true,
+        (a, b) => a == b && run_end.indirect == next.indirect,
+    };
+    let adjacent = match (run_end.block, next.block) {
+        (Some(a), Some(b)) => b == a + 1,
+        (None, None) => true,
+        _ => false,
+    };
+    same_way && adjacent
+}
+
+// one line per run of file blocks that are reached the same way and lie next to each other on
+// disk, the way the inode exercises draw it
+pub fn print_block_map(image: &Ext2Image, inode: &Ext2Inode) -> io::Result<()> {
+    println!(
+        "mode {:o}, {} links, {}B, {} blocks of {}B with the indirect ones",
+        inode.mode,
+        inode.links,
+        inode.size,
+        inode.sectors * 512 / image.sb.block_size,
+        image.sb.block_size
+    );
+    println!("pointers: {:?}", inode.pointers);
+    let map = image.block_map(inode)?;
+    let runs = map
+        .iter()
+        .map(|m| (m, m))
+        .coalesce(|(start, end), (next, _)| match continues(end, next) {
+            true => Ok((start, next)),
+            false => Err(((start, end), (next, next))),
+        });
+    for (start, end) in runs {
+        let mut line = format!(
+            "file blocks {:<9} ->",
+            range(start.file_block, end.file_block)
+        );
+        match (start.path.pointer, end.path.pointer) {
+            (InodePointer::Direct(first), InodePointer::Direct(last)) => {
+                line += &format!(" direct {}", plural("pointer", first, last))
+            }
+            (_, _) => {
+                if let InodePointer::Indirect { level, index } = start.path.pointer {
+                    let kind = ["single", "double", "triple"][level - 1];
+                    line += &format!(" {} indirect pointer {}", kind, index);
+                }
+                let last = start.path.indices.len() - 1;
+                for (depth, block) in start.indirect.iter().enumerate() {
+                    let (first, last) = match depth == last {
+                        true => (start.path.indices[last], end.path.indices[last]),
+                        false => (start.path.indices[depth], start.path.indices[depth]),
+                    };
+                    line += &format!(" -> block {}, {}", block, plural("entry", first, last));
+                }
+            }
+        }
+        line += &match (start.block, end.block) {
+            (Some(first), Some(last)) => format!(" -> {}", plural("block", first, last)),
+            _ => " -> hole".to_owned(),
+        };
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+fn print_overview(image: &Ext2Image) -> io::Result<()> {
+    let sb = &image.sb;
+    println!(
+        "ext2 {:?}, revision {}: {} blocks of {}B, {} inodes of {}B, {} free blocks, {} free inodes",
+        sb.volume_name,
+        sb.revision,
+        sb.block_count,
+        sb.block_size,
+        sb.inode_count,
+        sb.inode_size,
+        sb.free_blocks,
+        sb.free_inodes
+    );
+    for (g, group) in image.groups.iter().enumerate() {
+        println!(
+            "group {}: blocks from {}, block bitmap at {}, inode bitmap at {}, inode table at {}, {} free blocks, {} free inodes, {} directories",
+            g,
+            sb.first_data_block + g * sb.blocks_per_group,
+            group.block_bitmap,
+            group.inode_bitmap,
+            group.inode_table,
+            group.free_blocks,
+            group.free_inodes,
+            group.directories
+        );
+    }
+    for (path, inode) in image.walk()? {
+        let content = image.inode(inode)?;
+        println!(
+            "{:<28} inode {:>4} {:>9}B  {:?}",
+            path,
+            inode,
+            content.size,
+            content.file_type()
+        );
+    }
+    Ok(())
+}
+
+// `ext2 <image>` shows the superblock, the groups and every file, `ext2 <image> <path>` the
+// inode and block map of one file
+pub fn cli(args: &[String]) -> io::Result<()> {
+    let usage = || {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "usage: ext2 <image> [<path in the image>]",
+        )
+    };
+    let image = Ext2Image::open(args.first().ok_or_else(usage)?)?;
+    match &args[1..] {
+        [] => print_overview(&image),
+        [path] => print_block_map(&image, &image.inode(image.lookup(path)?)?),
+        _ => Err(usage()),
+    }
+}
+
+pub fn test_ext2_image() {
+    println!("\n## EXT2 IMAGES\n");
+
+    let dir = std::env::temp_dir().join("cap06_ext2");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("files/docs/empty_dir")).unwrap();
+    fs::write(dir.join("files/hello"), b"hello ext2\n").unwrap();
+    // 12 direct blocks, 256 behind the single indirect block, the rest behind the double one
+    let big = (0..300_000_usize)
+        .map(|i| (i * 7 % 253) as u8)
+        .collect_vec();
+    fs::write(dir.join("files/docs/big"), &big).unwrap();
+    let image_path = dir.join("disk.img");
+    let made = Command::new("mke2fs")
+        .args(["-q", "-F", "-t", "ext2", "-b", "1024", "-L", "cap06", "-d"])
+        .arg(dir.join("files"))
+        .arg(&image_path)
+        .arg("4096")
+        .output();
+    match made {
+        Ok(output) if output.status.success() => {}
+        Ok(output) => {
+            println!(
+                "mke2fs failed, skipped: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+            return;
+        }
+        Err(e) => {
+            println!("no mke2fs ({}), skipped", e);
+            return;
+        }
+    }
+
+    let image = Ext2Image::open(&image_path).unwrap();
+    print_overview(&image).unwrap();
+    assert_eq!(image.sb.volume_name, "cap06");
+    let hello = image.inode(image.lookup("/hello").unwrap()).unwrap();
+    assert_eq!(image.read(&hello).unwrap(), b"hello ext2\n");
+    let root = image.read_dir(&image.inode(ROOT_INODE).unwrap()).unwrap();
+    assert!(root.contains(&(".".to_owned(), ROOT_INODE)));
+    assert!(root.contains(&("..".to_owned(), ROOT_INODE)));
+    // more inodes in the superblock than in the groups
+    let mut bytes = fs::read(&image_path).unwrap();
+    let inode_count = image.sb.inode_count as u32 + image.sb.inodes_per_group as u32;
+    bytes[SUPERBLOCK_OFFSET..SUPERBLOCK_OFFSET + 4].copy_from_slice(&inode_count.to_le_bytes());
+    let error = Ext2Image::from_bytes(bytes)
+        .unwrap()
+        .inode(inode_count)
+        .unwrap_err();
+    println!("{}", error);
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    // a superblock whose data starts past its last block, one whose descriptors would start past
+    // the end of the image, and just a magic number with the same nonsense in 4 KiB
+    let original = fs::read(&image_path).unwrap();
+    let corrupted = |bytes: &[u8], fields: &[(usize, usize)]| {
+        let mut bytes = bytes.to_vec();
+        for (field, value) in fields {
+            let at = SUPERBLOCK_OFFSET + field;
+            bytes[at..at + 4].copy_from_slice(&(*value as u32).to_le_bytes());
+        }
+        bytes
+    };
+    let mut tiny = vec![0; 4096];
+    tiny[SUPERBLOCK_OFFSET + 56..SUPERBLOCK_OFFSET + 58]
+        .copy_from_slice(&(MAGIC as u16).to_le_bytes());
+    for bytes in [
+        corrupted(&original, &[(20, image.sb.block_count + 5)]),
+        corrupted(&original, &[(20, image.sb.block_count - 1)]),
+        corrupted(&tiny, &[(4, 4), (20, 9), (32, 8192), (40, 128)]),
+    ] {
+        let error = Ext2Image::from_bytes(bytes).err().unwrap();
+        println!("{}", error);
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    println!("\n### Block map of /docs/big\n");
+    let inode = image.inode(image.lookup("/docs/big").unwrap()).unwrap();
+    print_block_map(&image, &inode).unwrap();
+    assert_eq!(image.read(&inode).unwrap(), big);
+
+    // the model and the real thing agree
+    let layout = image.layout();
+    let map = image.block_map(&inode).unwrap();
+    assert_eq!(map.len(), 293);
+    for mapped in &map {
+        assert_eq!(mapped.indirect.len(), mapped.path.indices.len());
+        assert!(mapped.block.is_some());
+    }
+    let indirect = image.indirect_blocks(&inode).unwrap();
+    assert_eq!(indirect.len(), layout.metadata_blocks(big.len()));
+    assert_eq!(
+        inode.sectors * 512 / image.sb.block_size,
+        map.len() + indirect.len()
+    );
+    let chain = layout.block_chain(280 * 1024 + 5).unwrap();
+    let mapped = image.map_block(&inode, chain.file_block).unwrap();
+    println!("{}", chain);
+    println!(
+        "    on disk: inode -> blocks {:?} -> block {}",
+        mapped.indirect,
+        mapped.block.unwrap()
+    );
+    assert_eq!(mapped.path, chain.path);
+    assert_eq!(chain.block_reads(), 2 + mapped.indirect.len());
+    let offset = 280 * 1024 + 5;
+    let block = image.block(mapped.block.unwrap()).unwrap();
+    assert_eq!(block[chain.offset_in_block], big[offset]);
+
+    println!(
+        "\nthe image for the cli: cargo run -- ext2 {} /docs/big",
+        image_path.display()
+    );
+}
@@ -0,0 +1,815 @@
+use std::{collections::HashSet, fs, io, path::Path, process::Command};
+
+use itertools::Itertools;
+
+use super::{allocation::FatEntry, filesystem::get_uint};
+
+// reads real FAT12 and FAT16 images, e.g. from
+//   mkfs.fat -C -F 12 disk.img 1440 && mcopy -i disk.img some_file ::
+// the on-disk version of `allocation::Fat`:
+// boot sector + reserved sectors | FATs | root directory | data clusters, numbered from 2 on.
+// clusters are the allocation unit, a few sectors each. every FAT entry holds the next cluster
+// of the file, the directory entry the first one. read only, long file names get skipped and
+// files show up with their 8.3 names
+
+const DIRENT_SIZE: usize = 32;
+const ATTR_VOLUME_LABEL: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+// read only, hidden, system and volume label at once marks a long file name entry
+const ATTR_LONG_NAME: u8 = 0x0f;
+const DELETED: u8 = 0xe5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FatType {
+    Fat12,
+    Fat16,
+}
+
+impl FatType {
+    // the type follows from the number of clusters and nothing else, the label in the boot sector
+    // is only a hint
+    fn from_cluster_count(clusters: usize) -> io::Result<Self> {
+        match clusters {
+            0..=4084 => Ok(FatType::Fat12),
+            4085..=65524 => Ok(FatType::Fat16),
+            _ => Err(invalid(&format!(
+                "{} clusters, that's FAT32, which isn't supported",
+                clusters
+            ))),
+        }
+    }
+
+    fn entry_bits(self) -> usize {
+        match self {
+            FatType::Fat12 => 12,
+            FatType::Fat16 => 16,
+        }
+    }
+
+    // of one FAT with an entry for every cluster, the two reserved ones included
+    fn fat_bytes(self, clusters: usize) -> usize {
+        ((clusters + 2) * self.entry_bits()).div_ceil(8)
+    }
+}
+
+// the BIOS parameter block in the first sector, what mkfs.fat prints with -v
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BootSector {
+    pub oem_name: String,
+    pub bytes_per_sector: usize,
+    pub sectors_per_cluster: usize,
+    pub reserved_sectors: usize,
+    pub fat_count: usize,
+    pub root_entries: usize,
+    pub total_sectors: usize,
+    pub sectors_per_fat: usize,
+    pub volume_label: String,
+}
+
+impl BootSector {
+    fn parse(sector: &[u8]) -> io::Result<Self> {
+        if sector.len() < 512 || sector[510..512] != [0x55, 0xaa] {
+            return Err(invalid("no boot sector signature"));
+        }
+        let total_sectors = match get_uint(sector, 19, 2) {
+            0 => get_uint(sector, 32, 4),
+            small => small,
+        };
+        let boot = BootSector {
+            oem_name: text(&sector[3..11]),
+            bytes_per_sector: get_uint(sector, 11, 2),
+            sectors_per_cluster: sector[13] as usize,
+            reserved_sectors: get_uint(sector, 14, 2),
+            fat_count: sector[16] as usize,
+            root_entries: get_uint(sector, 17, 2),
+            total_sectors,
+            sectors_per_fat: get_uint(sector, 22, 2),
+            // only with the extended boot signature
+            volume_label: if sector[38] == 0x29 {
+                text(&sector[43..54])
+            } else {
+                String::new()
+            },
+        };
+        if !boot.bytes_per_sector.is_power_of_two()
+            || boot.bytes_per_sector < 512
+            || !boot.sectors_per_cluster.is_power_of_two()
+            || boot.fat_count == 0
+        {
+            return Err(invalid(&format!("nonsense boot sector {:?}", boot)));
+        }
+        if boot.sectors_per_fat == 0 {
+            return Err(invalid("no sectors per FAT, that's FAT32"));
+        }
+        Ok(boot)
+    }
+
+    pub fn cluster_size(&self) -> usize {
+        self.bytes_per_sector * self.sectors_per_cluster
+    }
+
+    pub fn first_fat_sector(&self) -> usize {
+        self.reserved_sectors
+    }
+
+    pub fn root_dir_sector(&self) -> usize {
+        self.reserved_sectors + self.fat_count * self.sectors_per_fat
+    }
+
+    pub fn first_data_sector(&self) -> usize {
+        self.root_dir_sector() + (self.root_entries * DIRENT_SIZE).div_ceil(self.bytes_per_sector)
+    }
+
+    pub fn cluster_count(&self) -> usize {
+        self.total_sectors.saturating_sub(self.first_data_sector()) / self.sectors_per_cluster
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    // NAME.EXT, without the padding
+    pub name: String,
+    pub attributes: u8,
+    // 0 for an empty file, and for the root directory in ".."
+    pub first_cluster: usize,
+    pub size: usize,
+}
+
+impl DirEntry {
+    pub fn is_directory(&self) -> bool {
+        self.attributes & ATTR_DIRECTORY != 0
+    }
+}
+
+pub struct FatImage {
+    pub boot: BootSector,
+    pub fat_type: FatType,
+    bytes: Vec<u8>,
+}
+
+fn invalid(what: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("not a FAT12/16 image: {}", what),
+    )
+}
+
+fn text(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).trim_end().to_owned()
+}
+
+impl FatImage {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::from_bytes(fs::read(path)?)
+    }
+
+    pub fn from_bytes(bytes: Vec<u8>) -> io::Result<Self> {
+        let boot = BootSector::parse(&bytes)?;
+        let fat_type = FatType::from_cluster_count(boot.cluster_count())?;
+        if bytes.len() < boot.total_sectors * boot.bytes_per_sector {
+            return Err(invalid(&format!(
+                "{} bytes, but the boot sector says {} sectors of {}B",
+                bytes.len(),
+                boot.total_sectors,
+                boot.bytes_per_sector
+            )));
+        }
+        // every region inside the image, and a FAT big enough for every cluster. the cluster
+        // count alone can't tell, it's 0 if the data area would start past the end
+        if boot.first_data_sector() > boot.total_sectors {
+            return Err(invalid(&format!(
+                "the data area starts at sector {}, past the last one {}",
+                boot.first_data_sector(),
+                boot.total_sectors
+            )));
+        }
+        if boot.sectors_per_fat * boot.bytes_per_sector < fat_type.fat_bytes(boot.cluster_count()) {
+            return Err(invalid(&format!(
+                "{} sectors per FAT, too few for {} clusters",
+                boot.sectors_per_fat,
+                boot.cluster_count()
+            )));
+        }
+        Ok(FatImage {
+            boot,
+            fat_type,
+            bytes,
+        })
+    }
+
+    fn sector(&self, sector: usize, count: usize) -> &[u8] {
+        let bps = self.boot.bytes_per_sector;
+        &self.bytes[sector * bps..(sector + count) * bps]
+    }
+
+    // the raw value in the first FAT. FAT12 packs two entries into three bytes
+    pub fn raw_entry(&self, cluster: usize) -> usize {
+        let fat = self.sector(self.boot.first_fat_sector(), self.boot.sectors_per_fat);
+        match self.fat_type {
+            FatType::Fat12 => {
+                let pair = get_uint(fat, cluster + cluster / 2, 2);
+                if cluster % 2 == 1 {
+                    pair >> 4
+                } else {
+                    pair & 0xfff
+                }
+            }
+            FatType::Fat16 => get_uint(fat, cluster * 2, 2),
+        }
+    }
+
+    // as in the textbook model. bad and reserved clusters are errors
+    pub fn entry(&self, cluster: usize) -> io::Result<FatEntry> {
+        if !(2..self.boot.cluster_count() + 2).contains(&cluster) {
+            return Err(invalid(&format!("there is no cluster {}", cluster)));
+        }
+        let bits = self.fat_type.entry_bits();
+        let raw = self.raw_entry(cluster);
+        // 0xff8..0xfff for FAT12, 0xfff8..0xffff for FAT16
+        let end_of_chain = (1 << bits) - 8;
+        let bad = end_of_chain - 1;
+        match raw {
+            0 => Ok(FatEntry::Free),
+            _ if raw >= end_of_chain => Ok(FatEntry::EndOfChain),
+            _ if raw == bad => Err(invalid(&format!("cluster {} is marked bad", cluster))),
+            _ if raw < 2 || raw >= self.boot.cluster_count() + 2 => Err(invalid(&format!(
+                "cluster {} points to the reserved cluster {:#x}",
+                cluster, raw
+            ))),
+            next => Ok(FatEntry::Next(next)),
+        }
+    }
+
+    // the clusters of a file, in order. empty for first cluster 0
+    pub fn chain(&self, start: usize) -> io::Result<Vec<usize>> {
+        let mut clusters = Vec::new();
+        let mut cur = start;
+        while cur != 0 {
+            // longer than the disk, so it runs in circles
+            if clusters.len() > self.boot.cluster_count() {
+                return Err(invalid(&format!("the chain from {} has a loop", start)));
+            }
+            clusters.push(cur);
+            cur = match self.entry(cur)? {
+                FatEntry::Next(next) => next,
+                FatEntry::EndOfChain => 0,
+                FatEntry::Free => {
+                    return Err(invalid(&format!(
+                        "the chain from {} runs into the free cluster {}",
+                        start, cur
+                    )))
+                }
+            };
+        }
+        Ok(clusters)
+    }
+
+    fn cluster(&self, cluster: usize) -> &[u8] {
+        let boot = &self.boot;
+        self.sector(
+            boot.first_data_sector() + (cluster - 2) * boot.sectors_per_cluster,
+            boot.sectors_per_cluster,
+        )
+    }
+
+    fn parse_entries(bytes: &[u8]) -> Vec<DirEntry> {
+        bytes
+            .chunks(DIRENT_SIZE)
+            // a first byte of 0 ends the directory
+            .take_while(|entry| entry[0] != 0)
+            .filter(|entry| {
+                entry[0] != DELETED
+                    && entry[11] & ATTR_LONG_NAME != ATTR_LONG_NAME
+                    && entry[11] & ATTR_VOLUME_LABEL == 0
+            })
+            .map(|entry| {
+                let mut name = text(&entry[0..8]);
+                // 0x05 stands for a real 0xe5 as the first character
+                if entry[0] == 0x05 {
+                    name.replace_range(0..1, "\u{e5}");
+                }
+                let ext = text(&entry[8..11]);
+                if !ext.is_empty() {
+                    name = format!("{}.{}", name, ext);
+                }
+                DirEntry {
+                    name,
+                    attributes: entry[11],
+                    first_cluster: get_uint(entry, 26, 2),
+                    size: get_uint(entry, 28, 4),
+                }
+            })
+            .collect_vec()
+    }
+
+    // a fixed area before the data clusters, not a file like every other directory
+    pub fn root_dir(&self) -> Vec<DirEntry> {
+        let boot = &self.boot;
+        let sectors = self.boot.first_data_sector() - boot.root_dir_sector();
+        Self::parse_entries(self.sector(boot.root_dir_sector(), sectors))
+    }
+
+    pub fn read_dir(&self, dir: &DirEntry) -> io::Result<Vec<DirEntry>> {
+        if !dir.is_directory() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is not a directory", dir.name),
+            ));
+        }
+        if dir.first_cluster == 0 {
+            return Ok(self.root_dir());
+        }
+        let bytes = self
+            .chain(dir.first_cluster)?
+            .into_iter()
+            .flat_map(|c| self.cluster(c).to_vec())
+            .collect_vec();
+        Ok(Self::parse_entries(&bytes))
+    }
+
+    // the size in the directory entry cuts the last cluster
+    pub fn read_file(&self, file: &DirEntry) -> io::Result<Vec<u8>> {
+        let clusters = self.chain(file.first_cluster)?;
+        if clusters.len() * self.boot.cluster_size() < file.size {
+            return Err(invalid(&format!(
+                "{} has {}B, but only {} clusters",
+                file.name,
+                file.size,
+                clusters.len()
+            )));
+        }
+        let mut data = clusters
+            .into_iter()
+            .flat_map(|c| self.cluster(c).to_vec())
+            .collect_vec();
+        data.truncate(file.size);
+        Ok(data)
+    }
+
+    // "/" separated, 8.3 names compared case insensitively like DOS does. "/" is the root
+    pub fn find(&self, path: &str) -> io::Result<DirEntry> {
+        let mut cur = DirEntry {
+            name: "/".to_owned(),
+            attributes: ATTR_DIRECTORY,
+            first_cluster: 0,
+            size: 0,
+        };
+        for name in path.split('/').filter(|c| !c.is_empty()) {
+            cur = self
+                .read_dir(&cur)?
+                .into_iter()
+                .find(|e| e.name.eq_ignore_ascii_case(name))
+                .ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::NotFound, format!("{} not found", path))
+                })?;
+        }
+        Ok(cur)
+    }
+
+    // every file and directory below `dir`, depth first, with its path
+    pub fn walk(&self, dir: &DirEntry, path: &str) -> io::Result<Vec<(String, DirEntry)>> {
+        let mut found = Vec::new();
+        self.walk_below(
+            dir,
+            path,
+            &mut HashSet::from([dir.first_cluster]),
+            &mut found,
+        )?;
+        Ok(found)
+    }
+
+    // `visited`: first clusters of the directories on the way, a broken image could have loops
+    fn walk_below(
+        &self,
+        dir: &DirEntry,
+        path: &str,
+        visited: &mut HashSet<usize>,
+        found: &mut Vec<(String, DirEntry)>,
+    ) -> io::Result<()> {
+        for entry in self.read_dir(dir)? {
+            if entry.name == "." || entry.name == ".." {
+                continue;
+            }
+            let entry_path = format!("{}/{}", path, entry.name);
+            found.push((entry_path.clone(), entry.clone()));
+            if entry.is_directory() {
+                if !visited.insert(entry.first_cluster) {
+                    return Err(invalid(&format!("{} is a directory loop", entry_path)));
+                }
+                self.walk_below(&entry, &entry_path, visited, found)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// "6, 9, 12-13, 8", consecutive clusters as a range
+fn runs(clusters: &[usize]) -> String {
+    if clusters.is_empty() {
+        return "none".to_owned();
+    }
+    clusters
+        .iter()
+        .map(|c| (*c, *c))
+        .coalesce(|(start, end), (next, _)| {
+            if next == end + 1 {
+                Ok((start, next))
+            } else {
+                Err(((start, end), (next, next)))
+            }
+        })
+        .map(|(start, end)| match start == end {
+            true => start.to_string(),
+            false => format!("{}-{}", start, end),
+        })
+        .join(", ")
+}
+
+fn print_listing(image: &FatImage) -> io::Result<()> {
+    let boot = &image.boot;
+    println!(
+        "{:?} {:?}, oem {:?}: {} sectors of {}B, {} clusters of {} sectors, {} FATs of {} sectors, {} root entries",
+        image.fat_type,
+        boot.volume_label,
+        boot.oem_name,
+        boot.total_sectors,
+        boot.bytes_per_sector,
+        boot.cluster_count(),
+        boot.sectors_per_cluster,
+        boot.fat_count,
+        boot.sectors_per_fat,
+        boot.root_entries
+    );
+    println!(
+        "FAT at sector {}, root directory at {}, cluster 2 at {}",
+        boot.first_fat_sector(),
+        boot.root_dir_sector(),
+        boot.first_data_sector()
+    );
+    let root = image.find("/")?;
+    for (path, entry) in image.walk(&root, "")? {
+        let clusters = image.chain(entry.first_cluster)?;
+        println!(
+            "{:<28} {:>8}B  clusters {}",
+            if entry.is_directory() {
+                format!("{}/", path)
+            } else {
+                path
+            },
+            entry.size,
+            runs(&clusters)
+        );
+    }
+    Ok(())
+}
+
+// `fat <image>` lists everything on it with the cluster chains, `fat <image> <path> [<out>]`
+// extracts a file to `out`, or to stdout
+pub fn cli(args: &[String]) -> io::Result<()> {
+    let usage = || {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "usage: fat <image> [<path in the image> [<output file>]]",
+        )
+    };
+    let image = FatImage::open(args.first().ok_or_else(usage)?)?;
+    match &args[1..] {
+        [] => print_listing(&image),
+        [path] => {
+            let data = image.read_file(&image.find(path)?)?;
+            io::Write::write_all(&mut io::stdout(), &data)
+        }
+        [path, out] => {
+            let data = image.read_file(&image.find(path)?)?;
+            fs::write(out, &data)?;
+            println!("{}: {}B to {}", path, data.len(), out);
+            Ok(())
+        }
+        _ => Err(usage()),
+    }
+}
+
+// what mkfs.fat and mcopy would write, just enough of it for the demo: no long file names,
+// one cluster per sector, clusters handed out in the order given
+struct ImageWriter {
+    image: Vec<u8>,
+    boot: BootSector,
+    fat_type: FatType,
+}
+
+impl ImageWriter {
+    fn format(total_sectors: usize, sectors_per_fat: usize, fat_type: FatType) -> Self {
+        let boot = BootSector {
+            oem_name: "mkfs.fat".to_owned(),
+            bytes_per_sector: 512,
+            sectors_per_cluster: 1,
+            reserved_sectors: 1,
+            fat_count: 2,
+            root_entries: 64,
+            total_sectors,
+            sectors_per_fat,
+            volume_label: "CAP06".to_owned(),
+        };
+        let mut image = vec![0; total_sectors * 512];
+        let s = &mut image[..512];
+        s[0..3].copy_from_slice(&[0xeb, 0x3c, 0x90]);
+        s[3..11].copy_from_slice(b"mkfs.fat");
+        s[11..13].copy_from_slice(&512_u16.to_le_bytes());
+        s[13] = 1;
+        s[14..16].copy_from_slice(&1_u16.to_le_bytes());
+        s[16] = 2;
+        s[17..19].copy_from_slice(&64_u16.to_le_bytes());
+        s[19..21].copy_from_slice(&(total_sectors as u16).to_le_bytes());
+        s[21] = 0xf8;
+        s[22..24].copy_from_slice(&(sectors_per_fat as u16).to_le_bytes());
+        s[38] = 0x29;
+        s[43..54].copy_from_slice(b"CAP06      ");
+        s[54..62].copy_from_slice(match fat_type {
+            FatType::Fat12 => b"FAT12   ",
+            FatType::Fat16 => b"FAT16   ",
+        });
+        s[510..512].copy_from_slice(&[0x55, 0xaa]);
+        let mut writer = ImageWriter {
+            image,
+            boot,
+            fat_type,
+        };
+        // media byte and end of chain in the two reserved entries
+        writer.set_entry(0, 0xfff8);
+        writer.set_entry(1, usize::MAX);
+        writer
+    }
+
+    fn set_entry(&mut self, cluster: usize, value: usize) {
+        for fat in 0..self.boot.fat_count {
+            let start = (self.boot.first_fat_sector() + fat * self.boot.sectors_per_fat) * 512;
+            let bytes = &mut self.image[start..];
+            match self.fat_type {
+                FatType::Fat12 => {
+                    let value = value & 0xfff;
+                    let at = cluster + cluster / 2;
+                    let pair = get_uint(bytes, at, 2);
+                    let pair = if cluster % 2 == 1 {
+                        pair & 0x000f | value << 4
+                    } else {
+                        pair & 0xf000 | value
+                    };
+                    bytes[at..at + 2].copy_from_slice(&(pair as u16).to_le_bytes());
+                }
+                FatType::Fat16 => bytes[cluster * 2..cluster * 2 + 2]
+                    .copy_from_slice(&(value as u16).to_le_bytes()),
+            }
+        }
+    }
+
+    // the chain and the data, returns the first cluster
+    fn write_clusters(&mut self, clusters: &[usize], data: &[u8]) -> usize {
+        for (i, cluster) in clusters.iter().enumerate() {
+            let next = clusters.get(i + 1).copied().unwrap_or(usize::MAX);
+            self.set_entry(*cluster, next);
+            let start = (self.boot.first_data_sector() + cluster - 2) * 512;
+            let chunk = data.get(i * 512..).unwrap_or_default();
+            let chunk = &chunk[..chunk.len().min(512)];
+            self.image[start..start + chunk.len()].copy_from_slice(chunk);
+        }
+        clusters.first().copied().unwrap_or(0)
+    }
+
+    // into slot `slot` of the root directory (dir 0) or of the directory at cluster `dir`
+    fn add_entry(
+        &mut self,
+        dir: usize,
+        slot: usize,
+        name: &str,
+        attributes: u8,
+        first: usize,
+        size: usize,
+    ) {
+        let (base, ext) = match name {
+            "." | ".." => (name, ""),
+            _ => name.split_once('.').unwrap_or((name, "")),
+        };
+        let mut entry = [b' '; DIRENT_SIZE];
+        entry[..base.len()].copy_from_slice(base.as_bytes());
+        entry[8..8 + ext.len()].copy_from_slice(ext.as_bytes());
+        entry[11..].fill(0);
+        entry[11] = attributes;
+        entry[26..28].copy_from_slice(&(first as u16).to_le_bytes());
+        entry[28..32].copy_from_slice(&(size as u32).to_le_bytes());
+        let sector = match dir {
+            0 => self.boot.root_dir_sector(),
+            cluster => self.boot.first_data_sector() + cluster - 2,
+        };
+        let at = sector * 512 + slot * DIRENT_SIZE;
+        self.image[at..at + DIRENT_SIZE].copy_from_slice(&entry);
+    }
+}
+
+pub fn test_fat_image() {
+    println!("\n## FAT12 AND FAT16 IMAGES\n");
+
+    println!("\n### A 1.44MB floppy\n");
+    let mut writer = ImageWriter::format(2880, 9, FatType::Fat12);
+    let text = (0..1500).map(|i| b"fat12"[i % 5]).collect_vec();
+    let big = (0..3000).map(|i| (i % 251) as u8).collect_vec();
+    let readme = writer.write_clusters(&[2, 3, 4], &text);
+    writer.add_entry(0, 0, "README.TXT", 0x20, readme, text.len());
+    // a deleted entry and a long name entry in between, both get skipped
+    writer.add_entry(0, 1, "OLD.TXT", 0x20, 0, 10);
+    writer.image[writer.boot.root_dir_sector() * 512 + DIRENT_SIZE] = DELETED;
+    writer.add_entry(0, 2, "A", ATTR_LONG_NAME, 0, 0);
+    let sub = writer.write_clusters(&[5], &[]);
+    writer.add_entry(0, 3, "SUB", ATTR_DIRECTORY, sub, 0);
+    writer.add_entry(sub, 0, ".", ATTR_DIRECTORY, sub, 0);
+    writer.add_entry(sub, 1, "..", ATTR_DIRECTORY, 0, 0);
+    // fragmented: the clusters of the file are wherever there was room
+    let big_start = writer.write_clusters(&[6, 9, 7, 12, 13, 8], &big);
+    writer.add_entry(sub, 2, "BIG.BIN", 0x20, big_start, big.len());
+    writer.add_entry(sub, 3, "EMPTY", 0x20, 0, 0);
+
+    let image = FatImage::from_bytes(writer.image.clone()).unwrap();
+    print_listing(&image).unwrap();
+    assert_eq!(image.fat_type, FatType::Fat12);
+    assert_eq!(image.boot.cluster_count(), 2880 - 23);
+    assert_eq!(
+        image
+            .root_dir()
+            .iter()
+            .map(|e| e.name.as_str())
+            .collect_vec(),
+        ["README.TXT", "SUB"]
+    );
+    assert_eq!(
+        image
+            .read_file(&image.find("/readme.txt").unwrap())
+            .unwrap(),
+        text
+    );
+    let big_entry = image.find("/SUB/BIG.BIN").unwrap();
+    assert_eq!(
+        image.chain(big_entry.first_cluster).unwrap(),
+        [6, 9, 7, 12, 13, 8]
+    );
+    assert_eq!(image.read_file(&big_entry).unwrap(), big);
+    assert_eq!(
+        image.read_file(&image.find("/SUB/EMPTY").unwrap()).unwrap(),
+        []
+    );
+    // "..", back in the root
+    assert_eq!(
+        image.read_dir(&image.find("/SUB/..").unwrap()).unwrap(),
+        image.root_dir()
+    );
+    // 12 bit entries: 6 -> 9 is in the low 12 bits of bytes 9 and 10, 7 -> 12 in the high 12 bits
+    // of bytes 10 and 11
+    let fat = &writer.image[512..];
+    println!(
+        "FAT bytes 9..12: {:02x?}, entry 6 == {:#05x}, entry 7 == {:#05x}",
+        &fat[9..12],
+        image.raw_entry(6),
+        image.raw_entry(7)
+    );
+    assert_eq!((image.raw_entry(6), image.raw_entry(7)), (9, 12));
+
+    println!("\n### Broken chains\n");
+    let mut broken = ImageWriter {
+        image: writer.image.clone(),
+        boot: writer.boot.clone(),
+        fat_type: FatType::Fat12,
+    };
+    broken.set_entry(13, 9);
+    let error = FatImage::from_bytes(broken.image.clone())
+        .unwrap()
+        .read_file(&big_entry)
+        .unwrap_err();
+    println!("cluster 13 -> 9: {}", error);
+    assert!(error.to_string().contains("loop"));
+    broken.set_entry(13, 0xff7);
+    let error = FatImage::from_bytes(broken.image)
+        .unwrap()
+        .read_file(&big_entry)
+        .unwrap_err();
+    println!("cluster 13 bad: {}", error);
+    assert!(error.to_string().contains("bad"));
+
+    let floppy = writer.image;
+
+    println!("\n### FAT16, 8MiB\n");
+    let mut writer = ImageWriter::format(16384, 64, FatType::Fat16);
+    let data = (0..100_000).map(|i| (i % 241) as u8).collect_vec();
+    // two extents with a gap in between
+    let clusters = (2..100)
+        .chain(300..)
+        .take(data.len().div_ceil(512))
+        .collect_vec();
+    let start = writer.write_clusters(&clusters, &data);
+    writer.add_entry(0, 0, "DATA.BIN", 0x20, start, data.len());
+    let image = FatImage::from_bytes(writer.image).unwrap();
+    print_listing(&image).unwrap();
+    assert_eq!(image.fat_type, FatType::Fat16);
+    assert_eq!(
+        image.read_file(&image.find("DATA.BIN").unwrap()).unwrap(),
+        data
+    );
+
+    // too big for FAT16
+    assert!(FatType::from_cluster_count(70_000).is_err());
+    // through a file, like the cli does it
+    let path = std::env::temp_dir().join("cap06_floppy.img");
+    fs::write(&path, &floppy).unwrap();
+    let image = FatImage::open(&path).unwrap();
+    assert_eq!(
+        image
+            .read_file(&image.find("/SUB/BIG.BIN").unwrap())
+            .unwrap(),
+        big
+    );
+    println!(
+        "\nthe floppy for the cli: cargo run -- fat {}",
+        path.display()
+    );
+
+    println!("\n### Bad geometry\n");
+    for (what, at, value) in [
+        ("1 sector per FAT", 22, 1),
+        ("a root directory past the end", 17, 0xfff0),
+    ] {
+        let mut bytes = floppy.clone();
+        bytes[at..at + 2].copy_from_slice(&(value as u16).to_le_bytes());
+        let error = FatImage::from_bytes(bytes).err().unwrap();
+        println!("{}: {}", what, error);
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    test_mkfs_fat();
+}
+
+// the real thing, if mtools and mkfs.fat are installed. not written by `ImageWriter`, so the
+// reader can't share its mistakes
+fn test_mkfs_fat() {
+    println!("\n### Made by mkfs.fat and mcopy\n");
+
+    let dir = std::env::temp_dir().join("cap06_fat");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let hello = b"hello fat\n".to_vec();
+    let big = (0..20_000_usize)
+        .map(|i| (i * 13 % 251) as u8)
+        .collect_vec();
+    fs::write(dir.join("hello.txt"), &hello).unwrap();
+    fs::write(dir.join("big.bin"), &big).unwrap();
+
+    for (fat_type, kib) in [(FatType::Fat12, "1440"), (FatType::Fat16, "8192")] {
+        let image_path = dir.join(format!("{:?}.img", fat_type));
+        // one sector per cluster, or the 8MiB would have too few clusters for FAT16
+        let bits = fat_type.entry_bits().to_string();
+        let steps: [(&str, Vec<&str>); 4] = [
+            (
+                "mkfs.fat",
+                vec!["-C", "-F", &bits, "-s", "1", "-n", "CAP06"],
+            ),
+            ("mmd", vec!["::SUB"]),
+            ("mcopy", vec!["hello.txt", "::HELLO.TXT"]),
+            ("mcopy", vec!["big.bin", "::SUB/BIG.BIN"]),
+        ];
+        for (tool, args) in steps {
+            let mut command = Command::new(tool);
+            command.current_dir(&dir).env("MTOOLS_SKIP_CHECK", "1");
+            if tool == "mkfs.fat" {
+                command.args(args).arg(&image_path).arg(kib);
+            } else {
+                command.arg("-i").arg(&image_path).args(args);
+            }
+            match command.output() {
+                Ok(output) if output.status.success() => {}
+                Ok(output) => {
+                    println!(
+                        "{} failed, skipped: {}",
+                        tool,
+                        String::from_utf8_lossy(&output.stderr)
+                    );
+                    return;
+                }
+                Err(e) => {
+                    println!("no {} ({}), skipped", tool, e);
+                    return;
+                }
+            }
+        }
+
+        let image = FatImage::open(&image_path).unwrap();
+        print_listing(&image).unwrap();
+        assert_eq!(image.fat_type, fat_type);
+        assert_eq!(image.boot.volume_label, "CAP06");
+        assert_eq!(
+            image.read_file(&image.find("/HELLO.TXT").unwrap()).unwrap(),
+            hello
+        );
+        assert_eq!(
+            image
+                .read_file(&image.find("/SUB/BIG.BIN").unwrap())
+                .unwrap(),
+            big
+        );
+    }
+}
@@ -0,0 +1,1490 @@
+use std::collections::{HashMap, VecDeque};
+
+use itertools::Itertools;
+
+use super::{
+    block_device::{BlockDevice, BlockId, IoStats, MemoryDisk},
+    inode::{InodeLayout, InodePointer},
+    journal::{smallest_write, JournalMode, Transaction},
+    permissions::{
+        check, may_delete, Acl, AclEntry, AclTag, Credentials, Denied, Gid, Uid, EXECUTE, READ,
+        SETGID, SETUID, WRITE,
+    },
+};
+
+// a small unix filesystem, laid out like ext2 without block groups:
+// superblock | journal | inode bitmap | block bitmap | inode table | data blocks
+// block 0 is the superblock, so 0 doubles as "no block" in pointers. inode 0 is never used either,
+// 1 is the root directory.
+// nothing is cached, every inode, bitmap and directory access goes to the device. that's the point:
+// `measure` shows what an operation costs in block reads and writes.
+// every operation runs with some credentials and checks them on the way, see `permissions`.
+// the ones here are root's, `as_user` has the same for anyone else
+
+pub type InodeId = usize;
+
+pub const ROOT: InodeId = 1;
+
+const MAGIC: usize = 0x05_06_f5;
+// kind, acl size, links, mode, size, owner, group and up to `MAX_ACL_ENTRIES` named entries, like
+// the small extended attributes ext4 keeps in the inode
+const INODE_HEADER: usize = 48;
+pub(super) const DIRENT_SIZE: usize = 32;
+pub const MAX_NAME_LEN: usize = DIRENT_SIZE - 5;
+// SYMLOOP_MAX, linux allows 40
+pub const MAX_SYMLINKS: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsError {
+    NotFound,
+    NotADirectory,
+    IsADirectory,
+    AlreadyExists,
+    DirectoryNotEmpty,
+    // no free block or inode left
+    NoSpace,
+    NameTooLong,
+    FileTooLarge,
+    // ".", ".." or an empty name where a new name is needed, or a directory moved below itself
+    InvalidArgument,
+    // EACCES, the mode bits or the acl say no
+    PermissionDenied,
+    // EPERM, only the owner or root may do that
+    NotPermitted,
+    // ELOOP, more than `MAX_SYMLINKS` symlinks on the way
+    TooManySymlinks,
+    // EBADF, no such open file, or not open for that
+    BadDescriptor,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    File,
+    Directory,
+    // the data is the path it points to
+    Symlink,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Inode {
+    pub kind: FileKind,
+    pub uid: Uid,
+    pub gid: Gid,
+    // permission bits with setuid, setgid and sticky. with an acl, the group bits are its mask
+    pub mode: u16,
+    pub acl: Option<Acl>,
+    pub links: usize,
+    pub size: usize,
+    // direct pointers, then single, double and triple indirect ones. 0 == no block
+    pub pointers: Vec<BlockId>,
+}
+
+// what `format` writes to block 0, everything else follows from it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct Superblock {
+    pub(super) layout: InodeLayout,
+    pub(super) block_count: usize,
+    pub(super) inode_count: usize,
+    // no journal at all with None, the journal is empty then
+    pub(super) journal_mode: Option<JournalMode>,
+    pub(super) journal_blocks: usize,
+}
+
+impl Superblock {
+    fn bits_per_block(&self) -> usize {
+        self.layout.block_size * 8
+    }
+
+    pub(super) fn journal_start(&self) -> BlockId {
+        1
+    }
+
+    pub(super) fn inode_bitmap(&self) -> BlockId {
+        self.journal_start() + self.journal_blocks
+    }
+
+    // blocks one transaction can log: the descriptor and the commit block take two, and the
+    // descriptor has a tag for every logged block after its 24 byte header
+    pub(super) fn journal_capacity(&self) -> usize {
+        self.journal_blocks
+            .saturating_sub(2)
+            .min((self.layout.block_size - 24) / 8)
+    }
+
+    pub(super) fn block_bitmap(&self) -> BlockId {
+        self.inode_bitmap() + self.inode_count.div_ceil(self.bits_per_block())
+    }
+
+    pub(super) fn inode_table(&self) -> BlockId {
+        self.block_bitmap() + self.block_count.div_ceil(self.bits_per_block())
+    }
+
+    pub(super) fn data_start(&self) -> BlockId {
+        self.inode_table() + self.inode_count.div_ceil(self.inodes_per_block())
+    }
+
+    fn pointer_count(&self) -> usize {
+        self.layout.direct + self.layout.indirect.iter().sum::<usize>()
+    }
+
+    fn inode_size(&self) -> usize {
+        INODE_HEADER + self.pointer_count() * self.layout.pointer_size
+    }
+
+    fn inodes_per_block(&self) -> usize {
+        self.layout.block_size / self.inode_size()
+    }
+
+    // index into `Inode::pointers`
+    fn slot(&self, pointer: InodePointer) -> usize {
+        match pointer {
+            InodePointer::Direct(i) => i,
+            InodePointer::Indirect { level, index } => {
+                self.layout.direct + self.layout.indirect[..level - 1].iter().sum::<usize>() + index
+            }
+        }
+    }
+
+    // 0 for direct pointers
+    pub(super) fn slot_level(&self, slot: usize) -> usize {
+        let mut end = self.layout.direct;
+        for level in 0..=3 {
+            if slot < end {
+                return level;
+            }
+            end += self.layout.indirect.get(level).copied().unwrap_or(0);
+        }
+        panic!("inode has no pointer {}", slot)
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let l = &self.layout;
+        let fields = [
+            MAGIC,
+            l.block_size,
+            l.pointer_size,
+            l.direct,
+            l.indirect[0],
+            l.indirect[1],
+            l.indirect[2],
+            self.block_count,
+            self.inode_count,
+            JournalMode::encode(self.journal_mode),
+            self.journal_blocks,
+        ];
+        let mut block = vec![0; fields.len() * 8];
+        for (i, field) in fields.into_iter().enumerate() {
+            put_uint(&mut block, i * 8, 8, field);
+        }
+        block
+    }
+
+    fn decode(block: &[u8]) -> Option<Self> {
+        let field = |i: usize| get_uint(block, i * 8, 8);
+        (field(0) == MAGIC).then(|| Superblock {
+            layout: InodeLayout {
+                block_size: field(1),
+                pointer_size: field(2),
+                direct: field(3),
+                indirect: [field(4), field(5), field(6)],
+            },
+            block_count: field(7),
+            inode_count: field(8),
+            journal_mode: JournalMode::decode(field(9)),
+            journal_blocks: field(10),
+        })
+    }
+}
+
+// little endian, `size` bytes
+pub(super) fn get_uint(bytes: &[u8], at: usize, size: usize) -> usize {
+    bytes[at..at + size]
+        .iter()
+        .rev()
+        .fold(0, |n, b| (n << 8) | *b as usize)
+}
+
+pub(super) fn put_uint(bytes: &mut [u8], at: usize, size: usize, value: usize) {
+    for (i, b) in bytes[at..at + size].iter_mut().enumerate() {
+        *b = (value >> (8 * i)) as u8;
+    }
+}
+
+impl Inode {
+    // root's, with the usual umask
+    fn new(kind: FileKind, pointer_count: usize) -> Self {
+        Inode {
+            kind,
+            uid: 0,
+            gid: 0,
+            mode: match kind {
+                FileKind::File => 0o644,
+                FileKind::Directory => 0o755,
+                // never checked, a symlink is only a path
+                FileKind::Symlink => 0o777,
+            },
+            acl: None,
+            links: 1,
+            size: 0,
+            pointers: vec![0; pointer_count],
+        }
+    }
+
+    fn encode(&self, sb: &Superblock) -> Vec<u8> {
+        let mut bytes = vec![0; sb.inode_size()];
+        bytes[0] = match self.kind {
+            FileKind::File => 1,
+            FileKind::Directory => 2,
+            FileKind::Symlink => 3,
+        };
+        put_uint(&mut bytes, 2, 2, self.links);
+        put_uint(&mut bytes, 4, 2, self.mode as usize);
+        put_uint(&mut bytes, 8, 8, self.size);
+        put_uint(&mut bytes, 16, 4, self.uid);
+        put_uint(&mut bytes, 20, 4, self.gid);
+        if let Some(acl) = &self.acl {
+            bytes[1] = acl.entries.len() as u8 + 1;
+            bytes[6] = acl.group_obj;
+            for (i, entry) in acl.entries.iter().enumerate() {
+                let at = 24 + 6 * i;
+                let (tag, id) = match entry.tag {
+                    AclTag::User(uid) => (1, uid),
+                    AclTag::Group(gid) => (2, gid),
+                };
+                bytes[at] = tag;
+                bytes[at + 1] = entry.perms;
+                put_uint(&mut bytes, at + 2, 4, id);
+            }
+        }
+        for (i, pointer) in self.pointers.iter().enumerate() {
+            let ps = sb.layout.pointer_size;
+            put_uint(&mut bytes, INODE_HEADER + i * ps, ps, *pointer);
+        }
+        bytes
+    }
+
+    // None for a free inode
+    fn decode(bytes: &[u8], sb: &Superblock) -> Option<Self> {
+        let kind = match bytes[0] {
+            1 => FileKind::File,
+            2 => FileKind::Directory,
+            3 => FileKind::Symlink,
+            _ => return None,
+        };
+        let ps = sb.layout.pointer_size;
+        // 0 for no acl at all, else the named entries + 1
+        let acl = (bytes[1] != 0).then(|| Acl {
+            group_obj: bytes[6],
+            entries: (0..bytes[1] as usize - 1)
+                .map(|i| {
+                    let at = 24 + 6 * i;
+                    let id = get_uint(bytes, at + 2, 4);
+                    AclEntry {
+                        tag: if bytes[at] == 1 {
+                            AclTag::User(id)
+                        } else {
+                            AclTag::Group(id)
+                        },
+                        perms: bytes[at + 1],
+                    }
+                })
+                .collect(),
+        });
+        Some(Inode {
+            kind,
+            uid: get_uint(bytes, 16, 4),
+            gid: get_uint(bytes, 20, 4),
+            mode: get_uint(bytes, 4, 2) as u16,
+            acl,
+            links: get_uint(bytes, 2, 2),
+            size: get_uint(bytes, 8, 8),
+            pointers: (0..sb.pointer_count())
+                .map(|i| get_uint(bytes, INODE_HEADER + i * ps, ps))
+                .collect(),
+        })
+    }
+}
+
+// inode number 0 marks a free slot
+fn encode_dirent(name: &str, inode: InodeId) -> Vec<u8> {
+    let mut bytes = vec![0; DIRENT_SIZE];
+    put_uint(&mut bytes, 0, 4, inode);
+    bytes[4] = name.len() as u8;
+    bytes[5..5 + name.len()].copy_from_slice(name.as_bytes());
+    bytes
+}
+
+pub(super) fn decode_dirent(bytes: &[u8]) -> Option<(String, InodeId)> {
+    let inode = get_uint(bytes, 0, 4);
+    let len = (bytes[4] as usize).min(MAX_NAME_LEN);
+    (inode != 0).then(|| {
+        (
+            String::from_utf8_lossy(&bytes[5..5 + len]).into_owned(),
+            inode,
+        )
+    })
+}
+
+pub struct Filesystem<D: BlockDevice> {
+    device: D,
+    pub(super) sb: Superblock,
+    // the running operation, only with a journal
+    pub(super) txn: Option<Transaction>,
+    // of the next transaction
+    pub(super) sequence: usize,
+    // blocks the journal replay at mount time wrote back
+    pub(super) replayed: usize,
+    // file descriptors per inode over all processes, like the in-memory inode table
+    pub(super) open_counts: HashMap<InodeId, usize>,
+}
+
+impl<D: BlockDevice> Filesystem<D> {
+    pub fn format(device: D, layout: InodeLayout, inode_count: usize) -> Self {
+        Self::format_with_journal(device, layout, inode_count, None)
+    }
+
+    // `journal`: the mode and how many blocks to reserve for it
+    pub fn format_with_journal(
+        device: D,
+        layout: InodeLayout,
+        inode_count: usize,
+        journal: Option<(JournalMode, usize)>,
+    ) -> Self {
+        assert_eq!(
+            layout.block_size,
+            device.block_size(),
+            "inode layout and device disagree on the block size"
+        );
+        let sb = Superblock {
+            layout,
+            block_count: device.block_count(),
+            inode_count,
+            journal_mode: journal.map(|(mode, _)| mode),
+            journal_blocks: journal.map_or(0, |(_, blocks)| blocks),
+        };
+        assert!(
+            sb.inodes_per_block() > 0,
+            "inodes don't fit into a block: {:?}",
+            layout
+        );
+        assert!(sb.data_start() < sb.block_count, "no room for data");
+        assert!(
+            journal.is_none() || sb.journal_capacity() >= smallest_write(&layout),
+            "a journal of {} blocks is too small for a one byte write",
+            sb.journal_blocks
+        );
+
+        let mut fs = Filesystem {
+            device,
+            sb,
+            txn: None,
+            sequence: 1,
+            replayed: 0,
+            open_counts: HashMap::new(),
+        };
+        fs.write_block(0, &sb.encode());
+        for block in 1..sb.data_start() {
+            fs.write_block(block, &[]);
+        }
+        for block in 0..sb.data_start() {
+            fs.set_bit(sb.block_bitmap(), block, true);
+        }
+        fs.set_bit(sb.inode_bitmap(), 0, true);
+
+        let root = fs.allocate_inode().expect("fresh filesystem");
+        assert_eq!(root, ROOT);
+        let root = Inode::new(FileKind::Directory, sb.pointer_count());
+        fs.init_directory(ROOT, ROOT, root)
+            .expect("fresh filesystem has room for the root directory");
+        fs
+    }
+
+    // replays the journal if the last transaction got committed, but not checkpointed
+    pub fn mount(mut device: D) -> Self {
+        let sb = Superblock::decode(&device.read_block(0)).expect("not formatted");
+        let mut fs = Filesystem {
+            device,
+            sb,
+            txn: None,
+            sequence: 1,
+            replayed: 0,
+            open_counts: HashMap::new(),
+        };
+        fs.recover();
+        fs
+    }
+
+    pub fn device(&self) -> &D {
+        &self.device
+    }
+
+    pub fn into_device(self) -> D {
+        self.device
+    }
+
+    pub fn layout(&self) -> InodeLayout {
+        self.sb.layout
+    }
+
+    // what `op` cost the device
+    pub fn measure<T>(&mut self, op: impl FnOnce(&mut Self) -> T) -> (T, IoStats) {
+        let before = self.device.stats();
+        let result = op(self);
+        let after = self.device.stats();
+        (
+            result,
+            IoStats {
+                reads: after.reads - before.reads,
+                writes: after.writes - before.writes,
+            },
+        )
+    }
+
+    pub fn replayed_blocks(&self) -> usize {
+        self.replayed
+    }
+
+    // every device access of the filesystem goes through these. during a transaction the writes
+    // only go to the device at the commit
+    pub(super) fn read_block(&mut self, block: BlockId) -> Vec<u8> {
+        match self.txn.as_ref().and_then(|txn| txn.get(block)) {
+            Some(data) => data.to_vec(),
+            None => self.device.read_block(block),
+        }
+    }
+
+    pub(super) fn write_block(&mut self, block: BlockId, data: &[u8]) {
+        self.write_to(block, data, false)
+    }
+
+    // contents of regular files, only journaled in `JournalMode::Data`
+    fn write_file_block(&mut self, block: BlockId, data: &[u8]) {
+        self.write_to(block, data, true)
+    }
+
+    fn write_to(&mut self, block: BlockId, data: &[u8], is_file_data: bool) {
+        let block_size = self.sb.layout.block_size;
+        match self.txn.as_mut() {
+            Some(txn) => txn.put(block, data, block_size, is_file_data),
+            None => self.device.write_block(block, data),
+        }
+    }
+
+    pub(super) fn device_mut(&mut self) -> &mut D {
+        &mut self.device
+    }
+
+    // bitmaps
+
+    pub(super) fn set_bit(&mut self, start: BlockId, index: usize, value: bool) {
+        let bits = self.sb.bits_per_block();
+        let mut block = self.read_block(start + index / bits);
+        let byte = &mut block[index % bits / 8];
+        if value {
+            *byte |= 1 << (index % 8);
+        } else {
+            *byte &= !(1 << (index % 8));
+        }
+        self.write_block(start + index / bits, &block);
+    }
+
+    // sets and returns the first clear bit below `count`
+    fn allocate_bit(&mut self, start: BlockId, count: usize) -> Option<usize> {
+        let bits = self.sb.bits_per_block();
+        for b in 0..count.div_ceil(bits) {
+            let mut block = self.read_block(start + b);
+            let free = (0..bits.min(count - b * bits)).find(|i| block[i / 8] & (1 << (i % 8)) == 0);
+            if let Some(i) = free {
+                block[i / 8] |= 1 << (i % 8);
+                self.write_block(start + b, &block);
+                return Some(b * bits + i);
+            }
+        }
+        None
+    }
+
+    fn count_clear_bits(&mut self, start: BlockId, count: usize) -> usize {
+        let bits = self.sb.bits_per_block();
+        (0..count.div_ceil(bits))
+            .map(|b| {
+                let block = self.read_block(start + b);
+                (0..bits.min(count - b * bits))
+                    .filter(|i| block[i / 8] & (1 << (i % 8)) == 0)
+                    .count()
+            })
+            .sum()
+    }
+
+    pub(super) fn allocate_block(&mut self) -> Result<BlockId, FsError> {
+        let sb = self.sb;
+        self.allocate_bit(sb.block_bitmap(), sb.block_count)
+            .ok_or(FsError::NoSpace)
+    }
+
+    fn allocate_inode(&mut self) -> Result<InodeId, FsError> {
+        let sb = self.sb;
+        self.allocate_bit(sb.inode_bitmap(), sb.inode_count)
+            .ok_or(FsError::NoSpace)
+    }
+
+    pub fn free_block_count(&mut self) -> usize {
+        let sb = self.sb;
+        self.count_clear_bits(sb.block_bitmap(), sb.block_count)
+    }
+
+    pub fn free_inode_count(&mut self) -> usize {
+        let sb = self.sb;
+        self.count_clear_bits(sb.inode_bitmap(), sb.inode_count)
+    }
+
+    // inodes
+
+    fn inode_location(&self, inode: InodeId) -> (BlockId, usize) {
+        assert!(inode < self.sb.inode_count, "there is no inode {}", inode);
+        (
+            self.sb.inode_table() + inode / self.sb.inodes_per_block(),
+            inode % self.sb.inodes_per_block() * self.sb.inode_size(),
+        )
+    }
+
+    pub(super) fn read_inode(&mut self, inode: InodeId) -> Option<Inode> {
+        let (block, at) = self.inode_location(inode);
+        let sb = self.sb;
+        Inode::decode(&self.read_block(block)[at..at + sb.inode_size()], &sb)
+    }
+
+    // an inode some directory entry points to
+    pub(super) fn inode(&mut self, inode: InodeId) -> Inode {
+        self.read_inode(inode)
+            .unwrap_or_else(|| panic!("inode {} is referenced, but free", inode))
+    }
+
+    // None clears it
+    pub(super) fn write_inode(&mut self, inode: InodeId, content: Option<&Inode>) {
+        let (block, at) = self.inode_location(inode);
+        let sb = self.sb;
+        let mut bytes = self.read_block(block);
+        let encoded = match content {
+            Some(content) => content.encode(&sb),
+            None => vec![0; sb.inode_size()],
+        };
+        bytes[at..at + sb.inode_size()].copy_from_slice(&encoded);
+        self.write_block(block, &bytes);
+    }
+
+    fn adjust_links(&mut self, inode: InodeId, by: isize) {
+        let mut content = self.inode(inode);
+        content.links = content
+            .links
+            .checked_add_signed(by)
+            .expect("negative link count");
+        self.write_inode(inode, Some(&content));
+    }
+
+    // file contents
+
+    // disk block of the n-th block of a file, None for a hole. with `allocate`, holes and missing
+    // indirect blocks get filled on the way. the bool says whether the block is fresh, i.e. all
+    // zeros but not written yet
+    fn map_block(
+        &mut self,
+        inode: &mut Inode,
+        file_block: usize,
+        allocate: bool,
+    ) -> Result<Option<(BlockId, bool)>, FsError> {
+        let path = self
+            .sb
+            .layout
+            .locate(file_block)
+            .ok_or(FsError::FileTooLarge)?;
+        let slot = self.sb.slot(path.pointer);
+        let ps = self.sb.layout.pointer_size;
+
+        let mut block = inode.pointers[slot];
+        let mut fresh = block == 0;
+        if fresh {
+            if !allocate {
+                return Ok(None);
+            }
+            block = self.allocate_block()?;
+            inode.pointers[slot] = block;
+        }
+        for index in path.indices {
+            let mut pointers = if fresh {
+                vec![0; self.sb.layout.block_size]
+            } else {
+                self.read_block(block)
+            };
+            let mut next = get_uint(&pointers, index * ps, ps);
+            let next_fresh = next == 0;
+            if next_fresh {
+                if !allocate {
+                    return Ok(None);
+                }
+                next = self.allocate_block()?;
+                put_uint(&mut pointers, index * ps, ps, next);
+            }
+            if fresh || next_fresh {
+                self.write_block(block, &pointers);
+            }
+            (block, fresh) = (next, next_fresh);
+        }
+        Ok(Some((block, fresh)))
+    }
+
+    pub(super) fn read_data(&mut self, inode: &mut Inode, offset: usize, len: usize) -> Vec<u8> {
+        let bs = self.sb.layout.block_size;
+        let end = inode.size.min(offset.saturating_add(len));
+        let mut data = Vec::new();
+        let mut pos = offset;
+        while pos < end {
+            let chunk = (bs - pos % bs).min(end - pos);
+            match self
+                .map_block(inode, pos / bs, false)
+                .expect("below the size, so within the max file size")
+            {
+                Some((block, _)) => {
+                    data.extend_from_slice(&self.read_block(block)[pos % bs..pos % bs + chunk])
+                }
+                None => data.resize(data.len() + chunk, 0),
+            }
+            pos += chunk;
+        }
+        data
+    }
+
+    // doesn't write the inode itself, its pointers and size change though
+    fn write_data(&mut self, inode: &mut Inode, offset: usize, data: &[u8]) -> Result<(), FsError> {
+        let bs = self.sb.layout.block_size;
+        let mut pos = offset;
+        let mut written = 0;
+        while written < data.len() {
+            let chunk = (bs - pos % bs).min(data.len() - written);
+            let (block, fresh) = self
+                .map_block(inode, pos / bs, true)?
+                .expect("allocated if missing");
+            let mut content = if fresh || chunk == bs {
+                vec![0; bs]
+            } else {
+                self.read_block(block)
+            };
+            content[pos % bs..pos % bs + chunk].copy_from_slice(&data[written..written + chunk]);
+            match inode.kind {
+                FileKind::File => self.write_file_block(block, &content),
+                // like ext2, the target of a symlink is metadata
+                FileKind::Directory | FileKind::Symlink => self.write_block(block, &content),
+            }
+            pos += chunk;
+            written += chunk;
+            inode.size = inode.size.max(pos);
+        }
+        Ok(())
+    }
+
+    // the data and indirect blocks below one pointer of the inode
+    fn free_tree(&mut self, block: BlockId, level: usize) {
+        if level > 0 {
+            let ps = self.sb.layout.pointer_size;
+            let pointers = self.read_block(block);
+            for i in 0..self.sb.layout.pointers_per_block() {
+                let child = get_uint(&pointers, i * ps, ps);
+                if child != 0 {
+                    self.free_tree(child, level - 1);
+                }
+            }
+        }
+        let bitmap = self.sb.block_bitmap();
+        self.set_bit(bitmap, block, false);
+    }
+
+    // every block and the inode itself
+    pub(super) fn free_inode(&mut self, inode: InodeId, content: &Inode) {
+        for (slot, block) in content.pointers.iter().enumerate() {
+            if *block != 0 {
+                let level = self.sb.slot_level(slot);
+                self.free_tree(*block, level);
+            }
+        }
+        self.write_inode(inode, None);
+        let bitmap = self.sb.inode_bitmap();
+        self.set_bit(bitmap, inode, false);
+    }
+
+    // directories
+
+    // the inode of `dir` after every change, a real one would update the mtime there anyway
+    pub(super) fn entries(
+        &mut self,
+        dir: InodeId,
+    ) -> Result<Vec<(usize, String, InodeId)>, FsError> {
+        let mut content = self.inode(dir);
+        self.entries_in(&mut content)
+    }
+
+    fn entries_in(
+        &mut self,
+        content: &mut Inode,
+    ) -> Result<Vec<(usize, String, InodeId)>, FsError> {
+        if content.kind != FileKind::Directory {
+            return Err(FsError::NotADirectory);
+        }
+        let data = self.read_data(content, 0, usize::MAX);
+        Ok(data
+            .chunks(DIRENT_SIZE)
+            .enumerate()
+            .filter_map(|(slot, entry)| {
+                decode_dirent(entry).map(|(name, inode)| (slot, name, inode))
+            })
+            .collect_vec())
+    }
+
+    // slot and inode of `name`, reads the directory block by block and stops at the first match
+    pub(super) fn find_entry(
+        &mut self,
+        dir: InodeId,
+        name: &str,
+    ) -> Result<Option<(usize, InodeId)>, FsError> {
+        let mut content = self.inode(dir);
+        self.find_in(&mut content, name)
+    }
+
+    fn find_in(
+        &mut self,
+        content: &mut Inode,
+        name: &str,
+    ) -> Result<Option<(usize, InodeId)>, FsError> {
+        if content.kind != FileKind::Directory {
+            return Err(FsError::NotADirectory);
+        }
+        let bs = self.sb.layout.block_size;
+        for start in (0..content.size).step_by(bs) {
+            let block = self.read_data(content, start, bs);
+            for (i, entry) in block.chunks(DIRENT_SIZE).enumerate() {
+                if let Some((entry_name, inode)) = decode_dirent(entry) {
+                    if entry_name == name {
+                        return Ok(Some((start / DIRENT_SIZE + i, inode)));
+                    }
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    pub(super) fn set_entry(
+        &mut self,
+        dir: InodeId,
+        slot: usize,
+        name: &str,
+        inode: InodeId,
+    ) -> Result<(), FsError> {
+        let mut content = self.inode(dir);
+        self.write_data(
+            &mut content,
+            slot * DIRENT_SIZE,
+            &encode_dirent(name, inode),
+        )?;
+        self.write_inode(dir, Some(&content));
+        Ok(())
+    }
+
+    // into the first free slot, or appended
+    pub(super) fn add_entry(
+        &mut self,
+        dir: InodeId,
+        name: &str,
+        inode: InodeId,
+    ) -> Result<(), FsError> {
+        let mut content = self.inode(dir);
+        let data = self.read_data(&mut content, 0, usize::MAX);
+        let slot = data
+            .chunks(DIRENT_SIZE)
+            .position(|entry| decode_dirent(entry).is_none())
+            .unwrap_or(data.len() / DIRENT_SIZE);
+        self.set_entry(dir, slot, name, inode)
+    }
+
+    pub(super) fn remove_entry(&mut self, dir: InodeId, slot: usize) {
+        self.set_entry(dir, slot, "", 0)
+            .expect("slot exists, nothing to allocate");
+    }
+
+    // `content` says who owns it
+    fn init_directory(
+        &mut self,
+        dir: InodeId,
+        parent: InodeId,
+        mut content: Inode,
+    ) -> Result<(), FsError> {
+        // its entry in the parent and its own "."
+        content.links = 2;
+        let mut entries = encode_dirent(".", dir);
+        entries.extend(encode_dirent("..", parent));
+        // written even if the disk is full, freeing it needs to know what it got
+        let result = self.write_data(&mut content, 0, &entries);
+        self.write_inode(dir, Some(&content));
+        result
+    }
+
+    // paths
+
+    // absolute from the root, relative from `cwd`. "." and ".." are real directory entries
+    pub fn resolve(&mut self, cwd: InodeId, path: &str) -> Result<InodeId, FsError> {
+        self.lookup(&Credentials::root(), cwd, path, true)
+            .map(|(inode, _)| inode)
+            .map_err(|d| d.error)
+    }
+
+    // the inode and its content, so the caller doesn't read it again. every directory on the way
+    // needs search permission. symlinks on the way are always followed, the last one with `follow`
+    pub(super) fn lookup(
+        &mut self,
+        cred: &Credentials,
+        cwd: InodeId,
+        path: &str,
+        follow: bool,
+    ) -> Result<(InodeId, Inode), Denied> {
+        let (mut cur, mut walked) = if path.starts_with('/') {
+            (ROOT, "/".to_owned())
+        } else {
+            (cwd, ".".to_owned())
+        };
+        let mut content = self.inode(cur);
+        let mut pending: VecDeque<String> = components(path).collect();
+        let mut followed = Vec::new();
+        while let Some(name) = pending.pop_front() {
+            if content.kind != FileKind::Directory {
+                return Err(FsError::NotADirectory.into());
+            }
+            check(cred, &content, EXECUTE, &walked)?;
+            let next = self
+                .find_in(&mut content, &name)?
+                .ok_or(FsError::NotFound)?
+                .1;
+            let mut next_content = self.inode(next);
+            if next_content.kind != FileKind::Symlink || !follow && pending.is_empty() {
+                cur = next;
+                content = next_content;
+                push_component(&mut walked, &name);
+                continue;
+            }
+
+            let target = self.read_data(&mut next_content, 0, usize::MAX);
+            let target = String::from_utf8_lossy(&target).into_owned();
+            push_component(&mut walked, &name);
+            followed.push(format!("{} -> {}", walked, target));
+            if followed.len() > MAX_SYMLINKS {
+                return Err(Denied {
+                    error: FsError::TooManySymlinks,
+                    reason: followed.join(", "),
+                });
+            }
+            // relative to the directory the symlink is in
+            if target.starts_with('/') {
+                cur = ROOT;
+                content = self.inode(ROOT);
+                walked = "/".to_owned();
+            } else {
+                walked.truncate(walked.rfind('/').map_or(0, |i| i.max(1)));
+            }
+            for component in components(&target).rev() {
+                pending.push_front(component);
+            }
+        }
+        Ok((cur, content))
+    }
+
+    // the directory a new or removed name is in, the name, and the directory's inode. changing
+    // a directory takes write and search permission on it. the name itself isn't followed
+    fn resolve_parent<'p>(
+        &mut self,
+        cred: &Credentials,
+        cwd: InodeId,
+        path: &'p str,
+    ) -> Result<(InodeId, &'p str, Inode), Denied> {
+        let path = path.trim_end_matches('/');
+        let (parent, name) = match path.rsplit_once('/') {
+            Some(("", name)) => ("/", name),
+            Some((parent, name)) => (parent, name),
+            None => ("", path),
+        };
+        if name.is_empty() || name == "." || name == ".." {
+            return Err(FsError::InvalidArgument.into());
+        }
+        if name.len() > MAX_NAME_LEN {
+            return Err(FsError::NameTooLong.into());
+        }
+        let (dir, content) = self.lookup(cred, cwd, parent, true)?;
+        if content.kind != FileKind::Directory {
+            return Err(FsError::NotADirectory.into());
+        }
+        let shown = if parent.is_empty() { "." } else { parent };
+        check(cred, &content, WRITE | EXECUTE, shown)?;
+        Ok((dir, name, content))
+    }
+
+    // `data` is the target of a symlink, files start empty
+    pub(super) fn create_inode(
+        &mut self,
+        cred: &Credentials,
+        cwd: InodeId,
+        path: &str,
+        kind: FileKind,
+        data: &[u8],
+    ) -> Result<InodeId, Denied> {
+        let (parent, name, dir) = self.resolve_parent(cred, cwd, path)?;
+        if self.find_entry(parent, name)?.is_some() {
+            return Err(FsError::AlreadyExists.into());
+        }
+        let inode = self.allocate_inode()?;
+        let mut content = Inode::new(kind, self.sb.pointer_count());
+        content.uid = cred.uid;
+        content.mode = match kind {
+            FileKind::File => 0o666 & !cred.umask,
+            FileKind::Directory => 0o777 & !cred.umask,
+            FileKind::Symlink => 0o777,
+        };
+        // a setgid directory hands down its group, and to subdirectories the bit as well
+        content.gid = cred.gid;
+        if dir.mode & SETGID != 0 {
+            content.gid = dir.gid;
+            if kind == FileKind::Directory {
+                content.mode |= SETGID;
+            }
+        }
+        let initialized = match kind {
+            FileKind::File | FileKind::Symlink => {
+                let result = self.write_data(&mut content, 0, data);
+                self.write_inode(inode, Some(&content));
+                result
+            }
+            FileKind::Directory => self.init_directory(inode, parent, content),
+        };
+        if let Err(e) = initialized.and_then(|_| self.add_entry(parent, name, inode)) {
+            let content = self.inode(inode);
+            self.free_inode(inode, &content);
+            return Err(e.into());
+        }
+        if kind == FileKind::Directory {
+            // its ".."
+            self.adjust_links(parent, 1);
+        }
+        Ok(inode)
+    }
+
+    pub fn create(&mut self, cwd: InodeId, path: &str) -> Result<InodeId, FsError> {
+        let root = Credentials::root();
+        self.transaction(|fs| fs.create_inode(&root, cwd, path, FileKind::File, &[]))
+            .map_err(|d| d.error)
+    }
+
+    pub fn mkdir(&mut self, cwd: InodeId, path: &str) -> Result<InodeId, FsError> {
+        let root = Credentials::root();
+        self.transaction(|fs| fs.create_inode(&root, cwd, path, FileKind::Directory, &[]))
+            .map_err(|d| d.error)
+    }
+
+    // `target` isn't looked at, it may not exist (yet)
+    pub fn symlink(&mut self, cwd: InodeId, target: &str, path: &str) -> Result<InodeId, FsError> {
+        let root = Credentials::root();
+        let target = target.as_bytes();
+        self.transaction(|fs| fs.create_inode(&root, cwd, path, FileKind::Symlink, target))
+            .map_err(|d| d.error)
+    }
+
+    // a second name for `existing`. a symlink gets linked itself, not what it points to
+    pub fn link(&mut self, cwd: InodeId, existing: &str, path: &str) -> Result<(), FsError> {
+        let root = Credentials::root();
+        self.transaction(|fs| fs.add_link(&root, cwd, existing, path))
+            .map_err(|d| d.error)
+    }
+
+    pub(super) fn add_link(
+        &mut self,
+        cred: &Credentials,
+        cwd: InodeId,
+        existing: &str,
+        path: &str,
+    ) -> Result<(), Denied> {
+        let (inode, mut content) = self.lookup(cred, cwd, existing, false)?;
+        if content.kind == FileKind::Directory {
+            return Err(Denied {
+                error: FsError::NotPermitted,
+                reason: format!(
+                    "{} is a directory, a second name could make a cycle",
+                    existing
+                ),
+            });
+        }
+        let (parent, name, _) = self.resolve_parent(cred, cwd, path)?;
+        if self.find_entry(parent, name)?.is_some() {
+            return Err(FsError::AlreadyExists.into());
+        }
+        self.add_entry(parent, name, inode)?;
+        content.links += 1;
+        self.write_inode(inode, Some(&content));
+        Ok(())
+    }
+
+    pub fn readlink(&mut self, cwd: InodeId, path: &str) -> Result<String, FsError> {
+        self.read_link(&Credentials::root(), cwd, path)
+            .map_err(|d| d.error)
+    }
+
+    pub(super) fn read_link(
+        &mut self,
+        cred: &Credentials,
+        cwd: InodeId,
+        path: &str,
+    ) -> Result<String, Denied> {
+        let (_, mut content) = self.lookup(cred, cwd, path, false)?;
+        if content.kind != FileKind::Symlink {
+            return Err(FsError::InvalidArgument.into());
+        }
+        let target = self.read_data(&mut content, 0, usize::MAX);
+        Ok(String::from_utf8_lossy(&target).into_owned())
+    }
+
+    // one name less. the inode goes once no name is left and no one has it open anymore, until
+    // then it's an orphan only the open files reach
+    fn drop_link(&mut self, inode: InodeId) {
+        let mut content = self.inode(inode);
+        content.links -= 1;
+        if content.links == 0 && !self.open_counts.contains_key(&inode) {
+            self.free_inode(inode, &content);
+        } else {
+            self.write_inode(inode, Some(&content));
+        }
+    }
+
+    pub fn unlink(&mut self, cwd: InodeId, path: &str) -> Result<(), FsError> {
+        let root = Credentials::root();
+        self.transaction(|fs| fs.unlink_file(&root, cwd, path))
+            .map_err(|d| d.error)
+    }
+
+    pub(super) fn unlink_file(
+        &mut self,
+        cred: &Credentials,
+        cwd: InodeId,
+        path: &str,
+    ) -> Result<(), Denied> {
+        let (parent, name, dir) = self.resolve_parent(cred, cwd, path)?;
+        let (slot, inode) = self.find_entry(parent, name)?.ok_or(FsError::NotFound)?;
+        let content = self.inode(inode);
+        if content.kind == FileKind::Directory {
+            return Err(FsError::IsADirectory.into());
+        }
+        may_delete(cred, &dir, &content, path)?;
+        self.remove_entry(parent, slot);
+        self.drop_link(inode);
+        Ok(())
+    }
+
+    fn is_empty_directory(&mut self, dir: InodeId) -> Result<bool, FsError> {
+        Ok(self
+            .entries(dir)?
+            .iter()
+            .all(|(_, name, _)| name == "." || name == ".."))
+    }
+
+    pub fn rmdir(&mut self, cwd: InodeId, path: &str) -> Result<(), FsError> {
+        let root = Credentials::root();
+        self.transaction(|fs| fs.remove_directory(&root, cwd, path))
+            .map_err(|d| d.error)
+    }
+
+    pub(super) fn remove_directory(
+        &mut self,
+        cred: &Credentials,
+        cwd: InodeId,
+        path: &str,
+    ) -> Result<(), Denied> {
+        let (parent, name, dir) = self.resolve_parent(cred, cwd, path)?;
+        let (slot, removed) = self.find_entry(parent, name)?.ok_or(FsError::NotFound)?;
+        let content = self.inode(removed);
+        if !self.is_empty_directory(removed)? {
+            return Err(FsError::DirectoryNotEmpty.into());
+        }
+        may_delete(cred, &dir, &content, path)?;
+        self.remove_entry(parent, slot);
+        self.adjust_links(parent, -1);
+        self.free_inode(removed, &content);
+        Ok(())
+    }
+
+    // replaces `to` if it exists: a file by a file, an empty directory by a directory
+    pub fn rename(&mut self, cwd: InodeId, from: &str, to: &str) -> Result<(), FsError> {
+        let root = Credentials::root();
+        self.transaction(|fs| fs.move_entry(&root, cwd, from, to))
+            .map_err(|d| d.error)
+    }
+
+    pub(super) fn move_entry(
+        &mut self,
+        cred: &Credentials,
+        cwd: InodeId,
+        from: &str,
+        to: &str,
+    ) -> Result<(), Denied> {
+        let (from_parent, from_name, from_dir) = self.resolve_parent(cred, cwd, from)?;
+        let (to_parent, to_name, to_dir) = self.resolve_parent(cred, cwd, to)?;
+        let (from_slot, inode) = self
+            .find_entry(from_parent, from_name)?
+            .ok_or(FsError::NotFound)?;
+        let moved = self.inode(inode);
+        let is_dir = moved.kind == FileKind::Directory;
+        may_delete(cred, &from_dir, &moved, from)?;
+        if is_dir && from_parent != to_parent {
+            // its ".." changes
+            check(cred, &moved, WRITE, from)?;
+        }
+
+        if is_dir {
+            // walk up from the new parent, the directory must not be on the way
+            let mut cur = to_parent;
+            while cur != ROOT {
+                if cur == inode {
+                    return Err(FsError::InvalidArgument.into());
+                }
+                cur = self
+                    .find_entry(cur, "..")?
+                    .expect("every directory has ..")
+                    .1;
+            }
+        }
+
+        match self.find_entry(to_parent, to_name)? {
+            Some((_, existing)) if existing == inode => return Ok(()),
+            Some((to_slot, existing)) => {
+                let replaced = self.inode(existing);
+                match (is_dir, replaced.kind) {
+                    (false, FileKind::Directory) => return Err(FsError::IsADirectory.into()),
+                    (true, FileKind::File | FileKind::Symlink) => {
+                        return Err(FsError::NotADirectory.into())
+                    }
+                    (true, FileKind::Directory) if !self.is_empty_directory(existing)? => {
+                        return Err(FsError::DirectoryNotEmpty.into())
+                    }
+                    _ => {}
+                }
+                may_delete(cred, &to_dir, &replaced, to)?;
+                self.set_entry(to_parent, to_slot, to_name, inode)?;
+                if is_dir {
+                    // loses the ".." of the replaced one, gets the one of `inode` below
+                    self.adjust_links(to_parent, -1);
+                    let content = self.inode(existing);
+                    self.free_inode(existing, &content);
+                } else {
+                    self.drop_link(existing);
+                }
+            }
+            None => self.add_entry(to_parent, to_name, inode)?,
+        }
+
+        self.remove_entry(from_parent, from_slot);
+        if is_dir && from_parent != to_parent {
+            self.set_entry(inode, 1, "..", to_parent)?;
+            self.adjust_links(from_parent, -1);
+            self.adjust_links(to_parent, 1);
+        }
+        Ok(())
+    }
+
+    pub fn read(
+        &mut self,
+        cwd: InodeId,
+        path: &str,
+        offset: usize,
+        len: usize,
+    ) -> Result<Vec<u8>, FsError> {
+        self.read_file(&Credentials::root(), cwd, path, offset, len)
+            .map_err(|d| d.error)
+    }
+
+    pub(super) fn read_file(
+        &mut self,
+        cred: &Credentials,
+        cwd: InodeId,
+        path: &str,
+        offset: usize,
+        len: usize,
+    ) -> Result<Vec<u8>, Denied> {
+        let (_, mut content) = self.lookup(cred, cwd, path, true)?;
+        if content.kind == FileKind::Directory {
+            return Err(FsError::IsADirectory.into());
+        }
+        check(cred, &content, READ, path)?;
+        Ok(self.read_data(&mut content, offset, len))
+    }
+
+    pub fn write(
+        &mut self,
+        cwd: InodeId,
+        path: &str,
+        offset: usize,
+        data: &[u8],
+    ) -> Result<(), FsError> {
+        let root = Credentials::root();
+        self.write_in_parts(offset, data, |fs, offset, data| {
+            fs.write_file(&root, cwd, path, offset, data)
+        })
+        .map_err(|d| d.error)
+    }
+
+    pub(super) fn write_file(
+        &mut self,
+        cred: &Credentials,
+        cwd: InodeId,
+        path: &str,
+        offset: usize,
+        data: &[u8],
+    ) -> Result<(), Denied> {
+        let (inode, mut content) = self.lookup(cred, cwd, path, true)?;
+        if content.kind == FileKind::Directory {
+            return Err(FsError::IsADirectory.into());
+        }
+        check(cred, &content, WRITE, path)?;
+        Ok(self.write_at(cred, inode, &mut content, offset, data)?)
+    }
+
+    // with the permission already checked, by the path or when the file got opened
+    pub(super) fn write_at(
+        &mut self,
+        cred: &Credentials,
+        inode: InodeId,
+        content: &mut Inode,
+        offset: usize,
+        data: &[u8],
+    ) -> Result<(), FsError> {
+        // or anyone allowed to write could plant a program that runs as the owner
+        if cred.uid != 0 {
+            content.mode &= !(SETUID | SETGID);
+        }
+        let result = self.write_data(content, offset, data);
+        // also when it ran out of space halfway, the blocks it got are in the inode
+        self.write_inode(inode, Some(content));
+        result
+    }
+
+    pub fn read_dir(
+        &mut self,
+        cwd: InodeId,
+        path: &str,
+    ) -> Result<Vec<(String, InodeId)>, FsError> {
+        self.list_dir(&Credentials::root(), cwd, path)
+            .map_err(|d| d.error)
+    }
+
+    pub(super) fn list_dir(
+        &mut self,
+        cred: &Credentials,
+        cwd: InodeId,
+        path: &str,
+    ) -> Result<Vec<(String, InodeId)>, Denied> {
+        let (_, mut content) = self.lookup(cred, cwd, path, true)?;
+        if content.kind == FileKind::Directory {
+            check(cred, &content, READ, path)?;
+        }
+        Ok(self
+            .entries_in(&mut content)?
+            .into_iter()
+            .map(|(_, name, inode)| (name, inode))
+            .collect_vec())
+    }
+
+    // only needs the path to be searchable
+    pub fn stat(&mut self, cwd: InodeId, path: &str) -> Result<Inode, FsError> {
+        self.lookup(&Credentials::root(), cwd, path, true)
+            .map(|(_, content)| content)
+            .map_err(|d| d.error)
+    }
+
+    // the symlink itself, not what it points to
+    pub fn lstat(&mut self, cwd: InodeId, path: &str) -> Result<Inode, FsError> {
+        self.lookup(&Credentials::root(), cwd, path, false)
+            .map(|(_, content)| content)
+            .map_err(|d| d.error)
+    }
+}
+
+fn components(path: &str) -> impl DoubleEndedIterator<Item = String> + '_ {
+    path.split('/').filter(|c| !c.is_empty()).map(str::to_owned)
+}
+
+fn push_component(walked: &mut String, name: &str) {
+    if !walked.ends_with('/') {
+        walked.push('/');
+    }
+    walked.push_str(name);
+}
+
+fn print_cost<T: std::fmt::Debug>(what: &str, (result, io): (T, IoStats)) -> T {
+    println!(
+        "{:<36} {:>5} reads {:>5} writes   {:?}",
+        what, io.reads, io.writes, result
+    );
+    result
+}
+
+pub fn test_filesystem() {
+    println!("\n## FILESYSTEM\n");
+
+    // few direct pointers, so even small files need indirect blocks
+    let layout = InodeLayout {
+        block_size: 1024,
+        pointer_size: 4,
+        direct: 4,
+        indirect: [1, 1, 0],
+    };
+    let mut fs = Filesystem::format(MemoryDisk::new(1024, 512), layout, 64);
+    let sb = fs.sb;
+    println!(
+        "inode bitmap at {}, block bitmap at {}, inode table at {}, data from block {} on",
+        sb.inode_bitmap(),
+        sb.block_bitmap(),
+        sb.inode_table(),
+        sb.data_start()
+    );
+    let free_blocks = fs.free_block_count();
+    let free_inodes = fs.free_inode_count();
+
+    println!("\n### Cost per operation\n");
+    let a = print_cost("mkdir /a", fs.measure(|fs| fs.mkdir(ROOT, "/a"))).unwrap();
+    print_cost("mkdir /a/b", fs.measure(|fs| fs.mkdir(ROOT, "/a/b"))).unwrap();
+    let c = print_cost("create /a/b/c", fs.measure(|fs| fs.create(ROOT, "/a/b/c"))).unwrap();
+    print_cost(
+        "write 5 bytes to /a/b/c",
+        fs.measure(|fs| fs.write(ROOT, "/a/b/c", 0, b"hello")),
+    )
+    .unwrap();
+
+    // open("/a/b/c"): root inode, root directory, a, its directory, b, its directory, c
+    let (opened, io) = fs.measure(|fs| fs.stat(ROOT, "/a/b/c"));
+    let size = print_cost("open /a/b/c", (opened.map(|i| i.size), io));
+    assert_eq!(size, Ok(5));
+    assert_eq!(
+        io,
+        IoStats {
+            reads: 7,
+            writes: 0
+        }
+    );
+    // relative to /a, every component costs the inode and the block of a directory, and c itself
+    // gets read too, it could be a symlink
+    let (resolved, io) = fs.measure(|fs| fs.resolve(a, "b/./../b/c"));
+    assert_eq!(
+        print_cost("resolve b/./../b/c from /a", (resolved, io)),
+        Ok(c)
+    );
+    assert_eq!(io.reads, 5 * 2 + 1);
+    assert_eq!(fs.resolve(c, ""), Ok(c));
+
+    let data = (0..10_000).map(|i| (i % 251) as u8).collect_vec();
+    print_cost(
+        "write 10000 bytes to /a/big",
+        fs.measure(|fs| {
+            fs.create(ROOT, "/a/big")?;
+            fs.write(ROOT, "/a/big", 0, &data)
+        }),
+    )
+    .unwrap();
+    let (read, io) = fs.measure(|fs| fs.read(ROOT, "/a/big", 0, usize::MAX));
+    print_cost("read /a/big", (read.as_ref().map(Vec::len), io)).unwrap();
+    assert_eq!(read.as_ref(), Ok(&data));
+    // path and inode, 10 data blocks, and the single indirect block once for each of the 6 data
+    // blocks behind it. nothing is cached
+    assert_eq!(io.reads, 5 + 10 + 6);
+    assert_eq!(fs.read(ROOT, "/a/big", 9998, 10), Ok(data[9998..].to_vec()));
+    print_cost(
+        "write 1 byte at 300000 of /a/big",
+        fs.measure(|fs| fs.write(ROOT, "/a/big", 300_000, b"!")),
+    )
+    .unwrap();
+    // a hole reads as zeros
+    assert_eq!(fs.read(ROOT, "/a/big", 299_999, 2), Ok(vec![0, b'!']));
+
+    print_cost(
+        "rename /a/b/c -> /a/d",
+        fs.measure(|fs| fs.rename(ROOT, "/a/b/c", "/a/d")),
+    )
+    .unwrap();
+    print_cost(
+        "rename /a/b -> /e",
+        fs.measure(|fs| fs.rename(ROOT, "/a/b", "/e")),
+    )
+    .unwrap();
+    print_cost("unlink /a/big", fs.measure(|fs| fs.unlink(ROOT, "/a/big"))).unwrap();
+
+    println!("\n### Directories\n");
+    for dir in ["/", "/a", "/e"] {
+        println!("{}: {:?}", dir, fs.read_dir(ROOT, dir).unwrap());
+    }
+    assert_eq!(fs.resolve(ROOT, "/e/.."), Ok(ROOT));
+    assert_eq!(fs.read(ROOT, "/a/d", 0, 100), Ok(b"hello".to_vec()));
+    // ".", "..", and the ".." of a and e
+    assert_eq!(fs.stat(ROOT, "/").unwrap().links, 4);
+    assert_eq!(fs.stat(ROOT, "/a").unwrap().links, 2);
+
+    println!("\n### Errors\n");
+    let errors = [
+        ("create /a/d", fs.create(ROOT, "/a/d").map(|_| ())),
+        ("resolve /a/d/x", fs.resolve(ROOT, "/a/d/x").map(|_| ())),
+        ("resolve /a/x", fs.resolve(ROOT, "/a/x").map(|_| ())),
+        ("unlink /a", fs.unlink(ROOT, "/a")),
+        ("rmdir /a", fs.rmdir(ROOT, "/a")),
+        ("rename /a -> /a/f", fs.rename(ROOT, "/a", "/a/f")),
+        ("mkdir /..", fs.mkdir(ROOT, "/..").map(|_| ())),
+        (
+            "create with a long name",
+            fs.create(ROOT, &"x".repeat(MAX_NAME_LEN + 1)).map(|_| ()),
+        ),
+        (
+            "write past the max size",
+            fs.write(ROOT, "/a/d", layout.max_file_size(), b"x"),
+        ),
+    ];
+    for (what, result) in &errors {
+        println!("{:<36} {:?}", what, result);
+    }
+    assert_eq!(
+        errors.map(|(_, result)| result.unwrap_err()),
+        [
+            FsError::AlreadyExists,
+            FsError::NotADirectory,
+            FsError::NotFound,
+            FsError::IsADirectory,
+            FsError::DirectoryNotEmpty,
+            FsError::InvalidArgument,
+            FsError::InvalidArgument,
+            FsError::NameTooLong,
+            FsError::FileTooLarge,
+        ]
+    );
+
+    println!("\n### Everything removed again\n");
+    fs.unlink(ROOT, "/a/d").unwrap();
+    fs.rmdir(ROOT, "/a").unwrap();
+    fs.rmdir(ROOT, "e").unwrap();
+    println!("{:?}", fs.read_dir(ROOT, "/").unwrap());
+    assert_eq!(fs.free_block_count(), free_blocks);
+    assert_eq!(fs.free_inode_count(), free_inodes);
+
+    // the image is all there is
+    let mut fs = Filesystem::mount(fs.into_device());
+    assert_eq!(fs.read_dir(ROOT, "/").unwrap().len(), 2);
+
+    println!("\n### Full disk\n");
+    let mut fs = Filesystem::format(MemoryDisk::new(1024, 128), layout, 64);
+    fs.create(ROOT, "/fill").unwrap();
+    let filled = fs.write(ROOT, "/fill", 0, &vec![1; 128 * 1024]);
+    let (free_blocks, free_inodes) = (fs.free_block_count(), fs.free_inode_count());
+    let created = fs.mkdir(ROOT, "/d");
+    println!("write 128 KiB: {:?}, then mkdir /d: {:?}", filled, created);
+    assert_eq!(filled, Err(FsError::NoSpace));
+    assert_eq!(free_blocks, 0);
+    assert_eq!(created, Err(FsError::NoSpace));
+    // nothing leaked, the inode went back
+    assert_eq!(fs.free_block_count(), free_blocks);
+    assert_eq!(fs.free_inode_count(), free_inodes);
+    assert_eq!(fs.resolve(ROOT, "/d"), Err(FsError::NotFound));
+}
@@ -0,0 +1,427 @@
+use std::collections::BTreeMap;
+
+use itertools::Itertools;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use super::block_device::{BlockId, FreeSpaceMap};
+
+// bookkeeping of a free-space manager in bytes. lists kept inside the free blocks themselves
+// cost no usable space, they just have to be read to be followed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MetadataCost {
+    pub reserved: usize,
+    pub in_free_blocks: usize,
+}
+
+pub trait FreeSpaceManager {
+    fn name(&self) -> String;
+    // n blocks, not necessarily contiguous. all or nothing
+    fn allocate(&mut self, n: usize) -> Option<Vec<BlockId>>;
+    fn free(&mut self, block: BlockId);
+    // ascending
+    fn free_blocks(&self) -> Vec<BlockId>;
+    fn metadata(&self) -> MetadataCost;
+    // blocks read so far just to follow the list to the next free blocks
+    fn list_reads(&self) -> usize {
+        0
+    }
+}
+
+// the bitmap: one bit per block, allocates the lowest free blocks
+impl FreeSpaceManager for FreeSpaceMap {
+    fn name(&self) -> String {
+        "bitmap".to_owned()
+    }
+
+    fn allocate(&mut self, n: usize) -> Option<Vec<BlockId>> {
+        let blocks = self.first_free(n)?;
+        blocks.iter().for_each(|b| self.mark_used(*b));
+        Some(blocks)
+    }
+
+    fn free(&mut self, block: BlockId) {
+        FreeSpaceMap::free(self, block)
+    }
+
+    fn free_blocks(&self) -> Vec<BlockId> {
+        (0..self.block_count())
+            .filter(|b| self.is_free(*b))
+            .collect_vec()
+    }
+
+    fn metadata(&self) -> MetadataCost {
+        MetadataCost {
+            reserved: self.block_count().div_ceil(8),
+            in_free_blocks: 0,
+        }
+    }
+}
+
+// every free block holds a pointer to the next one, the superblock points to the head.
+// a stack: the block freed last is handed out first
+pub struct FreeList {
+    pointer_size: usize,
+    // the head is the last one
+    stack: Vec<BlockId>,
+    reads: usize,
+}
+
+impl FreeList {
+    // with everything free, the list runs through the disk in order
+    pub fn new(block_count: usize, pointer_size: usize) -> Self {
+        FreeList {
+            pointer_size,
+            stack: (0..block_count).rev().collect(),
+            reads: 0,
+        }
+    }
+}
+
+impl FreeSpaceManager for FreeList {
+    fn name(&self) -> String {
+        "free list".to_owned()
+    }
+
+    fn allocate(&mut self, n: usize) -> Option<Vec<BlockId>> {
+        // following the list means reading every block that gets handed out
+        (n <= self.stack.len()).then(|| {
+            self.reads += n;
+            self.stack
+                .split_off(self.stack.len() - n)
+                .into_iter()
+                .rev()
+                .collect()
+        })
+    }
+
+    fn free(&mut self, block: BlockId) {
+        debug_assert!(
+            !self.stack.contains(&block),
+            "block {} is already free",
+            block
+        );
+        self.stack.push(block);
+    }
+
+    fn free_blocks(&self) -> Vec<BlockId> {
+        self.stack.iter().copied().sorted().collect_vec()
+    }
+
+    fn metadata(&self) -> MetadataCost {
+        MetadataCost {
+            reserved: self.pointer_size,
+            in_free_blocks: self.stack.len() * self.pointer_size,
+        }
+    }
+
+    fn list_reads(&self) -> usize {
+        self.reads
+    }
+}
+
+// the free list, but every list block is a free block full of addresses of other free blocks and
+// a pointer to the next list block. one read yields a whole group of free blocks
+pub struct Grouping {
+    pointer_size: usize,
+    // addresses per list block, besides the next pointer
+    group_size: usize,
+    // (list block, the free blocks it lists), the head is the last one
+    groups: Vec<(BlockId, Vec<BlockId>)>,
+    reads: usize,
+}
+
+impl Grouping {
+    pub fn new(block_count: usize, block_size: usize, pointer_size: usize) -> Self {
+        let mut grouping = Grouping {
+            pointer_size,
+            group_size: block_size / pointer_size - 1,
+            groups: Vec::new(),
+            reads: 0,
+        };
+        (0..block_count).rev().for_each(|b| grouping.free(b));
+        grouping
+    }
+}
+
+impl FreeSpaceManager for Grouping {
+    fn name(&self) -> String {
+        format!("grouping by {}", self.group_size)
+    }
+
+    fn allocate(&mut self, n: usize) -> Option<Vec<BlockId>> {
+        if self.free_blocks().len() < n {
+            return None;
+        }
+        let mut blocks = Vec::new();
+        while blocks.len() < n {
+            let (list_block, free) = self.groups.last_mut().expect("counted");
+            match free.pop() {
+                Some(block) => blocks.push(block),
+                // emptied, the list block itself goes last and the next group becomes the head
+                None => {
+                    blocks.push(*list_block);
+                    self.groups.pop();
+                    self.reads += 1;
+                }
+            }
+        }
+        Some(blocks)
+    }
+
+    fn free(&mut self, block: BlockId) {
+        match self.groups.last_mut() {
+            Some((_, free)) if free.len() < self.group_size => free.push(block),
+            _ => self.groups.push((block, Vec::new())),
+        }
+    }
+
+    fn free_blocks(&self) -> Vec<BlockId> {
+        self.groups
+            .iter()
+            .flat_map(|(list_block, free)| free.iter().chain([list_block]))
+            .copied()
+            .sorted()
+            .collect_vec()
+    }
+
+    fn metadata(&self) -> MetadataCost {
+        MetadataCost {
+            reserved: self.pointer_size,
+            in_free_blocks: self
+                .groups
+                .iter()
+                .map(|(_, free)| (free.len() + 1) * self.pointer_size)
+                .sum(),
+        }
+    }
+
+    fn list_reads(&self) -> usize {
+        self.reads
+    }
+}
+
+// runs of free blocks as (first block, length), kept in a tree like the extent trees of XFS or
+// ext4's mballoc. neighbouring runs get merged on free
+pub struct Counting {
+    pointer_size: usize,
+    extents: BTreeMap<BlockId, usize>,
+}
+
+impl Counting {
+    pub fn new(block_count: usize, pointer_size: usize) -> Self {
+        Counting {
+            pointer_size,
+            extents: BTreeMap::from([(0, block_count)]),
+        }
+    }
+
+    fn take(&mut self, start: BlockId, n: usize) -> Vec<BlockId> {
+        let len = self.extents.remove(&start).expect("extent exists");
+        let n = n.min(len);
+        if n < len {
+            self.extents.insert(start + n, len - n);
+        }
+        (start..start + n).collect_vec()
+    }
+}
+
+impl FreeSpaceManager for Counting {
+    fn name(&self) -> String {
+        "counting".to_owned()
+    }
+
+    // the first run that holds all of them, otherwise run after run from the start
+    fn allocate(&mut self, n: usize) -> Option<Vec<BlockId>> {
+        if self.extents.values().sum::<usize>() < n {
+            return None;
+        }
+        if let Some((&start, _)) = self.extents.iter().find(|(_, len)| **len >= n) {
+            return Some(self.take(start, n));
+        }
+        let mut blocks = Vec::new();
+        while blocks.len() < n {
+            let start = *self.extents.keys().next().expect("counted");
+            blocks.extend(self.take(start, n - blocks.len()));
+        }
+        Some(blocks)
+    }
+
+    fn free(&mut self, block: BlockId) {
+        let (mut start, mut len) = (block, 1);
+        if let Some((&before, &before_len)) = self.extents.range(..block).next_back() {
+            assert!(
+                before + before_len <= block,
+                "block {} is already free",
+                block
+            );
+            if before + before_len == block {
+                self.extents.remove(&before);
+                (start, len) = (before, before_len + 1);
+            }
+        }
+        if let Some(after_len) = self.extents.remove(&(block + 1)) {
+            len += after_len;
+        }
+        assert!(
+            !self.extents.contains_key(&block),
+            "block {} is already free",
+            block
+        );
+        self.extents.insert(start, len);
+    }
+
+    fn free_blocks(&self) -> Vec<BlockId> {
+        self.extents
+            .iter()
+            .flat_map(|(start, len)| *start..start + len)
+            .collect_vec()
+    }
+
+    fn metadata(&self) -> MetadataCost {
+        MetadataCost {
+            reserved: self.extents.len() * 2 * self.pointer_size,
+            in_free_blocks: 0,
+        }
+    }
+}
+
+// maximal runs of consecutive blocks, as lengths
+fn runs(sorted_blocks: &[BlockId]) -> Vec<usize> {
+    sorted_blocks
+        .iter()
+        .enumerate()
+        .group_by(|(i, b)| **b - i)
+        .into_iter()
+        .map(|(_, run)| run.count())
+        .collect_vec()
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct FragmentationReport {
+    pub free_blocks: usize,
+    pub free_runs: usize,
+    pub largest_free_run: usize,
+    // how many pieces the files are in, on average. 1.0 == all contiguous
+    pub extents_per_file: f64,
+    pub metadata: MetadataCost,
+    pub list_reads: usize,
+}
+
+// allocate a file of 1 to `max_file_blocks` blocks or delete a random one, `steps` times.
+// creating is more likely while less than `utilization` of the disk is in use, deleting above
+pub fn random_workload(
+    manager: &mut dyn FreeSpaceManager,
+    seed: u64,
+    utilization: f64,
+    steps: usize,
+    max_file_blocks: usize,
+) -> FragmentationReport {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let block_count = manager.free_blocks().len();
+    let mut files: Vec<Vec<BlockId>> = Vec::new();
+    for _ in 0..steps {
+        let used = files.iter().map(Vec::len).sum::<usize>() as f64 / block_count as f64;
+        let create = rng.gen_bool(if used < utilization { 0.75 } else { 0.25 });
+        if files.is_empty() || create {
+            if let Some(blocks) = manager.allocate(rng.gen_range(1..=max_file_blocks)) {
+                files.push(blocks);
+            }
+        } else {
+            let file = files.swap_remove(rng.gen_range(0..files.len()));
+            file.into_iter().for_each(|b| manager.free(b));
+        }
+    }
+
+    let free = manager.free_blocks();
+    let free_runs = runs(&free);
+    FragmentationReport {
+        free_blocks: free.len(),
+        free_runs: free_runs.len(),
+        largest_free_run: free_runs.iter().copied().max().unwrap_or(0),
+        extents_per_file: files
+            .iter()
+            .map(|f| runs(&f.iter().copied().sorted().collect_vec()).len())
+            .sum::<usize>() as f64
+            / files.len().max(1) as f64,
+        metadata: manager.metadata(),
+        list_reads: manager.list_reads(),
+    }
+}
+
+fn managers(block_count: usize, block_size: usize) -> Vec<Box<dyn FreeSpaceManager>> {
+    vec![
+        Box::new(FreeSpaceMap::new(block_count)),
+        Box::new(FreeList::new(block_count, 4)),
+        Box::new(Grouping::new(block_count, block_size, 4)),
+        Box::new(Counting::new(block_count, 4)),
+    ]
+}
+
+pub fn test_free_space() {
+    println!("\n## FREE-SPACE MANAGEMENT\n");
+
+    println!("\n### Allocate 3, 2 and 3 blocks, free the first file, allocate 4\n");
+    for mut manager in managers(16, 16) {
+        let a = manager.allocate(3).unwrap();
+        let b = manager.allocate(2).unwrap();
+        let c = manager.allocate(3).unwrap();
+        a.iter().for_each(|block| manager.free(*block));
+        let d = manager.allocate(4).unwrap();
+        println!(
+            "{:<14} {:?} {:?} {:?}, then {:?}, free: {:?}, metadata: {:?}",
+            manager.name(),
+            a,
+            b,
+            c,
+            d,
+            manager.free_blocks(),
+            manager.metadata()
+        );
+        assert_eq!(manager.free_blocks().len(), 16 - 9);
+        assert!(manager.allocate(8).is_none());
+        assert_eq!(manager.free_blocks().len(), 16 - 9);
+    }
+
+    println!("\n### Random workload, 4096 blocks of 1 KiB, files of 1 to 32 blocks, 70% in use\n");
+    println!(
+        "{:<16} {:>6} {:>9} {:>12} {:>13} {:>11} {:>17} {:>11}",
+        "",
+        "free",
+        "free runs",
+        "largest run",
+        "extents/file",
+        "reserved B",
+        "in free blocks B",
+        "list reads"
+    );
+    let reports = managers(4096, 1024)
+        .into_iter()
+        .map(|mut manager| {
+            let report = random_workload(manager.as_mut(), 3, 0.7, 5000, 32);
+            println!(
+                "{:<16} {:>6} {:>9} {:>12} {:>13.2} {:>11} {:>17} {:>11}",
+                manager.name(),
+                report.free_blocks,
+                report.free_runs,
+                report.largest_free_run,
+                report.extents_per_file,
+                report.metadata.reserved,
+                report.metadata.in_free_blocks,
+                report.list_reads
+            );
+            report
+        })
+        .collect_vec();
+    // they all made the same decisions, just with different blocks
+    assert!(reports.iter().map(|r| r.free_blocks).all_equal());
+    let [bitmap, list, grouping, counting] = reports.as_slice() else {
+        unreachable!()
+    };
+    // handing out whatever was freed last scatters the files all over the disk
+    assert!(list.extents_per_file > bitmap.extents_per_file);
+    // grouping hands out the same blocks as the list, it just has to read far fewer list blocks
+    assert_eq!(grouping.extents_per_file, list.extents_per_file);
+    assert!(grouping.list_reads * 100 < list.list_reads);
+    // looking for a run that fits keeps most files in one piece
+    assert!(counting.extents_per_file < bitmap.extents_per_file);
+}
@@ -0,0 +1,820 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
+    fmt,
+};
+
+use itertools::Itertools;
+
+use super::{
+    block_device::{BlockDevice, BlockId, MemoryDisk},
+    filesystem::{get_uint, put_uint, FileKind, Filesystem, FsError, Inode, InodeId, ROOT},
+    inode::InodeLayout,
+    journal::{crash_images, CrashDisk},
+};
+
+// e2fsck in miniature. nothing on the image is trusted except the superblock, the passes:
+// 1. every inode in use: walk its block pointers, clear the ones outside the data area and note
+//    which pointers reference which block
+// 2. the bitmaps against pass 1. a referenced block marked free would get handed out a second
+//    time, a block marked used that nothing references is lost for good
+// 3. blocks referenced more than once get copied, every reference but the first gets its own copy
+// 4. directory entries pointing to free inodes get removed, a wrong "." gets fixed
+// 5. inodes no directory reaches go to /lost+found, empty ones get freed. wrong ".." get fixed
+// 6. link counts from the entries that point to each inode
+// the bitmaps get fixed before any repair allocates, otherwise it could hand out a block in use.
+// a repair that needs room the image doesn't have, or a root that isn't a directory anymore, gets
+// reported as unrepairable and the damage stays
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    // a pointer outside the data area, in the inode or one of its indirect blocks
+    BadPointer {
+        inode: InodeId,
+        block: BlockId,
+    },
+    // referenced, but free in the bitmap
+    BlockMarkedFree(BlockId),
+    // used in the bitmap, but nothing references it
+    LostBlock(BlockId),
+    // in use, but free in the bitmap
+    InodeMarkedFree(InodeId),
+    // used in the bitmap, but the inode is free
+    LostInode(InodeId),
+    DuplicateBlock {
+        block: BlockId,
+        inodes: Vec<InodeId>,
+    },
+    DanglingEntry {
+        dir: InodeId,
+        name: String,
+        inode: InodeId,
+    },
+    WrongDot {
+        dir: InodeId,
+        points_to: InodeId,
+    },
+    WrongDotDot {
+        dir: InodeId,
+        points_to: InodeId,
+        parent: InodeId,
+    },
+    // in use, but no directory reaches it
+    Orphan {
+        inode: InodeId,
+        kind: FileKind,
+        reconnected: bool,
+    },
+    WrongLinkCount {
+        inode: InodeId,
+        stored: usize,
+        actual: usize,
+    },
+    // found, but the repair failed
+    Unrepairable {
+        problem: Box<Problem>,
+        error: FsError,
+    },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::BadPointer { inode, block } => write!(
+                f,
+                "inode {} points to block {} outside the data area: pointer cleared",
+                inode, block
+            ),
+            Problem::BlockMarkedFree(block) => write!(
+                f,
+                "block {} is in use, but free in the bitmap: marked used",
+                block
+            ),
+            Problem::LostBlock(block) => write!(
+                f,
+                "block {} is used in the bitmap, but nothing references it: marked free",
+                block
+            ),
+            Problem::InodeMarkedFree(inode) => write!(
+                f,
+                "inode {} is in use, but free in the bitmap: marked used",
+                inode
+            ),
+            Problem::LostInode(inode) => write!(
+                f,
+                "inode {} is used in the bitmap, but free: marked free",
+                inode
+            ),
+            Problem::DuplicateBlock { block, inodes } => write!(
+                f,
+                "block {} is referenced by the inodes {:?}: copied for all but the first",
+                block, inodes
+            ),
+            Problem::DanglingEntry { dir, name, inode } => write!(
+                f,
+                "entry {:?} in directory {} points to the free inode {}: removed",
+                name, dir, inode
+            ),
+            Problem::WrongDot { dir, points_to } => write!(
+                f,
+                "\".\" of directory {} points to {}: fixed",
+                dir, points_to
+            ),
+            Problem::WrongDotDot {
+                dir,
+                points_to,
+                parent,
+            } => write!(
+                f,
+                "\"..\" of directory {} points to {} instead of {}: fixed",
+                dir, points_to, parent
+            ),
+            Problem::Orphan {
+                inode,
+                kind,
+                reconnected,
+            } => write!(
+                f,
+                "{:?} inode {} is in no directory: {}",
+                kind,
+                inode,
+                if *reconnected {
+                    format!("moved to /lost+found/#{}", inode)
+                } else {
+                    "empty, freed".to_owned()
+                }
+            ),
+            Problem::WrongLinkCount {
+                inode,
+                stored,
+                actual,
+            } => write!(
+                f,
+                "inode {} has {} links, but {} entries point to it: fixed",
+                inode, stored, actual
+            ),
+            Problem::Unrepairable { problem, error } => {
+                write!(f, "{}, but that failed: {:?}", problem, error)
+            }
+        }
+    }
+}
+
+// where a block pointer is stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PointerAt {
+    Inode(InodeId, usize),
+    // entry of an indirect block
+    Indirect(BlockId, usize),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Reference {
+    inode: InodeId,
+    at: PointerAt,
+    // of the referenced block, 0 for data
+    level: usize,
+}
+
+// checks and repairs the whole image, returns what was wrong. a second run finds nothing but what
+// was unrepairable
+pub fn fsck<D: BlockDevice>(fs: &mut Filesystem<D>) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    let (inodes, references) = scan_inodes(fs, &mut problems);
+    check_bitmaps(fs, &inodes, &references, &mut problems);
+    copy_duplicates(fs, &references, &mut problems);
+    check_entries(fs, &inodes, &mut problems);
+    let inodes = reconnect_orphans(fs, inodes, &mut problems);
+    check_link_counts(fs, &inodes, &mut problems);
+    problems
+}
+
+fn in_data_area<D: BlockDevice>(fs: &Filesystem<D>, block: BlockId) -> bool {
+    (fs.sb.data_start()..fs.sb.block_count).contains(&block)
+}
+
+// pass 1
+fn scan_inodes<D: BlockDevice>(
+    fs: &mut Filesystem<D>,
+    problems: &mut Vec<Problem>,
+) -> (BTreeSet<InodeId>, BTreeMap<BlockId, Vec<Reference>>) {
+    let mut inodes = BTreeSet::new();
+    let mut references: BTreeMap<BlockId, Vec<Reference>> = BTreeMap::new();
+    for inode in 1..fs.sb.inode_count {
+        let Some(mut content) = fs.read_inode(inode) else {
+            continue;
+        };
+        inodes.insert(inode);
+        let mut cleared = false;
+        for slot in 0..content.pointers.len() {
+            let block = content.pointers[slot];
+            if block == 0 {
+                continue;
+            }
+            if !in_data_area(fs, block) {
+                problems.push(Problem::BadPointer { inode, block });
+                content.pointers[slot] = 0;
+                cleared = true;
+                continue;
+            }
+            let level = fs.sb.slot_level(slot);
+            if add_reference(
+                &mut references,
+                block,
+                inode,
+                PointerAt::Inode(inode, slot),
+                level,
+            ) {
+                scan_indirect(fs, inode, block, level, &mut references, problems);
+            }
+        }
+        if cleared {
+            fs.write_inode(inode, Some(&content));
+        }
+    }
+    (inodes, references)
+}
+
+// true for the first reference to `block`, only then the blocks below it get scanned. so a cycle
+// of indirect blocks ends, and a shared indirect block counts its children once
+fn add_reference(
+    references: &mut BTreeMap<BlockId, Vec<Reference>>,
+    block: BlockId,
+    inode: InodeId,
+    at: PointerAt,
+    level: usize,
+) -> bool {
+    let refs = references.entry(block).or_default();
+    refs.push(Reference { inode, at, level });
+    refs.len() == 1
+}
+
+fn scan_indirect<D: BlockDevice>(
+    fs: &mut Filesystem<D>,
+    inode: InodeId,
+    block: BlockId,
+    level: usize,
+    references: &mut BTreeMap<BlockId, Vec<Reference>>,
+    problems: &mut Vec<Problem>,
+) {
+    if level == 0 {
+        return;
+    }
+    let ps = fs.sb.layout.pointer_size;
+    let mut pointers = fs.read_block(block);
+    let mut cleared = false;
+    for i in 0..fs.sb.layout.pointers_per_block() {
+        let child = get_uint(&pointers, i * ps, ps);
+        if child == 0 {
+            continue;
+        }
+        if !in_data_area(fs, child) {
+            problems.push(Problem::BadPointer {
+                inode,
+                block: child,
+            });
+            put_uint(&mut pointers, i * ps, ps, 0);
+            cleared = true;
+            continue;
+        }
+        let at = PointerAt::Indirect(block, i);
+        if add_reference(references, child, inode, at, level - 1) {
+            scan_indirect(fs, inode, child, level - 1, references, problems);
+        }
+    }
+    if cleared {
+        fs.write_block(block, &pointers);
+    }
+}
+
+// one read per bitmap block
+fn read_bitmap<D: BlockDevice>(fs: &mut Filesystem<D>, start: BlockId, count: usize) -> Vec<bool> {
+    let bits = fs.sb.layout.block_size * 8;
+    (0..count.div_ceil(bits))
+        .flat_map(|b| {
+            let block = fs.read_block(start + b);
+            (0..bits.min(count - b * bits))
+                .map(|i| block[i / 8] & (1 << (i % 8)) != 0)
+                .collect_vec()
+        })
+        .collect()
+}
+
+// pass 2
+fn check_bitmaps<D: BlockDevice>(
+    fs: &mut Filesystem<D>,
+    inodes: &BTreeSet<InodeId>,
+    references: &BTreeMap<BlockId, Vec<Reference>>,
+    problems: &mut Vec<Problem>,
+) {
+    let sb = fs.sb;
+    let marked = read_bitmap(fs, sb.block_bitmap(), sb.block_count);
+    for (block, marked) in marked.into_iter().enumerate() {
+        let used = block < sb.data_start() || references.contains_key(&block);
+        if used != marked {
+            problems.push(match used {
+                true => Problem::BlockMarkedFree(block),
+                false => Problem::LostBlock(block),
+            });
+            fs.set_bit(sb.block_bitmap(), block, used);
+        }
+    }
+    let marked = read_bitmap(fs, sb.inode_bitmap(), sb.inode_count);
+    for (inode, marked) in marked.into_iter().enumerate() {
+        let used = inode == 0 || inodes.contains(&inode);
+        if used != marked {
+            problems.push(match used {
+                true => Problem::InodeMarkedFree(inode),
+                false => Problem::LostInode(inode),
+            });
+            fs.set_bit(sb.inode_bitmap(), inode, used);
+        }
+    }
+}
+
+fn set_pointer<D: BlockDevice>(fs: &mut Filesystem<D>, at: PointerAt, block: BlockId) {
+    match at {
+        PointerAt::Inode(inode, slot) => {
+            let mut content = fs.inode(inode);
+            content.pointers[slot] = block;
+            fs.write_inode(inode, Some(&content));
+        }
+        PointerAt::Indirect(indirect, i) => {
+            let ps = fs.sb.layout.pointer_size;
+            let mut pointers = fs.read_block(indirect);
+            put_uint(&mut pointers, i * ps, ps, block);
+            fs.write_block(indirect, &pointers);
+        }
+    }
+}
+
+// `block` and everything below it, for an indirect block that has to be a copy of its own.
+// `copies` gets every block allocated for it, to give them back if the disk runs full halfway
+fn copy_tree<D: BlockDevice>(
+    fs: &mut Filesystem<D>,
+    block: BlockId,
+    level: usize,
+    copies: &mut Vec<BlockId>,
+) -> Result<BlockId, FsError> {
+    let copy = fs.allocate_block()?;
+    copies.push(copy);
+    let mut content = fs.read_block(block);
+    if level > 0 {
+        let ps = fs.sb.layout.pointer_size;
+        for i in 0..fs.sb.layout.pointers_per_block() {
+            let child = get_uint(&content, i * ps, ps);
+            if child != 0 {
+                let child_copy = copy_tree(fs, child, level - 1, copies)?;
+                put_uint(&mut content, i * ps, ps, child_copy);
+            }
+        }
+    }
+    fs.write_block(copy, &content);
+    Ok(copy)
+}
+
+// pass 3
+fn copy_duplicates<D: BlockDevice>(
+    fs: &mut Filesystem<D>,
+    references: &BTreeMap<BlockId, Vec<Reference>>,
+    problems: &mut Vec<Problem>,
+) {
+    for (block, refs) in references {
+        if refs.len() < 2 {
+            continue;
+        }
+        let problem = Problem::DuplicateBlock {
+            block: *block,
+            inodes: refs.iter().map(|r| r.inode).collect(),
+        };
+        let copied = refs[1..].iter().try_for_each(|r| {
+            let mut copies = Vec::new();
+            match copy_tree(fs, *block, r.level, &mut copies) {
+                Ok(copy) => {
+                    set_pointer(fs, r.at, copy);
+                    Ok(())
+                }
+                Err(error) => {
+                    let bitmap = fs.sb.block_bitmap();
+                    copies
+                        .into_iter()
+                        .for_each(|copy| fs.set_bit(bitmap, copy, false));
+                    Err(error)
+                }
+            }
+        });
+        problems.push(match copied {
+            Ok(()) => problem,
+            Err(error) => Problem::Unrepairable {
+                problem: Box::new(problem),
+                error,
+            },
+        });
+    }
+}
+
+fn directories<D: BlockDevice>(fs: &mut Filesystem<D>, inodes: &BTreeSet<InodeId>) -> Vec<InodeId> {
+    inodes
+        .iter()
+        .copied()
+        .filter(|inode| fs.inode(*inode).kind == FileKind::Directory)
+        .collect()
+}
+
+// pass 4
+fn check_entries<D: BlockDevice>(
+    fs: &mut Filesystem<D>,
+    inodes: &BTreeSet<InodeId>,
+    problems: &mut Vec<Problem>,
+) {
+    for dir in directories(fs, inodes) {
+        for (slot, name, inode) in fs.entries(dir).expect("a directory") {
+            if !inodes.contains(&inode) {
+                problems.push(Problem::DanglingEntry { dir, name, inode });
+                fs.remove_entry(dir, slot);
+            } else if name == "." && inode != dir {
+                problems.push(Problem::WrongDot {
+                    dir,
+                    points_to: inode,
+                });
+                fs.set_entry(dir, slot, ".", dir)
+                    .expect("slot exists, nothing to allocate");
+            }
+        }
+    }
+}
+
+// the directory the first entry found for each reachable inode is in, the root is its own parent
+fn parents<D: BlockDevice>(fs: &mut Filesystem<D>) -> HashMap<InodeId, InodeId> {
+    let mut parents = HashMap::from([(ROOT, ROOT)]);
+    let mut pending = VecDeque::from([ROOT]);
+    while let Some(dir) = pending.pop_front() {
+        // only the root can be something else, then nothing is reachable
+        let Ok(entries) = fs.entries(dir) else {
+            continue;
+        };
+        for (_, name, inode) in entries {
+            if name == "." || name == ".." || parents.contains_key(&inode) {
+                continue;
+            }
+            parents.insert(inode, dir);
+            if fs.inode(inode).kind == FileKind::Directory {
+                pending.push_back(inode);
+            }
+        }
+    }
+    parents
+}
+
+fn lost_and_found<D: BlockDevice>(
+    fs: &mut Filesystem<D>,
+    inodes: &mut BTreeSet<InodeId>,
+) -> Result<InodeId, FsError> {
+    match fs.find_entry(ROOT, "lost+found")? {
+        Some((_, dir)) if fs.inode(dir).kind == FileKind::Directory => Ok(dir),
+        _ => {
+            let dir = fs.mkdir(ROOT, "/lost+found")?;
+            inodes.insert(dir);
+            Ok(dir)
+        }
+    }
+}
+
+fn is_empty<D: BlockDevice>(fs: &mut Filesystem<D>, inode: InodeId, content: &Inode) -> bool {
+    match content.kind {
+        FileKind::File | FileKind::Symlink => content.size == 0,
+        FileKind::Directory => fs
+            .entries(inode)
+            .expect("a directory")
+            .iter()
+            .all(|(_, name, _)| name == "." || name == ".."),
+    }
+}
+
+// pass 5. returns the inodes still in use
+fn reconnect_orphans<D: BlockDevice>(
+    fs: &mut Filesystem<D>,
+    mut inodes: BTreeSet<InodeId>,
+    problems: &mut Vec<Problem>,
+) -> BTreeSet<InodeId> {
+    loop {
+        let parents = parents(fs);
+        let orphans = inodes
+            .iter()
+            .copied()
+            .filter(|inode| !parents.contains_key(inode))
+            .collect_vec();
+        if orphans.is_empty() {
+            break;
+        }
+        // an orphaned directory takes what's in it along. only the top of an orphaned subtree goes
+        // to /lost+found, or the smallest one if they form a cycle
+        let mut below_orphans = BTreeSet::new();
+        for orphan in &orphans {
+            if fs.inode(*orphan).kind == FileKind::Directory {
+                for (_, name, inode) in fs.entries(*orphan).expect("a directory") {
+                    if name != "." && name != ".." {
+                        below_orphans.insert(inode);
+                    }
+                }
+            }
+        }
+        let top = orphans
+            .iter()
+            .copied()
+            .find(|inode| !below_orphans.contains(inode))
+            .unwrap_or(orphans[0]);
+
+        let content = fs.inode(top);
+        let reconnected = !is_empty(fs, top, &content);
+        let problem = Problem::Orphan {
+            inode: top,
+            kind: content.kind,
+            reconnected,
+        };
+        if !reconnected {
+            fs.free_inode(top, &content);
+            inodes.remove(&top);
+            problems.push(problem);
+            continue;
+        }
+        let added = lost_and_found(fs, &mut inodes)
+            .and_then(|dir| fs.add_entry(dir, &format!("#{}", top), top));
+        if let Err(error) = added {
+            // the others wouldn't fit either, they stay where they are
+            problems.push(Problem::Unrepairable {
+                problem: Box::new(problem),
+                error,
+            });
+            break;
+        }
+        problems.push(problem);
+    }
+
+    let parents = parents(fs);
+    for dir in directories(fs, &inodes) {
+        // still orphaned, nothing to compare with
+        let Some(&parent) = parents.get(&dir) else {
+            continue;
+        };
+        let dotdot = fs.find_entry(dir, "..").expect("a directory");
+        if !matches!(dotdot, Some((_, inode)) if inode == parent) {
+            let problem = Problem::WrongDotDot {
+                dir,
+                points_to: dotdot.map_or(0, |(_, inode)| inode),
+                parent,
+            };
+            let fixed = match dotdot {
+                Some((slot, _)) => fs.set_entry(dir, slot, "..", parent),
+                None => fs.add_entry(dir, "..", parent),
+            };
+            problems.push(match fixed {
+                Ok(()) => problem,
+                Err(error) => Problem::Unrepairable {
+                    problem: Box::new(problem),
+                    error,
+                },
+            });
+        }
+    }
+    inodes
+}
+
+// pass 6. "." and ".." count like every other entry
+fn check_link_counts<D: BlockDevice>(
+    fs: &mut Filesystem<D>,
+    inodes: &BTreeSet<InodeId>,
+    problems: &mut Vec<Problem>,
+) {
+    let mut counts: HashMap<InodeId, usize> = HashMap::new();
+    for dir in directories(fs, inodes) {
+        for (_, _, inode) in fs.entries(dir).expect("a directory") {
+            *counts.entry(inode).or_default() += 1;
+        }
+    }
+    for inode in inodes {
+        // none at all only for an orphan /lost+found had no room for, zero would free it
+        let Some(&actual) = counts.get(inode) else {
+            continue;
+        };
+        let mut content = fs.inode(*inode);
+        if content.links != actual {
+            problems.push(Problem::WrongLinkCount {
+                inode: *inode,
+                stored: content.links,
+                actual,
+            });
+            content.links = actual;
+            fs.write_inode(*inode, Some(&content));
+        }
+    }
+}
+
+type CrashOperation = dyn Fn(&mut Filesystem<CrashDisk<MemoryDisk>>);
+
+fn print_report(problems: &[Problem]) {
+    if problems.is_empty() {
+        println!("clean");
+    }
+    for problem in problems {
+        println!("{}", problem);
+    }
+}
+
+pub fn test_fsck() {
+    println!("\n## FSCK\n");
+
+    let layout = InodeLayout {
+        block_size: 512,
+        pointer_size: 4,
+        direct: 4,
+        indirect: [1, 0, 0],
+    };
+    let mut fs = Filesystem::format(MemoryDisk::new(512, 256), layout, 32);
+    fs.mkdir(ROOT, "/docs").unwrap();
+    fs.create(ROOT, "/docs/a").unwrap();
+    fs.write(ROOT, "/docs/a", 0, &[b'a'; 3000]).unwrap();
+    fs.create(ROOT, "/docs/b").unwrap();
+    fs.write(ROOT, "/docs/b", 0, b"bbb").unwrap();
+    fs.create(ROOT, "/c").unwrap();
+    fs.write(ROOT, "/c", 0, b"ccc").unwrap();
+    let orphan = fs.create(ROOT, "/orphan").unwrap();
+    fs.write(ROOT, "/orphan", 0, b"still here").unwrap();
+    let empty = fs.create(ROOT, "/empty").unwrap();
+    let gone = fs.create(ROOT, "/gone").unwrap();
+    fs.write(ROOT, "/gone", 0, b"gone").unwrap();
+    assert_eq!(fsck(&mut fs), []);
+    let free_blocks = fs.free_block_count();
+    let free_inodes = fs.free_inode_count();
+
+    println!("\n### Hand-made damage\n");
+    let sb = fs.sb;
+    let a = fs.resolve(ROOT, "/docs/a").unwrap();
+    let b = fs.resolve(ROOT, "/docs/b").unwrap();
+    let c = fs.resolve(ROOT, "/c").unwrap();
+    let mut a_content = fs.inode(a);
+    let mut b_content = fs.inode(b);
+    let mut c_content = fs.inode(c);
+    // a block of /docs/a free in the bitmap, a block nobody uses taken
+    fs.set_bit(sb.block_bitmap(), a_content.pointers[1], false);
+    fs.set_bit(sb.block_bitmap(), sb.block_count - 1, true);
+    // /c shares its block with /docs/b, its own one is lost
+    let c_block = c_content.pointers[0];
+    c_content.pointers[0] = b_content.pointers[0];
+    fs.write_inode(c, Some(&c_content));
+    // garbage in a pointer of the single indirect block of /docs/a, and one into the inode table
+    let indirect = a_content.pointers[4];
+    let mut pointers = fs.read_block(indirect);
+    put_uint(&mut pointers, 4, 4, 100_000);
+    fs.write_block(indirect, &pointers);
+    a_content.pointers[2] = 3;
+    fs.write_inode(a, Some(&a_content));
+    b_content.links = 3;
+    fs.write_inode(b, Some(&b_content));
+    // the entries of /orphan and /empty gone, but not their inodes
+    for name in ["orphan", "empty"] {
+        let (slot, _) = fs.find_entry(ROOT, name).unwrap().unwrap();
+        fs.remove_entry(ROOT, slot);
+    }
+    // the inode of /gone freed, but not its entry, its bitmap bit and its block
+    fs.write_inode(gone, None);
+
+    let problems = fsck(&mut fs);
+    print_report(&problems);
+    for expected in [
+        Problem::BadPointer { inode: a, block: 3 },
+        Problem::BadPointer {
+            inode: a,
+            block: 100_000,
+        },
+        Problem::BlockMarkedFree(a_content.pointers[1]),
+        Problem::LostBlock(sb.block_count - 1),
+        Problem::LostBlock(c_block),
+        Problem::LostInode(gone),
+        Problem::DuplicateBlock {
+            block: b_content.pointers[0],
+            inodes: vec![b, c],
+        },
+        Problem::DanglingEntry {
+            dir: ROOT,
+            name: "gone".to_owned(),
+            inode: gone,
+        },
+        Problem::Orphan {
+            inode: orphan,
+            kind: FileKind::File,
+            reconnected: true,
+        },
+        Problem::Orphan {
+            inode: empty,
+            kind: FileKind::File,
+            reconnected: false,
+        },
+        Problem::WrongLinkCount {
+            inode: b,
+            stored: 3,
+            actual: 1,
+        },
+    ] {
+        assert!(problems.contains(&expected), "missed: {}", expected);
+    }
+
+    println!("\n### Second run\n");
+    print_report(&fsck(&mut fs));
+    assert_eq!(fsck(&mut fs), []);
+    // the blocks behind the bad pointers, the own block of /c and the block of /gone are free
+    // again, /lost+found and the copy of the shared block are new. /gone and /empty gave back their
+    // inodes
+    assert_eq!(fs.free_block_count(), free_blocks + 4 - 2);
+    assert_eq!(fs.free_inode_count(), free_inodes + 2 - 1);
+    assert_eq!(fs.read(ROOT, "/c", 0, 10), Ok(b"bbb".to_vec()));
+    assert_eq!(fs.read(ROOT, "/docs/b", 0, 10), Ok(b"bbb".to_vec()));
+    assert_eq!(
+        fs.read(ROOT, &format!("/lost+found/#{}", orphan), 0, 100),
+        Ok(b"still here".to_vec())
+    );
+    assert_eq!(fs.stat(ROOT, "/docs/a").unwrap().size, 3000);
+    let a_data = fs.read(ROOT, "/docs/a", 0, 3000).unwrap();
+    let holes = a_data.chunks(512).filter(|block| block[0] == 0).count();
+    println!("/docs/a lost {} of its 6 blocks to the bad pointers", holes);
+    assert_eq!(holes, 2);
+
+    println!("\n### Full disk\n");
+    let mut fs = Filesystem::format(MemoryDisk::new(512, 64), layout, 32);
+    let a = fs.create(ROOT, "/a").unwrap();
+    fs.write(ROOT, "/a", 0, b"aaa").unwrap();
+    let b = fs.create(ROOT, "/b").unwrap();
+    let orphan = fs.create(ROOT, "/orphan").unwrap();
+    fs.write(ROOT, "/orphan", 0, b"still here").unwrap();
+    fs.create(ROOT, "/fill").unwrap();
+    assert_eq!(
+        fs.write(ROOT, "/fill", 0, &[0; 64 * 512]),
+        Err(FsError::NoSpace)
+    );
+    // /b shares the block of /a, /orphan lost its entry. no room for a copy or /lost+found
+    let mut b_content = fs.inode(b);
+    b_content.pointers[0] = fs.inode(a).pointers[0];
+    b_content.size = 3;
+    fs.write_inode(b, Some(&b_content));
+    let (slot, _) = fs.find_entry(ROOT, "orphan").unwrap().unwrap();
+    fs.remove_entry(ROOT, slot);
+    let problems = fsck(&mut fs);
+    print_report(&problems);
+    assert_eq!(
+        problems
+            .iter()
+            .filter(|p| matches!(p, Problem::Unrepairable { .. }))
+            .count(),
+        2
+    );
+    assert!(problems.contains(&Problem::Unrepairable {
+        problem: Box::new(Problem::Orphan {
+            inode: orphan,
+            kind: FileKind::File,
+            reconnected: true,
+        }),
+        error: FsError::NoSpace,
+    }));
+    // the damage stays, and so does every block
+    assert_eq!(fsck(&mut fs), problems);
+    assert_eq!(fs.free_block_count(), 0);
+
+    println!("\n### After crashes, no journal\n");
+    let mut fs = Filesystem::format(MemoryDisk::new(512, 256), layout, 32);
+    fs.mkdir(ROOT, "/d").unwrap();
+    fs.mkdir(ROOT, "/e").unwrap();
+    fs.create(ROOT, "/d/big").unwrap();
+    fs.write(ROOT, "/d/big", 0, &[b'x'; 4000]).unwrap();
+    let image = fs.into_device();
+    // every operation on its own, crashing after each of its writes
+    let operations: [(&str, &CrashOperation); 3] = [
+        ("mkdir /d/sub", &|fs| {
+            fs.mkdir(ROOT, "/d/sub").unwrap();
+        }),
+        ("rename /d -> /e/d", &|fs| {
+            fs.rename(ROOT, "/d", "/e/d").unwrap()
+        }),
+        ("unlink /d/big", &|fs| fs.unlink(ROOT, "/d/big").unwrap()),
+    ];
+    for (what, op) in operations {
+        let images = crash_images(&image, op);
+        let writes = images.len() - 1;
+        let mut repaired = 0;
+        for (crash_after, image) in images.into_iter().enumerate() {
+            let mut fs = Filesystem::mount(image);
+            let problems = fsck(&mut fs);
+            if !problems.is_empty() {
+                repaired += 1;
+                println!(
+                    "{}, crash after {} of {} writes:",
+                    what, crash_after, writes
+                );
+                for problem in &problems {
+                    println!("    {}", problem);
+                }
+            }
+            assert_eq!(fsck(&mut fs), [], "{}, after {} writes", what, crash_after);
+            assert!(fs.read_dir(ROOT, "/").is_ok());
+        }
+        assert!(repaired > 0);
+    }
+}
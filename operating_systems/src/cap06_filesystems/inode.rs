@@ -0,0 +1,274 @@
+use std::fmt;
+
+use itertools::Itertools;
+
+// the pointer part of a unix inode. everything the usual exercises give: block size, pointer size
+// and how many pointers of each kind the inode has
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InodeLayout {
+    pub block_size: usize,
+    pub pointer_size: usize,
+    pub direct: usize,
+    // number of single, double and triple indirect pointers
+    pub indirect: [usize; 3],
+}
+
+// which pointer in the inode a block hangs off
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InodePointer {
+    Direct(usize),
+    // level 1 == single indirect, `index` counts the pointers of that level
+    Indirect { level: usize, index: usize },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockPath {
+    pub pointer: InodePointer,
+    // entry in every indirect block on the way down, the outermost first. empty for direct blocks
+    pub indices: Vec<usize>,
+}
+
+// what reading a byte at some offset of a file costs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockChain {
+    pub offset: usize,
+    pub file_block: usize,
+    pub offset_in_block: usize,
+    pub path: BlockPath,
+}
+
+impl BlockChain {
+    // the inode itself, the indirect blocks and the data block, nothing cached
+    pub fn block_reads(&self) -> usize {
+        2 + self.path.indices.len()
+    }
+}
+
+impl fmt::Display for BlockChain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "byte {}: inode", self.offset)?;
+        match self.path.pointer {
+            InodePointer::Direct(i) => write!(f, " -> direct pointer {}", i)?,
+            InodePointer::Indirect { level, index } => {
+                write!(f, " -> {} indirect pointer {}", level_name(level), index)?;
+                for (i, entry) in self.path.indices.iter().enumerate() {
+                    write!(
+                        f,
+                        " -> entry {} of the {} indirect block",
+                        entry,
+                        level_name(level - i)
+                    )?;
+                }
+            }
+        }
+        write!(
+            f,
+            " -> byte {} of file block {}, {} block reads",
+            self.offset_in_block,
+            self.file_block,
+            self.block_reads()
+        )
+    }
+}
+
+fn level_name(level: usize) -> &'static str {
+    ["single", "double", "triple"][level - 1]
+}
+
+impl InodeLayout {
+    // ext2/3: 12 direct pointers, one of each indirect kind, 32 bit block numbers
+    pub fn ext2(block_size: usize) -> Self {
+        InodeLayout {
+            block_size,
+            pointer_size: 4,
+            direct: 12,
+            indirect: [1, 1, 1],
+        }
+    }
+
+    pub fn pointers_per_block(&self) -> usize {
+        self.block_size / self.pointer_size
+    }
+
+    // data blocks one pointer of that level reaches, level 0 is a direct pointer
+    fn blocks_per_pointer(&self, level: usize) -> usize {
+        self.pointers_per_block()
+            .checked_pow(level as u32)
+            .expect("file system too big to compute")
+    }
+
+    pub fn max_blocks(&self) -> usize {
+        (1..=3).fold(self.direct, |sum, level| {
+            sum + self.indirect[level - 1] * self.blocks_per_pointer(level)
+        })
+    }
+
+    pub fn max_file_size(&self) -> usize {
+        self.max_blocks()
+            .checked_mul(self.block_size)
+            .expect("file system too big to compute")
+    }
+
+    // where the pointer to the n-th block of a file is. None beyond the max file size
+    pub fn locate(&self, file_block: usize) -> Option<BlockPath> {
+        if file_block < self.direct {
+            return Some(BlockPath {
+                pointer: InodePointer::Direct(file_block),
+                indices: Vec::new(),
+            });
+        }
+        let mut rest = file_block - self.direct;
+        for level in 1..=3 {
+            let per_pointer = self.blocks_per_pointer(level);
+            let reachable = self.indirect[level - 1] * per_pointer;
+            if rest >= reachable {
+                rest -= reachable;
+                continue;
+            }
+            // digits of `rest` in base pointers_per_block
+            let indices = (0..level)
+                .rev()
+                .map(|below| {
+                    rest % self.blocks_per_pointer(below + 1) / self.blocks_per_pointer(below)
+                })
+                .collect_vec();
+            return Some(BlockPath {
+                pointer: InodePointer::Indirect {
+                    level,
+                    index: rest / per_pointer,
+                },
+                indices,
+            });
+        }
+        None
+    }
+
+    pub fn block_chain(&self, offset: usize) -> Option<BlockChain> {
+        let file_block = offset / self.block_size;
+        Some(BlockChain {
+            offset,
+            file_block,
+            offset_in_block: offset % self.block_size,
+            path: self.locate(file_block)?,
+        })
+    }
+
+    // indirect blocks a file of `file_size` bytes needs besides its data blocks
+    pub fn metadata_blocks(&self, file_size: usize) -> usize {
+        assert!(
+            file_size <= self.max_file_size(),
+            "{} bytes are more than an inode can hold",
+            file_size
+        );
+        let mut rest = file_size
+            .div_ceil(self.block_size)
+            .saturating_sub(self.direct);
+        let mut blocks = 0;
+        for level in 1..=3 {
+            for _ in 0..self.indirect[level - 1] {
+                let leaves = rest.min(self.blocks_per_pointer(level));
+                rest -= leaves;
+                // one level of the tree after the other, the top one is a single block
+                blocks += (1..=level)
+                    .map(|k| leaves.div_ceil(self.blocks_per_pointer(k)))
+                    .sum::<usize>();
+            }
+        }
+        blocks
+    }
+}
+
+fn print_layout(layout: &InodeLayout) {
+    println!(
+        "{}B blocks, {}B pointers ({} per block), {} direct, {:?} single/double/triple indirect",
+        layout.block_size,
+        layout.pointer_size,
+        layout.pointers_per_block(),
+        layout.direct,
+        layout.indirect
+    );
+    println!(
+        "max file size: {} blocks, {}B = {:.2}GiB",
+        layout.max_blocks(),
+        layout.max_file_size(),
+        layout.max_file_size() as f64 / (1 << 30) as f64
+    );
+}
+
+pub fn test_inode() {
+    println!("\n## INODES\n");
+
+    println!("\n### ext2, 4 KiB blocks\n");
+    let ext2 = InodeLayout::ext2(4096);
+    print_layout(&ext2);
+    assert_eq!(
+        ext2.max_blocks(),
+        12 + 1024 + 1024 * 1024 + 1024 * 1024 * 1024
+    );
+    assert_eq!(ext2.max_file_size(), 4_402_345_721_856);
+
+    for size in [0_usize, 48 * 1024, 48 * 1024 + 1, 5 * 1024 * 1024, 1 << 30] {
+        println!(
+            "a file of {}B needs {} data and {} indirect blocks",
+            size,
+            size.div_ceil(ext2.block_size),
+            ext2.metadata_blocks(size)
+        );
+    }
+    assert_eq!(ext2.metadata_blocks(48 * 1024), 0);
+    assert_eq!(ext2.metadata_blocks(48 * 1024 + 1), 1);
+    // 12 direct, 1024 over the single indirect block, 244 over the double indirect block and one
+    // block below it
+    assert_eq!(ext2.metadata_blocks(5 * 1024 * 1024), 3);
+    // 262144 blocks: 1 single indirect block, the double indirect one and 255 below it
+    assert_eq!(ext2.metadata_blocks(1 << 30), 257);
+
+    let offsets = [
+        0,
+        12 * 4096 - 1,
+        12 * 4096,
+        5_000_000,
+        (12 + 1024 + 1024 * 1024) * 4096,
+        ext2.max_file_size() - 1,
+    ];
+    for offset in offsets {
+        println!("{}", ext2.block_chain(offset).unwrap());
+    }
+    let chain = ext2.block_chain(5_000_000).unwrap();
+    assert_eq!((chain.file_block, chain.offset_in_block), (1220, 2880));
+    assert_eq!(
+        chain.path,
+        BlockPath {
+            pointer: InodePointer::Indirect { level: 2, index: 0 },
+            indices: vec![0, 184]
+        }
+    );
+    assert_eq!(chain.block_reads(), 4);
+    assert_eq!(
+        ext2.block_chain(ext2.max_file_size() - 1)
+            .unwrap()
+            .path
+            .indices,
+        [1023, 1023, 1023]
+    );
+    assert_eq!(ext2.block_chain(ext2.max_file_size()), None);
+
+    println!("\n### 1 KiB blocks, 10 direct, two single indirect pointers\n");
+    let layout = InodeLayout {
+        block_size: 1024,
+        pointer_size: 4,
+        direct: 10,
+        indirect: [2, 1, 0],
+    };
+    print_layout(&layout);
+    assert_eq!(layout.max_blocks(), 10 + 2 * 256 + 256 * 256);
+    println!("{}", layout.block_chain(300 * 1024).unwrap());
+    assert_eq!(
+        layout.block_chain(300 * 1024).unwrap().path,
+        BlockPath {
+            pointer: InodePointer::Indirect { level: 1, index: 1 },
+            indices: vec![34]
+        }
+    );
+    assert_eq!(layout.block_chain(layout.max_file_size()), None);
+}
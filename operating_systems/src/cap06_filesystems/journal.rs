@@ -0,0 +1,562 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use itertools::Itertools;
+
+use super::{
+    block_device::{BlockDevice, BlockId, IoStats, MemoryDisk},
+    filesystem::{
+        decode_dirent, get_uint, put_uint, FileKind, Filesystem, InodeId, DIRENT_SIZE, ROOT,
+    },
+    inode::InodeLayout,
+};
+
+// a write-ahead journal like jbd in ext3, one transaction per operation, no revoke records.
+// a transaction is written as
+//   descriptor (sequence number, home block of every logged block) | logged blocks | commit block
+// into the journal area. once the commit block is on disk, the transaction counts: the blocks
+// get written to their home locations (checkpoint) and the descriptor gets cleared. a crash
+// before the commit loses the operation, a crash after it gets fixed by replaying at mount time.
+// block writes are atomic and happen in the order they are issued, a real disk needs barriers
+// (or checksums in the commit block) for that
+
+const DESCRIPTOR_MAGIC: usize = 0x6a_62_64_01;
+const COMMIT_MAGIC: usize = 0x6a_62_64_02;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JournalMode {
+    // only metadata is logged, file data goes to its home location after the commit.
+    // ext3's data=writeback
+    Writeback,
+    // only metadata is logged, but file data goes to its home location before the commit.
+    // ext3's default, data=ordered
+    Ordered,
+    // everything is logged, file data as well. data=journal
+    Data,
+}
+
+impl JournalMode {
+    pub(super) fn encode(mode: Option<JournalMode>) -> usize {
+        match mode {
+            None => 0,
+            Some(JournalMode::Writeback) => 1,
+            Some(JournalMode::Ordered) => 2,
+            Some(JournalMode::Data) => 3,
+        }
+    }
+
+    pub(super) fn decode(code: usize) -> Option<JournalMode> {
+        match code {
+            1 => Some(JournalMode::Writeback),
+            2 => Some(JournalMode::Ordered),
+            3 => Some(JournalMode::Data),
+            _ => None,
+        }
+    }
+}
+
+fn is_logged(mode: JournalMode, is_file_data: bool) -> bool {
+    !is_file_data || mode == JournalMode::Data
+}
+
+// the most blocks a write of a single byte changes: its data block, a fresh indirect block on
+// every level and the bitmap block of each, and the inode. every transaction needs room for that
+pub(super) fn smallest_write(layout: &InodeLayout) -> usize {
+    let levels = layout
+        .indirect
+        .iter()
+        .rposition(|n| *n > 0)
+        .map_or(0, |i| i + 1);
+    (1 + levels) * 2 + 1
+}
+
+// the blocks an operation changed, not written anywhere yet
+#[derive(Debug, Default)]
+pub(super) struct Transaction {
+    // in the order they were changed first, and whether they hold file data
+    blocks: Vec<(BlockId, Vec<u8>, bool)>,
+    index: HashMap<BlockId, usize>,
+}
+
+impl Transaction {
+    pub(super) fn get(&self, block: BlockId) -> Option<&[u8]> {
+        self.index.get(&block).map(|i| self.blocks[*i].1.as_slice())
+    }
+
+    pub(super) fn put(
+        &mut self,
+        block: BlockId,
+        data: &[u8],
+        block_size: usize,
+        is_file_data: bool,
+    ) {
+        let mut content = data.to_vec();
+        content.resize(block_size, 0);
+        match self.index.get(&block) {
+            Some(i) => self.blocks[*i] = (block, content, is_file_data),
+            None => {
+                self.index.insert(block, self.blocks.len());
+                self.blocks.push((block, content, is_file_data));
+            }
+        }
+    }
+}
+
+impl<D: BlockDevice> Filesystem<D> {
+    // runs `op` as one transaction. without a journal, every write goes to the device right away
+    pub(super) fn transaction<T>(&mut self, op: impl FnOnce(&mut Self) -> T) -> T {
+        if self.sb.journal_mode.is_none() || self.txn.is_some() {
+            return op(self);
+        }
+        self.txn = Some(Transaction::default());
+        let result = op(self);
+        let txn = self.txn.take().expect("started above");
+        self.commit(txn);
+        result
+    }
+
+    // `write(fs, offset, data)` for all of `data`, in as many transactions as it takes to fit
+    // each one into the journal. like in ext3 a big write isn't atomic, but every part of it is.
+    // a part that doesn't fit gets dropped before any of it reached the disk, and half of it is
+    // tried instead
+    pub(super) fn write_in_parts<E>(
+        &mut self,
+        offset: usize,
+        data: &[u8],
+        mut write: impl FnMut(&mut Self, usize, &[u8]) -> Result<(), E>,
+    ) -> Result<(), E> {
+        if self.sb.journal_mode.is_none() || self.txn.is_some() {
+            return write(self, offset, data);
+        }
+        let (mut done, mut part) = (0, data.len());
+        loop {
+            let end = data.len().min(done + part);
+            self.txn = Some(Transaction::default());
+            let result = write(self, offset + done, &data[done..end]);
+            let txn = self.txn.take().expect("started above");
+            if self.logged_blocks(&txn) > self.sb.journal_capacity() {
+                assert!(part > 1, "format makes room for a one byte write");
+                part /= 2;
+                continue;
+            }
+            self.commit(txn);
+            result?;
+            done = end;
+            if done == data.len() {
+                return Ok(());
+            }
+        }
+    }
+
+    fn logged_blocks(&self, txn: &Transaction) -> usize {
+        let mode = self.sb.journal_mode.expect("only with a journal");
+        txn.blocks
+            .iter()
+            .filter(|(_, _, is_file_data)| is_logged(mode, *is_file_data))
+            .count()
+    }
+
+    fn commit(&mut self, txn: Transaction) {
+        if txn.blocks.is_empty() {
+            return;
+        }
+        let mode = self.sb.journal_mode.expect("only with a journal");
+        assert!(
+            self.logged_blocks(&txn) <= self.sb.journal_capacity(),
+            "transaction of {} blocks doesn't fit into the journal, only writes get split",
+            self.logged_blocks(&txn)
+        );
+        let (metadata, data): (Vec<_>, Vec<_>) = txn
+            .blocks
+            .into_iter()
+            .partition(|(_, _, is_file_data)| is_logged(mode, *is_file_data));
+
+        let start = self.sb.journal_start();
+        let block_size = self.sb.layout.block_size;
+
+        if mode == JournalMode::Ordered {
+            data.iter()
+                .for_each(|(block, content, _)| self.device_mut().write_block(*block, content));
+        }
+
+        let sequence = self.sequence;
+        self.sequence += 1;
+        let mut descriptor = vec![0; block_size];
+        put_uint(&mut descriptor, 0, 8, DESCRIPTOR_MAGIC);
+        put_uint(&mut descriptor, 8, 8, sequence);
+        put_uint(&mut descriptor, 16, 8, metadata.len());
+        for (i, (block, _, _)) in metadata.iter().enumerate() {
+            put_uint(&mut descriptor, 24 + 8 * i, 8, *block);
+        }
+        self.device_mut().write_block(start, &descriptor);
+        for (i, (_, content, _)) in metadata.iter().enumerate() {
+            self.device_mut().write_block(start + 1 + i, content);
+        }
+        let mut commit = vec![0; 16];
+        put_uint(&mut commit, 0, 8, COMMIT_MAGIC);
+        put_uint(&mut commit, 8, 8, sequence);
+        self.device_mut()
+            .write_block(start + 1 + metadata.len(), &commit);
+
+        if mode == JournalMode::Writeback {
+            data.iter()
+                .for_each(|(block, content, _)| self.device_mut().write_block(*block, content));
+        }
+
+        // checkpoint
+        for (block, content, _) in &metadata {
+            self.device_mut().write_block(*block, content);
+        }
+        self.clear_journal(sequence);
+    }
+
+    // keeps the sequence number, so a stale commit block can never match a new descriptor
+    fn clear_journal(&mut self, sequence: usize) {
+        let mut descriptor = vec![0; 16];
+        put_uint(&mut descriptor, 8, 8, sequence);
+        let start = self.sb.journal_start();
+        self.device_mut().write_block(start, &descriptor);
+    }
+
+    pub(super) fn recover(&mut self) {
+        if self.sb.journal_mode.is_none() {
+            return;
+        }
+        let start = self.sb.journal_start();
+        let descriptor = self.read_block(start);
+        let sequence = get_uint(&descriptor, 8, 8);
+        self.sequence = sequence + 1;
+        if get_uint(&descriptor, 0, 8) != DESCRIPTOR_MAGIC {
+            return;
+        }
+
+        let count = get_uint(&descriptor, 16, 8);
+        let commit = self.read_block(start + 1 + count);
+        if get_uint(&commit, 0, 8) == COMMIT_MAGIC && get_uint(&commit, 8, 8) == sequence {
+            for i in 0..count {
+                let home = get_uint(&descriptor, 24 + 8 * i, 8);
+                let content = self.read_block(start + 1 + i);
+                self.write_block(home, &content);
+            }
+            self.replayed += count;
+        }
+        // without a commit block, the transaction never happened
+        self.clear_journal(sequence);
+    }
+}
+
+// loses every write after the first `crash_after` ones, as if the power went out
+pub struct CrashDisk<D: BlockDevice> {
+    inner: D,
+    crash_after: Option<usize>,
+    // issued, lost ones included
+    writes: usize,
+}
+
+impl<D: BlockDevice> CrashDisk<D> {
+    pub fn new(inner: D, crash_after: Option<usize>) -> Self {
+        CrashDisk {
+            inner,
+            crash_after,
+            writes: 0,
+        }
+    }
+
+    pub fn writes(&self) -> usize {
+        self.writes
+    }
+
+    pub fn into_inner(self) -> D {
+        self.inner
+    }
+}
+
+impl<D: BlockDevice> BlockDevice for CrashDisk<D> {
+    fn block_size(&self) -> usize {
+        self.inner.block_size()
+    }
+
+    fn block_count(&self) -> usize {
+        self.inner.block_count()
+    }
+
+    fn read_block(&mut self, id: BlockId) -> Vec<u8> {
+        self.inner.read_block(id)
+    }
+
+    fn write_block(&mut self, id: BlockId, data: &[u8]) {
+        if !matches!(self.crash_after, Some(c) if self.writes >= c) {
+            self.inner.write_block(id, data);
+        }
+        self.writes += 1;
+    }
+
+    fn stats(&self) -> IoStats {
+        self.inner.stats()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Node {
+    File(Vec<u8>),
+    Directory,
+    Symlink(String),
+    // the entry points to a free inode
+    Dangling(InodeId),
+}
+
+// everything a user could see of the filesystem, and the bitmaps
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct FsState {
+    tree: BTreeMap<String, Node>,
+    free_blocks: usize,
+    free_inodes: usize,
+}
+
+// like `read_dir` and `read`, but without trusting the image
+fn state<D: BlockDevice>(fs: &mut Filesystem<D>) -> FsState {
+    let mut tree = BTreeMap::new();
+    let mut visited = HashSet::new();
+    let mut pending = vec![(String::new(), ROOT)];
+    while let Some((path, inode)) = pending.pop() {
+        let Some(mut content) = fs.read_inode(inode) else {
+            tree.insert(path, Node::Dangling(inode));
+            continue;
+        };
+        let data = fs.read_data(&mut content, 0, usize::MAX);
+        match content.kind {
+            FileKind::File => {
+                tree.insert(path, Node::File(data));
+            }
+            FileKind::Symlink => {
+                let target = String::from_utf8_lossy(&data).into_owned();
+                tree.insert(path, Node::Symlink(target));
+            }
+            FileKind::Directory => {
+                tree.insert(format!("{}/", path), Node::Directory);
+                if !visited.insert(inode) {
+                    continue;
+                }
+                for (name, child) in data.chunks(DIRENT_SIZE).filter_map(decode_dirent) {
+                    if name != "." && name != ".." {
+                        pending.push((format!("{}/{}", path, name), child));
+                    }
+                }
+            }
+        }
+    }
+    FsState {
+        tree,
+        free_blocks: fs.free_block_count(),
+        free_inodes: fs.free_inode_count(),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CrashOutcome {
+    // as if the operation never started
+    Old,
+    // as if it completed
+    New,
+    Inconsistent(String),
+}
+
+fn classify(recovered: &FsState, old: &FsState, new: &FsState) -> CrashOutcome {
+    if recovered == old {
+        return CrashOutcome::Old;
+    }
+    if recovered == new {
+        return CrashOutcome::New;
+    }
+    if recovered.tree == old.tree || recovered.tree == new.tree {
+        return CrashOutcome::Inconsistent(format!(
+            "bitmaps: {} free blocks and {} free inodes, {} and {} before, {} and {} after",
+            recovered.free_blocks,
+            recovered.free_inodes,
+            old.free_blocks,
+            old.free_inodes,
+            new.free_blocks,
+            new.free_inodes
+        ));
+    }
+    let path = recovered
+        .tree
+        .keys()
+        .chain(new.tree.keys())
+        .find(|p| {
+            recovered.tree.get(*p) != new.tree.get(*p) && recovered.tree.get(*p) != old.tree.get(*p)
+        })
+        .expect("the trees differ");
+    CrashOutcome::Inconsistent(match (recovered.tree.get(path), new.tree.get(path)) {
+        (Some(Node::File(got)), Some(Node::File(expected))) => {
+            let from = (0..got.len())
+                .find(|i| got.get(*i) != expected.get(*i))
+                .unwrap_or(got.len());
+            format!(
+                "{} holds {:?}... from byte {} on, data that was never written to it",
+                path,
+                String::from_utf8_lossy(&got[from..got.len().min(from + 12)]),
+                from
+            )
+        }
+        (Some(Node::Dangling(inode)), _) => format!("{} points to the free inode {}", path, inode),
+        (got, expected) => format!("{}: {:?} instead of {:?}", path, got, expected),
+    })
+}
+
+// runs `op` on a copy of `image` once to count its writes, then again for every prefix of those
+// writes. the images as the crash left them, before any mount replayed the journal
+pub fn crash_images(
+    image: &MemoryDisk,
+    op: &dyn Fn(&mut Filesystem<CrashDisk<MemoryDisk>>),
+) -> Vec<MemoryDisk> {
+    let mut fs = Filesystem::mount(CrashDisk::new(image.clone(), None));
+    op(&mut fs);
+    let writes = fs.device().writes();
+
+    (0..=writes)
+        .map(|crash_after| {
+            let mut fs = Filesystem::mount(CrashDisk::new(image.clone(), Some(crash_after)));
+            op(&mut fs);
+            fs.into_device().into_inner()
+        })
+        .collect_vec()
+}
+
+// every crash image mounted (which replays the journal) and compared with the state before and
+// after `op`
+pub fn crash_test(
+    image: &MemoryDisk,
+    op: &dyn Fn(&mut Filesystem<CrashDisk<MemoryDisk>>),
+) -> Vec<CrashOutcome> {
+    let old = state(&mut Filesystem::mount(image.clone()));
+    let mut images = crash_images(image, op);
+    let new = state(&mut Filesystem::mount(images.last().unwrap().clone()));
+
+    images
+        .drain(..)
+        .map(|crashed| classify(&state(&mut Filesystem::mount(crashed)), &old, &new))
+        .collect_vec()
+}
+
+pub fn test_journaling() {
+    println!("\n## JOURNALING\n");
+
+    let layout = InodeLayout {
+        block_size: 512,
+        pointer_size: 4,
+        direct: 8,
+        indirect: [1, 0, 0],
+    };
+    let secret = "SECRET-".repeat(200);
+    let hello = "hello-".repeat(200);
+    println!(
+        "the operation: write {} bytes to the empty /d/new. beforehand /old with {} bytes got deleted, its blocks still hold the old data",
+        hello.len(),
+        secret.len()
+    );
+    println!("every crash point: o == old state, n == new state, X == inconsistent\n");
+
+    let modes = [
+        None,
+        Some(JournalMode::Writeback),
+        Some(JournalMode::Ordered),
+        Some(JournalMode::Data),
+    ];
+    let outcomes = modes.map(|mode| {
+        let mut fs = Filesystem::format_with_journal(
+            MemoryDisk::new(512, 256),
+            layout,
+            32,
+            mode.map(|m| (m, 40)),
+        );
+        fs.create(ROOT, "/old").unwrap();
+        fs.write(ROOT, "/old", 0, secret.as_bytes()).unwrap();
+        fs.unlink(ROOT, "/old").unwrap();
+        fs.mkdir(ROOT, "/d").unwrap();
+        fs.create(ROOT, "/d/new").unwrap();
+        let image = fs.into_device();
+
+        let outcomes = crash_test(&image, &|fs| {
+            fs.write(ROOT, "/d/new", 0, hello.as_bytes()).unwrap()
+        });
+        println!(
+            "{:<20} {} writes: {}",
+            mode.map_or("no journal".to_owned(), |m| format!("{:?}", m)),
+            outcomes.len() - 1,
+            outcomes
+                .iter()
+                .map(|o| match o {
+                    CrashOutcome::Old => 'o',
+                    CrashOutcome::New => 'n',
+                    CrashOutcome::Inconsistent(_) => 'X',
+                })
+                .collect::<String>()
+        );
+        for (reason, crash_points) in &outcomes
+            .iter()
+            .enumerate()
+            .filter_map(|(i, o)| match o {
+                CrashOutcome::Inconsistent(reason) => Some((reason, i)),
+                _ => None,
+            })
+            .group_by(|(reason, _)| *reason)
+        {
+            let crash_points = crash_points.map(|(_, i)| i).collect_vec();
+            println!(
+                "    after {} writes: {}",
+                if crash_points.len() == 1 {
+                    crash_points[0].to_string()
+                } else {
+                    format!("{}-{}", crash_points[0], crash_points.last().unwrap())
+                },
+                reason
+            );
+        }
+        outcomes
+    });
+
+    let inconsistent = |outcomes: &[CrashOutcome]| {
+        outcomes
+            .iter()
+            .filter(|o| matches!(o, CrashOutcome::Inconsistent(_)))
+            .count()
+    };
+    let [none, writeback, ordered, data] = &outcomes;
+    assert!(inconsistent(none) > 0);
+    // metadata committed before the data got written: the new file shows the deleted one
+    assert!(writeback
+        .iter()
+        .any(|o| matches!(o, CrashOutcome::Inconsistent(reason) if reason.contains("SECRET"))));
+    assert_eq!(inconsistent(ordered), 0);
+    assert_eq!(inconsistent(data), 0);
+    for outcomes in [writeback, ordered, data] {
+        // all or nothing, and the switch happens exactly at the commit block
+        let switch = outcomes
+            .iter()
+            .position(|o| *o != CrashOutcome::Old)
+            .unwrap();
+        assert!(outcomes[switch..].iter().all(|o| *o != CrashOutcome::Old));
+    }
+    // but the data goes through the journal once more
+    assert!(data.len() > ordered.len());
+
+    println!("\n### A write bigger than the journal\n");
+    let mut fs = Filesystem::format_with_journal(
+        MemoryDisk::new(512, 256),
+        layout,
+        32,
+        Some((JournalMode::Data, 40)),
+    );
+    let big = (0..100 * 512).map(|i| (i % 253) as u8).collect_vec();
+    fs.create(ROOT, "/big").unwrap();
+    let sequence = fs.sequence;
+    fs.write(ROOT, "/big", 0, &big).unwrap();
+    println!(
+        "{} blocks of data through a journal of 40 blocks in {} transactions",
+        big.len() / 512,
+        fs.sequence - sequence
+    );
+    assert!(fs.sequence - sequence >= 3);
+    let mut fs = Filesystem::mount(fs.into_device());
+    assert_eq!(fs.read(ROOT, "/big", 0, usize::MAX), Ok(big));
+}
@@ -0,0 +1,887 @@
+use std::collections::{BTreeSet, HashMap};
+
+use itertools::Itertools;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use super::block_device::{BlockDevice, BlockId, MemoryDisk};
+use super::filesystem::{get_uint, put_uint, Filesystem, FsError, InodeId, ROOT};
+use super::inode::InodeLayout;
+
+// a log-structured filesystem: nothing is ever overwritten in place, every changed block goes to
+// the end of the log. the disk is
+//   checkpoint region 0 | checkpoint region 1 | segment 0 | segment 1 | ...
+// and each segment starts with a summary that says who every block in it belongs to.
+// inodes move with every write, so the inode map says where each one is. the imap itself is
+// written to the log as well, the checkpoint region only keeps where its chunks are. a
+// checkpoint alternates between the two regions, so a torn one leaves the older intact.
+// files have direct pointers only and there are no directories, inode numbers are the names.
+// all inodes stay in memory and are written back at the next checkpoint, packed into as few
+// blocks as possible, like sprite LFS does.
+// what was written after the last checkpoint is gone after a crash, there's no roll forward
+
+const CHECKPOINTS: [BlockId; 2] = [0, 1];
+// inode number and size, then the pointers
+const INODE_HEADER: usize = 16;
+const INODE_SIZE: usize = 128;
+const POINTERS: usize = (INODE_SIZE - INODE_HEADER) / 4;
+// inode number, file block and time of every block in the segment
+const SUMMARY_ENTRY: usize = 12;
+// the file block of a block of inodes
+const INODE_BLOCK: usize = u32::MAX as usize;
+// the cleaner starts below that many free segments and stops at the other. it keeps a few for
+// the checkpoints in between
+const CLEAN_BELOW: usize = 4;
+const CLEAN_UNTIL: usize = 8;
+const RESERVED: usize = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CleaningPolicy {
+    // the emptiest segment
+    Greedy,
+    // the one where (1 - u) * age / (1 + u) is largest: cold segments get cleaned at a higher
+    // utilization, because their free space stays free for longer
+    CostBenefit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SegmentState {
+    Free,
+    // the head of the log
+    Open,
+    Full,
+    // copied out, free once a checkpoint doesn't point into it anymore
+    Cleaned,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct SegmentUsage {
+    state: SegmentState,
+    live: usize,
+    // time of the youngest data block. inodes and imap chunks don't count, the cleaner writes
+    // those as well and would make cold segments look young
+    modified: usize,
+}
+
+// what the segment summary records for a block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Owner {
+    Data { inode: InodeId, file_block: usize },
+    // the imap says which ones are still in there
+    Inodes,
+    ImapChunk(usize),
+}
+
+impl Owner {
+    // inode 0 doesn't exist, so it marks imap chunks
+    fn encode(self) -> (usize, usize) {
+        match self {
+            Owner::Data { inode, file_block } => (inode, file_block),
+            Owner::Inodes => (1, INODE_BLOCK),
+            Owner::ImapChunk(chunk) => (0, chunk),
+        }
+    }
+
+    fn decode(inode: usize, file_block: usize) -> Self {
+        match (inode, file_block) {
+            (0, chunk) => Owner::ImapChunk(chunk),
+            (_, INODE_BLOCK) => Owner::Inodes,
+            (inode, file_block) => Owner::Data { inode, file_block },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct LfsInode {
+    size: usize,
+    pointers: Vec<BlockId>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LfsStats {
+    // blocks the user wrote, the rest of the device writes are overhead
+    pub user_blocks: usize,
+    pub cleaned_segments: usize,
+    pub copied_blocks: usize,
+    pub checkpoints: usize,
+}
+
+pub struct Lfs<D: BlockDevice> {
+    device: D,
+    segment_blocks: usize,
+    policy: CleaningPolicy,
+    segments: Vec<SegmentUsage>,
+    // where the current version of every inode is, 0 == not written yet or deleted
+    imap: Vec<BlockId>,
+    imap_chunks: Vec<BlockId>,
+    dirty_chunks: BTreeSet<usize>,
+    inodes: HashMap<InodeId, LfsInode>,
+    dirty_inodes: BTreeSet<InodeId>,
+    head: usize,
+    // the blocks of the open segment behind its summary, with their time
+    buffer: Vec<(Owner, usize, Vec<u8>)>,
+    // how many of them are on the device already
+    flushed: usize,
+    // counts user block writes
+    clock: usize,
+    serial: usize,
+    cleaning: bool,
+    stats: LfsStats,
+}
+
+impl<D: BlockDevice> Lfs<D> {
+    pub fn format(
+        device: D,
+        segment_blocks: usize,
+        inode_count: usize,
+        policy: CleaningPolicy,
+    ) -> Self {
+        let block_size = device.block_size();
+        assert!(
+            segment_blocks >= 2 && (segment_blocks - 1) * SUMMARY_ENTRY + 4 <= block_size,
+            "the summary of a segment of {} blocks doesn't fit into one block",
+            segment_blocks
+        );
+        let chunks = inode_count.div_ceil(block_size / 4);
+        assert!(
+            24 + 4 * chunks + 8 <= block_size,
+            "the imap of {} inodes doesn't fit into the checkpoint region",
+            inode_count
+        );
+        let segment_count = (device.block_count() - CHECKPOINTS.len()) / segment_blocks;
+        assert!(
+            segment_count > CLEAN_UNTIL,
+            "too few segments for the cleaner"
+        );
+        let mut segments = vec![
+            SegmentUsage {
+                state: SegmentState::Free,
+                live: 0,
+                modified: 0,
+            };
+            segment_count
+        ];
+        segments[0].state = SegmentState::Open;
+        let mut lfs = Lfs {
+            device,
+            segment_blocks,
+            policy,
+            segments,
+            imap: vec![0; inode_count],
+            imap_chunks: vec![0; chunks],
+            dirty_chunks: (0..chunks).collect(),
+            inodes: HashMap::new(),
+            dirty_inodes: BTreeSet::new(),
+            head: 0,
+            buffer: Vec::new(),
+            flushed: 0,
+            clock: 0,
+            serial: 0,
+            cleaning: false,
+            stats: LfsStats::default(),
+        };
+        lfs.sync();
+        lfs
+    }
+
+    // everything as of the newest intact checkpoint
+    pub fn mount(mut device: D, policy: CleaningPolicy) -> Self {
+        let block_size = device.block_size();
+        let checkpoint = CHECKPOINTS
+            .iter()
+            .map(|region| device.read_block(*region))
+            .filter(|block| {
+                let serial = get_uint(block, 0, 8);
+                serial != 0 && get_uint(block, block_size - 8, 8) == serial
+            })
+            .max_by_key(|block| get_uint(block, 0, 8))
+            .expect("no intact checkpoint region");
+        let serial = get_uint(&checkpoint, 0, 8);
+        let clock = get_uint(&checkpoint, 8, 8);
+        let segment_blocks = get_uint(&checkpoint, 16, 4);
+        let inode_count = get_uint(&checkpoint, 20, 4);
+        let per_chunk = block_size / 4;
+        let imap_chunks = (0..inode_count.div_ceil(per_chunk))
+            .map(|c| get_uint(&checkpoint, 24 + 4 * c, 4))
+            .collect_vec();
+        let mut imap = vec![0; inode_count];
+        for (c, address) in imap_chunks.iter().enumerate() {
+            let chunk = device.read_block(*address);
+            for (i, entry) in imap
+                .iter_mut()
+                .skip(c * per_chunk)
+                .take(per_chunk)
+                .enumerate()
+            {
+                *entry = get_uint(&chunk, 4 * i, 4);
+            }
+        }
+        let mut inodes = HashMap::new();
+        for address in imap.iter().filter(|a| **a != 0).unique() {
+            let block = device.read_block(*address);
+            for record in block.chunks(INODE_SIZE) {
+                let inode = get_uint(record, 0, 4);
+                // an older version of an inode that's been written elsewhere since
+                if inode == 0 || imap[inode] != *address {
+                    continue;
+                }
+                let pointers = (0..POINTERS)
+                    .map(|i| get_uint(record, INODE_HEADER + 4 * i, 4))
+                    .collect_vec();
+                let size = get_uint(record, 4, 8);
+                inodes.insert(inode, LfsInode { size, pointers });
+            }
+        }
+        // the segment usage follows from what the checkpoint can reach
+        let segment_count = (device.block_count() - CHECKPOINTS.len()) / segment_blocks;
+        let mut segments = vec![
+            SegmentUsage {
+                state: SegmentState::Free,
+                live: 0,
+                modified: 0,
+            };
+            segment_count
+        ];
+        let reachable = imap_chunks
+            .iter()
+            .chain(imap.iter().filter(|a| **a != 0).unique())
+            .chain(
+                inodes
+                    .values()
+                    .flat_map(|i| i.pointers.iter().filter(|p| **p != 0)),
+            );
+        for address in reachable {
+            let usage = &mut segments[(address - CHECKPOINTS.len()) / segment_blocks];
+            usage.state = SegmentState::Full;
+            usage.live += 1;
+        }
+        for (s, usage) in segments.iter_mut().enumerate() {
+            if usage.state == SegmentState::Full {
+                let summary = device.read_block(CHECKPOINTS.len() + s * segment_blocks);
+                usage.modified = (0..get_uint(&summary, 0, 4))
+                    .map(|i| 4 + i * SUMMARY_ENTRY)
+                    .filter(|at| {
+                        let owner = get_uint(&summary, *at, 4);
+                        let file_block = get_uint(&summary, at + 4, 4);
+                        matches!(Owner::decode(owner, file_block), Owner::Data { .. })
+                    })
+                    .map(|at| get_uint(&summary, at + 8, 4))
+                    .max()
+                    .unwrap_or(0);
+            }
+        }
+        let head = segments
+            .iter()
+            .position(|s| s.state == SegmentState::Free)
+            .expect("no free segment");
+        segments[head].state = SegmentState::Open;
+        Lfs {
+            device,
+            segment_blocks,
+            policy,
+            segments,
+            imap,
+            imap_chunks,
+            dirty_chunks: BTreeSet::new(),
+            inodes,
+            dirty_inodes: BTreeSet::new(),
+            head,
+            buffer: Vec::new(),
+            flushed: 0,
+            clock,
+            serial,
+            cleaning: false,
+            stats: LfsStats::default(),
+        }
+    }
+
+    pub fn device(&self) -> &D {
+        &self.device
+    }
+
+    // without a checkpoint, like pulling the plug
+    pub fn into_device(self) -> D {
+        self.device
+    }
+
+    pub fn stats(&self) -> LfsStats {
+        self.stats
+    }
+
+    pub fn free_segments(&self) -> usize {
+        self.segments
+            .iter()
+            .filter(|s| s.state == SegmentState::Free)
+            .count()
+    }
+
+    // live blocks, metadata included, of all the room there is for blocks
+    pub fn utilization(&self) -> f64 {
+        let live: usize = self.segments.iter().map(|s| s.live).sum();
+        live as f64 / (self.segments.len() * (self.segment_blocks - 1)) as f64
+    }
+
+    fn segment_start(&self, segment: usize) -> BlockId {
+        CHECKPOINTS.len() + segment * self.segment_blocks
+    }
+
+    fn segment_of(&self, address: BlockId) -> usize {
+        (address - CHECKPOINTS.len()) / self.segment_blocks
+    }
+
+    fn read_at(&mut self, address: BlockId) -> Vec<u8> {
+        let offset = address - self.segment_start(self.segment_of(address));
+        if self.segment_of(address) == self.head && offset <= self.buffer.len() {
+            self.buffer[offset - 1].2.clone()
+        } else {
+            self.device.read_block(address)
+        }
+    }
+
+    // a new segment if the open one is full. that may start the cleaner, which moves blocks and
+    // writes inodes and imap chunks. so a block that records where other blocks are has to be put
+    // together after this, not before
+    fn make_room(&mut self) {
+        while self.buffer.len() == self.segment_blocks - 1 {
+            self.seal();
+        }
+    }
+
+    fn append(&mut self, owner: Owner, time: usize, data: Vec<u8>) -> BlockId {
+        self.make_room();
+        self.buffer.push((owner, time, data));
+        let usage = &mut self.segments[self.head];
+        usage.live += 1;
+        if let Owner::Data { .. } = owner {
+            usage.modified = usage.modified.max(time);
+        }
+        self.segment_start(self.head) + self.buffer.len()
+    }
+
+    // inode blocks die with the last inode in them that's still current
+    fn release_inode_blocks(&mut self, blocks: Vec<BlockId>) {
+        for block in blocks {
+            if !self.imap.contains(&block) {
+                self.kill(block);
+            }
+        }
+    }
+
+    // a block that isn't needed anymore
+    fn kill(&mut self, address: BlockId) {
+        if address != 0 {
+            let segment = self.segment_of(address);
+            self.segments[segment].live -= 1;
+        }
+    }
+
+    // the new blocks of the open segment and its summary
+    fn flush(&mut self) {
+        if self.flushed == self.buffer.len() {
+            return;
+        }
+        let start = self.segment_start(self.head);
+        let mut summary = vec![0; self.device.block_size()];
+        put_uint(&mut summary, 0, 4, self.buffer.len());
+        for (i, (owner, time, _)) in self.buffer.iter().enumerate() {
+            let (inode, file_block) = owner.encode();
+            let at = 4 + i * SUMMARY_ENTRY;
+            put_uint(&mut summary, at, 4, inode);
+            put_uint(&mut summary, at + 4, 4, file_block);
+            put_uint(&mut summary, at + 8, 4, *time);
+        }
+        for i in self.flushed..self.buffer.len() {
+            self.device.write_block(start + 1 + i, &self.buffer[i].2);
+        }
+        self.device.write_block(start, &summary);
+        self.flushed = self.buffer.len();
+    }
+
+    fn seal(&mut self) {
+        self.flush();
+        self.segments[self.head].state = SegmentState::Full;
+        self.head = self
+            .segments
+            .iter()
+            .position(|s| s.state == SegmentState::Free)
+            .expect("the log is full, the cleaner couldn't keep up");
+        self.segments[self.head] = SegmentUsage {
+            state: SegmentState::Open,
+            live: 0,
+            modified: 0,
+        };
+        self.buffer.clear();
+        self.flushed = 0;
+        if self.free_segments() < CLEAN_BELOW && !self.cleaning {
+            self.clean();
+        }
+    }
+
+    fn is_live(&self, owner: Owner, address: BlockId) -> bool {
+        match owner {
+            Owner::Data { inode, file_block } => self
+                .inodes
+                .get(&inode)
+                .is_some_and(|i| i.pointers.get(file_block) == Some(&address)),
+            Owner::Inodes => self.imap.contains(&address),
+            Owner::ImapChunk(chunk) => self.imap_chunks.get(chunk) == Some(&address),
+        }
+    }
+
+    fn victim(&self) -> Option<usize> {
+        let capacity = (self.segment_blocks - 1) as f64;
+        let candidates = (0..self.segments.len())
+            .filter(|s| self.segments[*s].state == SegmentState::Full)
+            .filter(|s| self.segments[*s].live < self.segment_blocks - 1);
+        match self.policy {
+            CleaningPolicy::Greedy => candidates.min_by_key(|s| self.segments[*s].live),
+            CleaningPolicy::CostBenefit => candidates
+                .map(|s| {
+                    let u = self.segments[s].live as f64 / capacity;
+                    let age = (self.clock + 1 - self.segments[s].modified) as f64;
+                    (s, (1.0 - u) * age / (1.0 + u))
+                })
+                .max_by(|(_, a), (_, b)| a.total_cmp(b))
+                .map(|(s, _)| s),
+        }
+    }
+
+    // copies the live blocks out of segments until there are enough free ones again. inodes
+    // and imap chunks don't get copied, marking them dirty is enough
+    fn clean(&mut self) {
+        self.cleaning = true;
+        for _ in 0..2 * self.segments.len() {
+            if self.free_segments() >= CLEAN_UNTIL {
+                break;
+            }
+            // cleaned segments only become free with a checkpoint, and that needs room for the
+            // inodes the cleaner touched
+            let cleaned = self
+                .segments
+                .iter()
+                .any(|s| s.state == SegmentState::Cleaned);
+            if self.free_segments() <= RESERVED && cleaned {
+                self.sync();
+                continue;
+            }
+            let Some(victim) = self.victim() else {
+                break;
+            };
+            let start = self.segment_start(victim);
+            let summary = self.device.read_block(start);
+            let mut live = Vec::new();
+            for i in 0..get_uint(&summary, 0, 4) {
+                let at = 4 + i * SUMMARY_ENTRY;
+                let owner = Owner::decode(get_uint(&summary, at, 4), get_uint(&summary, at + 4, 4));
+                let address = start + 1 + i;
+                if self.is_live(owner, address) {
+                    let time = get_uint(&summary, at + 8, 4);
+                    live.push((owner, time, address));
+                }
+            }
+            self.segments[victim].state = SegmentState::Cleaned;
+            self.stats.cleaned_segments += 1;
+            // oldest first, so blocks of the same age end up together
+            live.sort_by_key(|(_, time, _)| *time);
+            for (owner, time, address) in live {
+                match owner {
+                    Owner::Data { inode, file_block } => {
+                        let data = self.device.read_block(address);
+                        let new = self.append(owner, time, data);
+                        self.inodes.get_mut(&inode).unwrap().pointers[file_block] = new;
+                        self.kill(address);
+                        self.dirty_inodes.insert(inode);
+                        self.stats.copied_blocks += 1;
+                    }
+                    Owner::Inodes => {
+                        let moved = (0..self.imap.len()).filter(|i| self.imap[*i] == address);
+                        self.dirty_inodes.extend(moved.collect_vec());
+                    }
+                    Owner::ImapChunk(chunk) => {
+                        self.dirty_chunks.insert(chunk);
+                    }
+                }
+            }
+        }
+        self.sync();
+        self.cleaning = false;
+    }
+
+    // writes the dirty inodes and imap chunks to the log, then the checkpoint region
+    pub fn sync(&mut self) {
+        let block_size = self.device.block_size();
+        loop {
+            self.make_room();
+            if self.dirty_inodes.is_empty() {
+                break;
+            }
+            let batch = (0..block_size / INODE_SIZE)
+                .map_while(|_| self.dirty_inodes.pop_first())
+                .collect_vec();
+            let mut block = vec![0; block_size];
+            for (inode, record) in batch.iter().zip(block.chunks_mut(INODE_SIZE)) {
+                let content = &self.inodes[inode];
+                put_uint(record, 0, 4, *inode);
+                put_uint(record, 4, 8, content.size);
+                for (i, pointer) in content.pointers.iter().enumerate() {
+                    put_uint(record, INODE_HEADER + 4 * i, 4, *pointer);
+                }
+            }
+            let new = self.append(Owner::Inodes, self.clock, block);
+            let old = batch
+                .iter()
+                .map(|inode| std::mem::replace(&mut self.imap[*inode], new))
+                .unique()
+                .collect_vec();
+            for inode in batch {
+                self.dirty_chunks.insert(inode / (block_size / 4));
+            }
+            self.release_inode_blocks(old);
+        }
+        loop {
+            self.make_room();
+            let Some(chunk) = self.dirty_chunks.pop_first() else {
+                break;
+            };
+            let mut block = vec![0; block_size];
+            let per_chunk = block_size / 4;
+            for (i, address) in self
+                .imap
+                .iter()
+                .skip(chunk * per_chunk)
+                .take(per_chunk)
+                .enumerate()
+            {
+                put_uint(&mut block, 4 * i, 4, *address);
+            }
+            let new = self.append(Owner::ImapChunk(chunk), self.clock, block);
+            self.kill(self.imap_chunks[chunk]);
+            self.imap_chunks[chunk] = new;
+        }
+        self.flush();
+        self.serial += 1;
+        let mut checkpoint = vec![0; block_size];
+        put_uint(&mut checkpoint, 0, 8, self.serial);
+        put_uint(&mut checkpoint, 8, 8, self.clock);
+        put_uint(&mut checkpoint, 16, 4, self.segment_blocks);
+        put_uint(&mut checkpoint, 20, 4, self.imap.len());
+        for (c, address) in self.imap_chunks.iter().enumerate() {
+            put_uint(&mut checkpoint, 24 + 4 * c, 4, *address);
+        }
+        put_uint(&mut checkpoint, block_size - 8, 8, self.serial);
+        self.device
+            .write_block(CHECKPOINTS[self.serial % 2], &checkpoint);
+        self.stats.checkpoints += 1;
+        for usage in &mut self.segments {
+            if usage.state == SegmentState::Cleaned {
+                assert_eq!(usage.live, 0, "a cleaned segment still has live blocks");
+                usage.state = SegmentState::Free;
+            }
+        }
+    }
+
+    pub fn create(&mut self) -> Result<InodeId, FsError> {
+        let inode = (1..self.imap.len())
+            .find(|i| !self.inodes.contains_key(i))
+            .ok_or(FsError::NoSpace)?;
+        let pointers = vec![0; POINTERS];
+        self.inodes.insert(inode, LfsInode { size: 0, pointers });
+        self.dirty_inodes.insert(inode);
+        Ok(inode)
+    }
+
+    pub fn delete(&mut self, inode: InodeId) -> Result<(), FsError> {
+        let content = self.inodes.remove(&inode).ok_or(FsError::NotFound)?;
+        for pointer in content.pointers {
+            self.kill(pointer);
+        }
+        let old = std::mem::replace(&mut self.imap[inode], 0);
+        self.release_inode_blocks(vec![old]);
+        self.dirty_inodes.remove(&inode);
+        self.dirty_chunks
+            .insert(inode / (self.device.block_size() / 4));
+        Ok(())
+    }
+
+    pub fn size(&self, inode: InodeId) -> Result<usize, FsError> {
+        self.inodes
+            .get(&inode)
+            .map(|i| i.size)
+            .ok_or(FsError::NotFound)
+    }
+
+    pub fn write(&mut self, inode: InodeId, offset: usize, data: &[u8]) -> Result<(), FsError> {
+        let block_size = self.device.block_size();
+        if !self.inodes.contains_key(&inode) {
+            return Err(FsError::NotFound);
+        }
+        let end = offset + data.len();
+        if end > POINTERS * block_size {
+            return Err(FsError::FileTooLarge);
+        }
+        for file_block in offset / block_size..end.div_ceil(block_size) {
+            let start = file_block * block_size;
+            let (from, to) = (offset.max(start), end.min(start + block_size));
+            let old = self.inodes[&inode].pointers[file_block];
+            let mut block = if old != 0 && to - from < block_size {
+                self.read_at(old)
+            } else {
+                vec![0; block_size]
+            };
+            block[from - start..to - start].copy_from_slice(&data[from - offset..to - offset]);
+            let owner = Owner::Data { inode, file_block };
+            let new = self.append(owner, self.clock, block);
+            // the cleaner may have moved the old block in the meantime
+            let old = std::mem::replace(
+                &mut self.inodes.get_mut(&inode).unwrap().pointers[file_block],
+                new,
+            );
+            self.kill(old);
+            self.clock += 1;
+            self.stats.user_blocks += 1;
+        }
+        let content = self.inodes.get_mut(&inode).unwrap();
+        content.size = content.size.max(end);
+        self.dirty_inodes.insert(inode);
+        Ok(())
+    }
+
+    pub fn read(&mut self, inode: InodeId, offset: usize, len: usize) -> Result<Vec<u8>, FsError> {
+        let block_size = self.device.block_size();
+        let size = self.size(inode)?;
+        let end = (offset + len).min(size);
+        let mut data = Vec::new();
+        for file_block in offset / block_size..end.div_ceil(block_size) {
+            let start = file_block * block_size;
+            let pointer = self.inodes[&inode].pointers[file_block];
+            let block = if pointer == 0 {
+                vec![0; block_size]
+            } else {
+                self.read_at(pointer)
+            };
+            data.extend_from_slice(
+                &block[offset.max(start) - start..end.min(start + block_size) - start],
+            );
+        }
+        Ok(data)
+    }
+
+    // one line per segment: "1:0" is block 0 of inode 1, "i" a block of inodes, "m0" the first
+    // imap chunk. dead blocks are in parentheses
+    fn describe_segment(&mut self, segment: usize) -> String {
+        let start = self.segment_start(segment);
+        let summary = if segment == self.head {
+            self.buffer.iter().map(|(owner, _, _)| *owner).collect_vec()
+        } else {
+            let block = self.device.read_block(start);
+            (0..get_uint(&block, 0, 4))
+                .map(|i| {
+                    let at = 4 + i * SUMMARY_ENTRY;
+                    Owner::decode(get_uint(&block, at, 4), get_uint(&block, at + 4, 4))
+                })
+                .collect_vec()
+        };
+        let blocks = summary
+            .iter()
+            .enumerate()
+            .map(|(i, owner)| {
+                let name = match owner {
+                    Owner::Data { inode, file_block } => format!("{}:{}", inode, file_block),
+                    Owner::Inodes => "i".to_owned(),
+                    Owner::ImapChunk(chunk) => format!("m{}", chunk),
+                };
+                if self.is_live(*owner, start + 1 + i) {
+                    name
+                } else {
+                    format!("({})", name)
+                }
+            })
+            .join(" ");
+        format!(
+            "segment {} [{:?}, {} live]: summary {}",
+            segment, self.segments[segment].state, self.segments[segment].live, blocks
+        )
+    }
+}
+
+fn fill(block_size: usize, seed: usize) -> Vec<u8> {
+    vec![(seed % 251) as u8 + 1; block_size]
+}
+
+// overwrites random blocks of equal sized files, `hot` of the writes go to the first 10% of
+// them. returns the device writes per user block in the steady state: right after the files got
+// written sequentially every segment is full or empty, the cleaner has it easy for a while. so
+// the measurement starts after `WARM_UP` overwrites of all the live data
+fn write_amplification(utilization: f64, hot: f64, policy: CleaningPolicy) -> (f64, usize) {
+    const SEGMENTS: usize = 64;
+    const SEGMENT_BLOCKS: usize = 32;
+    const FILE_BLOCKS: usize = 16;
+    // in overwrites of all the live data
+    const WARM_UP: usize = 4;
+    const MEASURED: usize = 4;
+    let block_size = 512;
+    let disk = MemoryDisk::new(block_size, 2 + SEGMENTS * SEGMENT_BLOCKS);
+    let mut lfs = Lfs::format(disk, SEGMENT_BLOCKS, 256, policy);
+    let files = (utilization * (SEGMENTS * (SEGMENT_BLOCKS - 1)) as f64) as usize / FILE_BLOCKS;
+    let mut content = HashMap::new();
+    let inodes = (0..files).map(|_| lfs.create().unwrap()).collect_vec();
+    for (f, inode) in inodes.iter().enumerate() {
+        let data = (0..FILE_BLOCKS)
+            .flat_map(|b| fill(block_size, f + b))
+            .collect_vec();
+        lfs.write(*inode, 0, &data).unwrap();
+        for b in 0..FILE_BLOCKS {
+            content.insert((*inode, b), f + b);
+        }
+    }
+    lfs.sync();
+    let mut rng = StdRng::seed_from_u64(45);
+    let live_blocks = files * FILE_BLOCKS;
+    let hot_blocks = live_blocks / 10;
+    let mut overwrite = |lfs: &mut Lfs<MemoryDisk>, writes: std::ops::Range<usize>| {
+        for i in writes {
+            let block = if rng.gen_bool(hot) {
+                rng.gen_range(0..hot_blocks)
+            } else {
+                rng.gen_range(hot_blocks..live_blocks)
+            };
+            let (inode, file_block) = (inodes[block / FILE_BLOCKS], block % FILE_BLOCKS);
+            lfs.write(inode, file_block * block_size, &fill(block_size, i))
+                .unwrap();
+            content.insert((inode, file_block), i);
+            // every 256 blocks, 8 segments
+            if i % 256 == 255 {
+                lfs.sync();
+            }
+        }
+        lfs.sync();
+    };
+    let warm_up = WARM_UP * live_blocks;
+    overwrite(&mut lfs, 0..warm_up);
+    let (writes, user_blocks) = (lfs.device().stats().writes, lfs.stats().user_blocks);
+    let cleaned = lfs.stats().cleaned_segments;
+    overwrite(&mut lfs, warm_up..warm_up + MEASURED * live_blocks);
+    let amplification = (lfs.device().stats().writes - writes) as f64
+        / (lfs.stats().user_blocks - user_blocks) as f64;
+    let cleaned = lfs.stats().cleaned_segments - cleaned;
+    // everything survived the cleaner, also after a mount
+    let mut lfs = Lfs::mount(lfs.into_device(), policy);
+    for ((inode, file_block), seed) in content {
+        assert_eq!(
+            lfs.read(inode, file_block * block_size, block_size),
+            Ok(fill(block_size, seed))
+        );
+    }
+    (amplification, cleaned)
+}
+
+pub fn test_lfs() {
+    println!("\n## LOG-STRUCTURED FILESYSTEM\n");
+
+    println!("\n### The log\n");
+    let mut lfs = Lfs::format(
+        MemoryDisk::new(512, 2 + 16 * 16),
+        16,
+        64,
+        CleaningPolicy::Greedy,
+    );
+    let a = lfs.create().unwrap();
+    let b = lfs.create().unwrap();
+    lfs.write(a, 0, &[b'a'; 3 * 512]).unwrap();
+    lfs.write(b, 0, b"hello").unwrap();
+    lfs.sync();
+    // the old block 1 of `a`, the old inodes and the old imap chunk are dead now
+    lfs.write(a, 512, &[b'A'; 512]).unwrap();
+    lfs.write(b, 5, b", world").unwrap();
+    lfs.sync();
+    for segment in 0..2 {
+        println!("{}", lfs.describe_segment(segment));
+    }
+    assert_eq!(lfs.read(b, 0, 100), Ok(b"hello, world".to_vec()));
+    assert_eq!(lfs.read(a, 510, 4), Ok(b"aaAA".to_vec()));
+    assert_eq!(lfs.write(9, 0, b"x"), Err(FsError::NotFound));
+    assert_eq!(
+        lfs.write(a, POINTERS * 512, &[0; 513]),
+        Err(FsError::FileTooLarge)
+    );
+
+    println!("\n### Checkpoints\n");
+    // written, but no checkpoint yet
+    lfs.write(b, 0, b"HELLO").unwrap();
+    let c = lfs.create().unwrap();
+    lfs.write(c, 0, b"lost").unwrap();
+    let serial = lfs.serial;
+    let mut disk = lfs.into_device();
+    let mut lfs = Lfs::mount(disk.clone(), CleaningPolicy::Greedy);
+    println!(
+        "crash before the checkpoint: b is {:?}, c is {:?}",
+        String::from_utf8(lfs.read(b, 0, 100).unwrap()).unwrap(),
+        lfs.size(c)
+    );
+    assert_eq!(lfs.read(b, 0, 100), Ok(b"hello, world".to_vec()));
+    assert_eq!(lfs.size(c), Err(FsError::NotFound));
+    // a torn write of the newest region: its serial at the end is missing
+    let region = CHECKPOINTS[serial % 2];
+    let torn = disk.read_block(region)[..256].to_vec();
+    disk.write_block(region, &torn);
+    let mut lfs = Lfs::mount(disk, CleaningPolicy::Greedy);
+    let content = String::from_utf8(lfs.read(b, 0, 100).unwrap()).unwrap();
+    println!(
+        "newest checkpoint region torn: back to checkpoint {}, b is {:?}",
+        lfs.serial, content
+    );
+    assert_eq!(lfs.serial, serial - 1);
+    assert_eq!(lfs.read(b, 0, 100), Ok(b"hello".to_vec()));
+
+    println!("\n### Write amplification\n");
+    // the in place model for comparison: a block of an existing file costs the block itself and
+    // the inode
+    let mut fs = Filesystem::format(MemoryDisk::new(512, 2048), InodeLayout::ext2(512), 64);
+    fs.create(ROOT, "f").unwrap();
+    fs.write(ROOT, "f", 0, &[1; 16 * 512]).unwrap();
+    let (_, io) = fs.measure(|fs| fs.write(ROOT, "f", 5 * 512, &[2; 512]).unwrap());
+    println!(
+        "in place: {} writes per block at any utilization, each to wherever the block is\n",
+        io.writes
+    );
+    println!(
+        "{:<13} {:>20} {:>20} {:>20} {:>20}",
+        "utilization",
+        "uniform greedy",
+        "uniform cost-ben.",
+        "hot/cold greedy",
+        "hot/cold cost-ben."
+    );
+    let mut rows = Vec::new();
+    for utilization in [0.3, 0.5, 0.7, 0.8] {
+        // uniform: 10% of the writes go to 10% of the blocks
+        let row = [
+            (0.1, CleaningPolicy::Greedy),
+            (0.1, CleaningPolicy::CostBenefit),
+            (0.9, CleaningPolicy::Greedy),
+            (0.9, CleaningPolicy::CostBenefit),
+        ]
+        .into_iter()
+        .map(|(hot, policy)| write_amplification(utilization, hot, policy))
+        .collect_vec();
+        println!(
+            "{:<13} {}",
+            format!("{:.0}%", utilization * 100.0),
+            row.iter()
+                .map(|(wa, cleaned)| format!("{:>20}", format!("{:.2} ({} cleaned)", wa, cleaned)))
+                .join(" ")
+        );
+        rows.push(row);
+    }
+    // more live data, more copying
+    for column in 0..4 {
+        assert!(rows.windows(2).all(|r| r[0][column].0 <= r[1][column].0));
+    }
+    // the LFS paper has cost-benefit ahead for hot/cold. it cleans cold segments at a higher
+    // utilization and bets that their free space stays free. on 64 segments a segment full of
+    // cold blocks still loses one every few hundred writes, so the bet doesn't pay off here
+    let (greedy, cost_benefit) = (rows[3][2].0, rows[3][3].0);
+    println!(
+        "\nhot/cold at 80%: cost-benefit {cost_benefit:.2}, greedy {greedy:.2}. the paper's win \
+         needs cold data that stays untouched for much longer than it does on a disk this small"
+    );
+}
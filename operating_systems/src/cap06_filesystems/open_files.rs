@@ -0,0 +1,446 @@
+use itertools::Itertools;
+
+use super::{
+    block_device::{BlockDevice, MemoryDisk},
+    filesystem::{FileKind, Filesystem, FsError, InodeId, MAX_SYMLINKS, ROOT},
+    inode::InodeLayout,
+    permissions::{check, Credentials, Denied, READ, WRITE},
+};
+
+// file descriptors. every process has its own table of them, each with an offset and what it was
+// opened for. the permissions are checked once at open, like in unix: a chmod afterwards doesn't
+// take away an open file.
+// the filesystem counts the descriptors per inode over all processes. an unlinked file lives on
+// as long as one is left, its blocks are freed on the last close
+
+pub type Fd = usize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct OpenFlags {
+    pub read: bool,
+    pub write: bool,
+    // O_CREAT, a missing file gets created
+    pub create: bool,
+    // O_APPEND, every write goes to the end
+    pub append: bool,
+}
+
+impl OpenFlags {
+    pub fn read_only() -> Self {
+        OpenFlags {
+            read: true,
+            ..Default::default()
+        }
+    }
+
+    pub fn write_only() -> Self {
+        OpenFlags {
+            write: true,
+            ..Default::default()
+        }
+    }
+
+    pub fn read_write() -> Self {
+        OpenFlags {
+            read: true,
+            write: true,
+            ..Default::default()
+        }
+    }
+
+    pub fn create(self) -> Self {
+        OpenFlags {
+            create: true,
+            ..self
+        }
+    }
+
+    pub fn append(self) -> Self {
+        OpenFlags {
+            append: true,
+            ..self
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpenFile {
+    pub inode: InodeId,
+    pub offset: usize,
+    pub flags: OpenFlags,
+}
+
+pub struct Process {
+    pub cred: Credentials,
+    pub cwd: InodeId,
+    // indexed by fd, None for a closed one
+    files: Vec<Option<OpenFile>>,
+}
+
+impl Process {
+    pub fn new(cred: Credentials, cwd: InodeId) -> Self {
+        Process {
+            cred,
+            cwd,
+            files: Vec::new(),
+        }
+    }
+
+    // the lowest free fd, like open(2)
+    pub fn open<D: BlockDevice>(
+        &mut self,
+        fs: &mut Filesystem<D>,
+        path: &str,
+        flags: OpenFlags,
+    ) -> Result<Fd, Denied> {
+        let (cred, cwd) = (&self.cred, self.cwd);
+        let inode = fs.transaction(|fs| -> Result<InodeId, Denied> {
+            let (inode, content) = match fs.lookup(cred, cwd, path, true) {
+                Err(d) if d.error == FsError::NotFound && flags.create => {
+                    let inode = fs.create_inode(cred, cwd, path, FileKind::File, &[])?;
+                    // a fresh file may be opened however it was asked for
+                    return Ok(inode);
+                }
+                found => found?,
+            };
+            if content.kind == FileKind::Directory {
+                return Err(FsError::IsADirectory.into());
+            }
+            let want = if flags.read { READ } else { 0 } | if flags.write { WRITE } else { 0 };
+            check(cred, &content, want, path)?;
+            Ok(inode)
+        })?;
+
+        *fs.open_counts.entry(inode).or_insert(0) += 1;
+        let file = Some(OpenFile {
+            inode,
+            offset: 0,
+            flags,
+        });
+        match self.files.iter().position(Option::is_none) {
+            Some(fd) => {
+                self.files[fd] = file;
+                Ok(fd)
+            }
+            None => {
+                self.files.push(file);
+                Ok(self.files.len() - 1)
+            }
+        }
+    }
+
+    fn file(&mut self, fd: Fd) -> Result<&mut OpenFile, FsError> {
+        self.files
+            .get_mut(fd)
+            .and_then(Option::as_mut)
+            .ok_or(FsError::BadDescriptor)
+    }
+
+    // from the offset on, which moves past what got read
+    pub fn read<D: BlockDevice>(
+        &mut self,
+        fs: &mut Filesystem<D>,
+        fd: Fd,
+        len: usize,
+    ) -> Result<Vec<u8>, FsError> {
+        let file = self.file(fd)?;
+        if !file.flags.read {
+            return Err(FsError::BadDescriptor);
+        }
+        let mut content = fs.inode(file.inode);
+        let data = fs.read_data(&mut content, file.offset, len);
+        file.offset += data.len();
+        Ok(data)
+    }
+
+    pub fn write<D: BlockDevice>(
+        &mut self,
+        fs: &mut Filesystem<D>,
+        fd: Fd,
+        data: &[u8],
+    ) -> Result<(), FsError> {
+        let cred = self.cred.clone();
+        let file = self.file(fd)?;
+        if !file.flags.write {
+            return Err(FsError::BadDescriptor);
+        }
+        let (inode, append) = (file.inode, file.flags.append);
+        let offset = if append {
+            fs.inode(inode).size
+        } else {
+            file.offset
+        };
+        fs.write_in_parts(offset, data, |fs, offset, data| {
+            let mut content = fs.inode(inode);
+            fs.write_at(&cred, inode, &mut content, offset, data)
+        })?;
+        self.file(fd)?.offset = offset + data.len();
+        Ok(())
+    }
+
+    // lseek with SEEK_SET. past the end is fine, a write there leaves a hole
+    pub fn seek(&mut self, fd: Fd, offset: usize) -> Result<(), FsError> {
+        self.file(fd)?.offset = offset;
+        Ok(())
+    }
+
+    pub fn close<D: BlockDevice>(&mut self, fs: &mut Filesystem<D>, fd: Fd) -> Result<(), FsError> {
+        let inode = self.file(fd)?.inode;
+        self.files[fd] = None;
+        fs.release(inode);
+        Ok(())
+    }
+
+    // closes everything, like a process that exits
+    pub fn exit<D: BlockDevice>(&mut self, fs: &mut Filesystem<D>) {
+        for fd in 0..self.files.len() {
+            if self.files[fd].is_some() {
+                self.close(fs, fd).expect("open");
+            }
+        }
+    }
+
+    pub fn open_files(&self) -> Vec<(Fd, OpenFile)> {
+        self.files
+            .iter()
+            .enumerate()
+            .filter_map(|(fd, file)| file.map(|file| (fd, file)))
+            .collect()
+    }
+}
+
+impl<D: BlockDevice> Filesystem<D> {
+    pub fn open_count(&self, inode: InodeId) -> usize {
+        self.open_counts.get(&inode).copied().unwrap_or(0)
+    }
+
+    // one descriptor less. an inode no name points to anymore goes with the last one
+    fn release(&mut self, inode: InodeId) {
+        let count = self.open_counts.get_mut(&inode).expect("opened");
+        *count -= 1;
+        if *count > 0 {
+            return;
+        }
+        self.open_counts.remove(&inode);
+        self.transaction(|fs| {
+            let content = fs.inode(inode);
+            if content.links == 0 {
+                fs.free_inode(inode, &content);
+            }
+        });
+    }
+}
+
+fn print_table<D: BlockDevice>(name: &str, process: &Process, fs: &Filesystem<D>) {
+    println!("{} (uid {}):", name, process.cred.uid);
+    for (fd, file) in process.open_files() {
+        let mode = match (file.flags.read, file.flags.write) {
+            (true, true) => "rw",
+            (true, false) => "r",
+            (false, true) => "w",
+            (false, false) => "-",
+        };
+        println!(
+            "  fd {}  inode {:>2}  offset {:>3}  {:<2}{}  opened {} times",
+            fd,
+            file.inode,
+            file.offset,
+            mode,
+            if file.flags.append { " append" } else { "" },
+            fs.open_count(file.inode)
+        );
+    }
+}
+
+pub fn test_links() {
+    println!("\n## LINKS AND OPEN FILES\n");
+
+    let layout = InodeLayout {
+        block_size: 1024,
+        pointer_size: 4,
+        direct: 4,
+        indirect: [1, 1, 0],
+    };
+    let mut fs = Filesystem::format(MemoryDisk::new(1024, 256), layout, 64);
+
+    println!("### Hard links\n");
+    fs.mkdir(ROOT, "/docs").unwrap();
+    let report = fs.create(ROOT, "/docs/report").unwrap();
+    fs.write(ROOT, "/docs/report", 0, b"first draft").unwrap();
+    fs.link(ROOT, "/docs/report", "/report").unwrap();
+    // one inode, two names
+    assert_eq!(fs.resolve(ROOT, "/report"), Ok(report));
+    let stat = fs.stat(ROOT, "/report").unwrap();
+    println!(
+        "/report and /docs/report are inode {}, {} links",
+        report, stat.links
+    );
+    assert_eq!(stat.links, 2);
+    fs.write(ROOT, "/report", 0, b"final").unwrap();
+    assert_eq!(
+        fs.read(ROOT, "/docs/report", 0, usize::MAX),
+        Ok(b"final draft".to_vec())
+    );
+    fs.unlink(ROOT, "/docs/report").unwrap();
+    let stat = fs.stat(ROOT, "/report").unwrap();
+    println!("after unlink /docs/report: {} link, {:?}", stat.links, {
+        let data = fs.read(ROOT, "/report", 0, usize::MAX).unwrap();
+        String::from_utf8(data).unwrap()
+    });
+    assert_eq!(stat.links, 1);
+    let directory_link = fs.link(ROOT, "/docs", "/docs2");
+    println!("link /docs /docs2: {:?}", directory_link);
+    assert_eq!(directory_link, Err(FsError::NotPermitted));
+
+    println!("\n### Symlinks\n");
+    fs.symlink(ROOT, "/docs", "/d").unwrap();
+    fs.symlink(ROOT, "../report", "/docs/up").unwrap();
+    fs.symlink(ROOT, "/nowhere", "/dangling").unwrap();
+    for path in ["/d", "/docs/up", "/dangling"] {
+        let lstat = fs.lstat(ROOT, path).unwrap();
+        println!(
+            "{:<10} -> {:<10} lstat: {:?} {}  stat: {:?}",
+            path,
+            fs.readlink(ROOT, path).unwrap(),
+            lstat.kind,
+            lstat.size,
+            fs.stat(ROOT, path).map(|i| (i.kind, i.size))
+        );
+    }
+    assert_eq!(fs.stat(ROOT, "/docs/up").unwrap().links, 1);
+    assert_eq!(fs.stat(ROOT, "/dangling"), Err(FsError::NotFound));
+    assert_eq!(
+        fs.read(ROOT, "/d/up", 0, usize::MAX),
+        Ok(b"final draft".to_vec())
+    );
+    assert_eq!(fs.readlink(ROOT, "/report"), Err(FsError::InvalidArgument));
+    // /d costs its inode and its block, then it starts over at the root inode. up is relative
+    // to /docs, whose block gets read again for ".."
+    let (_, direct) = fs.measure(|fs| fs.resolve(ROOT, "/report"));
+    let (_, through) = fs.measure(|fs| fs.resolve(ROOT, "/d/up"));
+    println!(
+        "resolve /report: {} reads, through /d/up: {} reads",
+        direct.reads, through.reads
+    );
+    assert_eq!(direct.reads, 3);
+    assert_eq!(through.reads, 4 + 1 + 2 + 3 + 2 + 2);
+    // unlink removes the symlink, not the file
+    fs.unlink(ROOT, "/d/up").unwrap();
+    assert!(fs.stat(ROOT, "/report").is_ok());
+
+    println!("\n### Loops\n");
+    fs.symlink(ROOT, "/loop2", "/loop1").unwrap();
+    fs.symlink(ROOT, "loop1", "/loop2").unwrap();
+    let looped = fs.resolve(ROOT, "/loop1");
+    println!("resolve /loop1: {:?}", looped);
+    assert_eq!(looped, Err(FsError::TooManySymlinks));
+    // the reason lists the chain
+    let root = Credentials::root();
+    let denied = fs.as_user(&root).stat(ROOT, "/loop1").unwrap_err();
+    println!("{}", denied);
+    // a chain as long as the limit is fine, one more isn't
+    fs.symlink(ROOT, "/report", "/chain0").unwrap();
+    for i in 1..=MAX_SYMLINKS {
+        fs.symlink(ROOT, &format!("chain{}", i - 1), &format!("/chain{}", i))
+            .unwrap();
+    }
+    let longest = format!("/chain{}", MAX_SYMLINKS - 1);
+    let too_long = format!("/chain{}", MAX_SYMLINKS);
+    println!(
+        "{}: {:?}, {}: {:?}",
+        longest,
+        fs.resolve(ROOT, &longest).map(|_| "found"),
+        too_long,
+        fs.resolve(ROOT, &too_long).map(|_| "found")
+    );
+    assert_eq!(fs.resolve(ROOT, &longest), Ok(report));
+    assert_eq!(fs.resolve(ROOT, &too_long), Err(FsError::TooManySymlinks));
+
+    println!("\n### Open file tables\n");
+    let alice = Credentials::user(1000, 1000, &[]);
+    let bob = Credentials::user(1001, 1001, &[]);
+    fs.mkdir(ROOT, "/tmp").unwrap();
+    fs.as_user(&root).chmod(ROOT, "/tmp", 0o1777).unwrap();
+    let mut a = Process::new(alice, ROOT);
+    let mut b = Process::new(bob, ROOT);
+
+    let log = a
+        .open(&mut fs, "/tmp/log", OpenFlags::write_only().create())
+        .unwrap();
+    a.write(&mut fs, log, b"one ").unwrap();
+    a.write(&mut fs, log, b"two ").unwrap();
+    let tail = b.open(&mut fs, "/tmp/log", OpenFlags::read_only()).unwrap();
+    let appender = a
+        .open(&mut fs, "/tmp/log", OpenFlags::write_only().append())
+        .unwrap();
+    // bob's offset is his own
+    assert_eq!(b.read(&mut fs, tail, 4), Ok(b"one ".to_vec()));
+    a.write(&mut fs, appender, b"three").unwrap();
+    print_table("alice", &a, &fs);
+    print_table("bob", &b, &fs);
+    assert_eq!(b.read(&mut fs, tail, 100), Ok(b"two three".to_vec()));
+    assert_eq!(b.write(&mut fs, tail, b"x"), Err(FsError::BadDescriptor));
+    let writable = b.open(&mut fs, "/tmp/log", OpenFlags::read_write());
+    println!("bob opens /tmp/log rw: {}", writable.unwrap_err());
+
+    // checked at open, not at every read
+    fs.as_user(&a.cred).chmod(ROOT, "/tmp/log", 0o600).unwrap();
+    b.seek(tail, 0).unwrap();
+    assert_eq!(b.read(&mut fs, tail, 3), Ok(b"one".to_vec()));
+    assert!(b.open(&mut fs, "/tmp/log", OpenFlags::read_only()).is_err());
+
+    println!("\n### Unlinked, but open\n");
+    let free_blocks = fs.free_block_count();
+    let free_inodes = fs.free_inode_count();
+    let log_inode = fs.resolve(ROOT, "/tmp/log").unwrap();
+    fs.as_user(&a.cred).unlink(ROOT, "/tmp/log").unwrap();
+    assert_eq!(fs.resolve(ROOT, "/tmp/log"), Err(FsError::NotFound));
+    let orphan = fs.inode(log_inode);
+    println!(
+        "/tmp/log unlinked: inode {} has {} links, open count {}, {} bytes",
+        log_inode,
+        orphan.links,
+        fs.open_count(log_inode),
+        orphan.size
+    );
+    // still readable and writable through the descriptors, and nothing freed yet
+    a.write(&mut fs, appender, b" four").unwrap();
+    assert_eq!(
+        b.read(&mut fs, tail, usize::MAX),
+        Ok(b" two three four".to_vec())
+    );
+    assert_eq!(fs.free_inode_count(), free_inodes);
+    a.exit(&mut fs);
+    println!(
+        "alice exits: open count {}, {} free inodes",
+        fs.open_count(log_inode),
+        fs.free_inode_count()
+    );
+    assert_eq!(fs.free_inode_count(), free_inodes);
+    b.close(&mut fs, tail).unwrap();
+    println!(
+        "bob closes it: {} free inodes, {} free blocks",
+        fs.free_inode_count(),
+        fs.free_block_count()
+    );
+    assert_eq!(fs.free_inode_count(), free_inodes + 1);
+    assert_eq!(fs.free_block_count(), free_blocks + 1);
+    assert_eq!(b.close(&mut fs, tail), Err(FsError::BadDescriptor));
+
+    // a rename over an open file is an unlink of it as well
+    let mut c = Process::new(root.clone(), ROOT);
+    fs.create(ROOT, "/old").unwrap();
+    fs.write(ROOT, "/old", 0, b"old contents").unwrap();
+    fs.create(ROOT, "/new").unwrap();
+    fs.write(ROOT, "/new", 0, b"new contents").unwrap();
+    let old = c.open(&mut fs, "/old", OpenFlags::read_only()).unwrap();
+    fs.rename(ROOT, "/new", "/old").unwrap();
+    let reopened = c.open(&mut fs, "/old", OpenFlags::read_only()).unwrap();
+    let both = [old, reopened]
+        .iter()
+        .map(|fd| String::from_utf8(c.read(&mut fs, *fd, usize::MAX).unwrap()).unwrap())
+        .collect_vec();
+    println!("open before and after renaming /new over /old: {:?}", both);
+    assert_eq!(both, ["old contents", "new contents"]);
+    c.exit(&mut fs);
+}
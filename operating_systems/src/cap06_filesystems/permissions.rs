@@ -0,0 +1,803 @@
+use std::fmt;
+
+use itertools::Itertools;
+
+use super::{
+    block_device::{BlockDevice, MemoryDisk},
+    filesystem::{FileKind, Filesystem, FsError, Inode, InodeId, ROOT},
+    inode::InodeLayout,
+};
+
+// unix permissions on top of `Filesystem`: owner, group, the rwx bits for owner, group and others,
+// setuid, setgid and sticky, and POSIX.1e ACLs with named users and groups and a mask.
+// every operation gets the credentials of the caller and checks them on the way: search on every
+// directory of a path, write on a directory whose entries change, read or write on the file.
+// a refusal says why, so "can alice read /home/bob/todo" gets an answer with a reason
+
+pub type Uid = usize;
+pub type Gid = usize;
+
+pub const READ: u8 = 4;
+pub const WRITE: u8 = 2;
+pub const EXECUTE: u8 = 1;
+
+pub const SETUID: u16 = 0o4000;
+pub const SETGID: u16 = 0o2000;
+pub const STICKY: u16 = 0o1000;
+
+// named entries, as many as fit into the inode header
+pub const MAX_ACL_ENTRIES: usize = 4;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Credentials {
+    pub uid: Uid,
+    pub gid: Gid,
+    // supplementary groups
+    pub groups: Vec<Gid>,
+    pub umask: u16,
+}
+
+impl Credentials {
+    pub fn root() -> Self {
+        Credentials::user(0, 0, &[])
+    }
+
+    pub fn user(uid: Uid, gid: Gid, groups: &[Gid]) -> Self {
+        Credentials {
+            uid,
+            gid,
+            groups: groups.to_vec(),
+            umask: 0o022,
+        }
+    }
+
+    pub fn in_group(&self, gid: Gid) -> bool {
+        self.gid == gid || self.groups.contains(&gid)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AclTag {
+    User(Uid),
+    Group(Gid),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AclEntry {
+    pub tag: AclTag,
+    pub perms: u8,
+}
+
+// the owner and other entries are the mode bits, and so is the mask: with an acl the group bits
+// of the mode limit everything in the group class
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Acl {
+    // the owning group's own entry, the mode only has room for the mask
+    pub group_obj: u8,
+    pub entries: Vec<AclEntry>,
+}
+
+// the errno and what led to it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Denied {
+    pub error: FsError,
+    pub reason: String,
+}
+
+impl From<FsError> for Denied {
+    fn from(error: FsError) -> Self {
+        Denied {
+            error,
+            reason: String::new(),
+        }
+    }
+}
+
+impl fmt::Display for Denied {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.reason.is_empty() {
+            write!(f, "{:?}", self.error)
+        } else {
+            write!(f, "{:?}: {}", self.error, self.reason)
+        }
+    }
+}
+
+fn denied(error: FsError, reason: String) -> Denied {
+    Denied { error, reason }
+}
+
+pub fn rwx(perms: u8) -> String {
+    [(READ, 'r'), (WRITE, 'w'), (EXECUTE, 'x')]
+        .iter()
+        .map(|&(bit, c)| if perms & bit != 0 { c } else { '-' })
+        .collect()
+}
+
+// like `ls -l`: s/S for setuid and setgid with or without x, t/T for sticky, + with an acl
+pub fn mode_string(inode: &Inode) -> String {
+    let mut s = String::from(match inode.kind {
+        FileKind::File => "-",
+        FileKind::Directory => "d",
+        FileKind::Symlink => "l",
+    });
+    for (shift, special, set, unset) in [
+        (6, SETUID, 's', 'S'),
+        (3, SETGID, 's', 'S'),
+        (0, STICKY, 't', 'T'),
+    ] {
+        let mut bits = rwx((inode.mode >> shift) as u8 & 7).into_bytes();
+        if inode.mode & special != 0 {
+            bits[2] = if bits[2] == b'x' { set } else { unset } as u8;
+        }
+        s.push_str(&String::from_utf8(bits).expect("ascii"));
+    }
+    if inode.acl.is_some() {
+        s.push('+');
+    }
+    s
+}
+
+// POSIX.1e access check. the first class that matches decides: owner, named user, the group class
+// (owning group and named groups, any of them granting is enough), other.
+// root may do anything, except executing a file nobody may execute
+pub(super) fn check(cred: &Credentials, inode: &Inode, want: u8, path: &str) -> Result<(), Denied> {
+    let who = format!(
+        "{} is {} {}:{} and uid {}",
+        path,
+        mode_string(inode),
+        inode.uid,
+        inode.gid,
+        cred.uid
+    );
+    if cred.uid == 0 {
+        if want & EXECUTE != 0 && inode.kind == FileKind::File && inode.mode & 0o111 == 0 {
+            return Err(denied(
+                FsError::PermissionDenied,
+                format!("{} is root, but no one has x", who),
+            ));
+        }
+        return Ok(());
+    }
+
+    let mask = (inode.mode >> 3) as u8 & 7;
+    let decide = |class: String, perms: u8| {
+        if perms & want == want {
+            Ok(())
+        } else {
+            Err(denied(
+                FsError::PermissionDenied,
+                format!(
+                    "{} falls under {}, which has {}, not {}",
+                    who,
+                    class,
+                    rwx(perms),
+                    rwx(want)
+                ),
+            ))
+        }
+    };
+
+    if cred.uid == inode.uid {
+        return decide("the owner".to_owned(), (inode.mode >> 6) as u8 & 7);
+    }
+    let acl_entries = inode.acl.as_ref().map_or(&[][..], |acl| &acl.entries);
+    if let Some(entry) = acl_entries.iter().find(|e| e.tag == AclTag::User(cred.uid)) {
+        return decide(
+            format!(
+                "user:{}:{} masked by {}",
+                cred.uid,
+                rwx(entry.perms),
+                rwx(mask)
+            ),
+            entry.perms & mask,
+        );
+    }
+
+    // without an acl the group bits are the owning group's, not a mask
+    let mut matching = Vec::new();
+    if cred.in_group(inode.gid) {
+        match &inode.acl {
+            Some(acl) => matching.push((
+                format!("the owning group {} with {}", inode.gid, rwx(acl.group_obj)),
+                acl.group_obj & mask,
+            )),
+            None => matching.push((format!("the owning group {}", inode.gid), mask)),
+        }
+    }
+    for entry in acl_entries {
+        if let AclTag::Group(gid) = entry.tag {
+            if cred.in_group(gid) {
+                matching.push((
+                    format!("group:{}:{}", gid, rwx(entry.perms)),
+                    entry.perms & mask,
+                ));
+            }
+        }
+    }
+    if matching.iter().any(|(_, perms)| perms & want == want) {
+        return Ok(());
+    }
+    if !matching.is_empty() {
+        // each entry has to grant everything on its own, together they never count
+        let classes = matching.iter().map(|(class, _)| class).join(" and ");
+        let masked = if inode.acl.is_some() {
+            format!(" masked by {}", rwx(mask))
+        } else {
+            String::new()
+        };
+        let perms = matching.iter().map(|(_, perms)| rwx(*perms)).join(" and ");
+        return Err(denied(
+            FsError::PermissionDenied,
+            format!(
+                "{} falls under {}{}, which {} {}, not {}",
+                who,
+                classes,
+                masked,
+                if matching.len() == 1 { "has" } else { "have" },
+                perms,
+                rwx(want)
+            ),
+        ));
+    }
+    decide("other".to_owned(), inode.mode as u8 & 7)
+}
+
+// in a sticky directory like /tmp, write permission on it isn't enough to remove or rename
+// someone else's entry
+pub(super) fn may_delete(
+    cred: &Credentials,
+    dir: &Inode,
+    victim: &Inode,
+    path: &str,
+) -> Result<(), Denied> {
+    if dir.mode & STICKY == 0 || cred.uid == 0 || cred.uid == dir.uid || cred.uid == victim.uid {
+        return Ok(());
+    }
+    Err(denied(
+        FsError::NotPermitted,
+        format!(
+            "{} is in a sticky directory, and uid {} owns neither it ({}) nor the directory ({})",
+            path, cred.uid, victim.uid, dir.uid
+        ),
+    ))
+}
+
+fn require_owner(cred: &Credentials, inode: &Inode, path: &str, op: &str) -> Result<(), Denied> {
+    if cred.uid == 0 || cred.uid == inode.uid {
+        return Ok(());
+    }
+    Err(denied(
+        FsError::NotPermitted,
+        format!(
+            "only root and the owner {} of {} may {}, not uid {}",
+            inode.uid, path, op, cred.uid
+        ),
+    ))
+}
+
+impl<D: BlockDevice> Filesystem<D> {
+    // the same operations as the ones on `Filesystem`, with `cred` instead of root
+    pub fn as_user(&mut self, cred: &Credentials) -> AsUser<'_, D> {
+        AsUser {
+            fs: self,
+            cred: cred.clone(),
+        }
+    }
+
+    fn change_mode(
+        &mut self,
+        cred: &Credentials,
+        cwd: InodeId,
+        path: &str,
+        mode: u16,
+    ) -> Result<(), Denied> {
+        let (inode, mut content) = self.lookup(cred, cwd, path, true)?;
+        require_owner(cred, &content, path, "chmod")?;
+        content.mode = mode & 0o7777;
+        // or anyone could hand out a group they aren't in
+        if cred.uid != 0 && content.kind == FileKind::File && !cred.in_group(content.gid) {
+            content.mode &= !SETGID;
+        }
+        self.write_inode(inode, Some(&content));
+        Ok(())
+    }
+
+    fn change_owner(
+        &mut self,
+        cred: &Credentials,
+        cwd: InodeId,
+        path: &str,
+        uid: Option<Uid>,
+        gid: Option<Gid>,
+    ) -> Result<(), Denied> {
+        let (inode, mut content) = self.lookup(cred, cwd, path, true)?;
+        if cred.uid != 0 {
+            require_owner(cred, &content, path, "chown")?;
+            if uid.is_some_and(|uid| uid != content.uid) {
+                return Err(denied(
+                    FsError::NotPermitted,
+                    format!("only root may give {} away", path),
+                ));
+            }
+            if let Some(gid) = gid.filter(|&gid| !cred.in_group(gid)) {
+                return Err(denied(
+                    FsError::NotPermitted,
+                    format!("uid {} isn't in group {}", cred.uid, gid),
+                ));
+            }
+        }
+        content.uid = uid.unwrap_or(content.uid);
+        content.gid = gid.unwrap_or(content.gid);
+        if content.kind == FileKind::File {
+            content.mode &= !(SETUID | SETGID);
+        }
+        self.write_inode(inode, Some(&content));
+        Ok(())
+    }
+
+    // like setfacl, the mask becomes the union of the group class. no entries removes the acl
+    fn change_acl(
+        &mut self,
+        cred: &Credentials,
+        cwd: InodeId,
+        path: &str,
+        entries: &[AclEntry],
+    ) -> Result<(), Denied> {
+        if entries.len() > MAX_ACL_ENTRIES {
+            return Err(FsError::InvalidArgument.into());
+        }
+        let (inode, mut content) = self.lookup(cred, cwd, path, true)?;
+        require_owner(cred, &content, path, "set its acl")?;
+        let group_obj = match &content.acl {
+            Some(acl) => acl.group_obj,
+            None => (content.mode >> 3) as u8 & 7,
+        };
+        let group_class = if entries.is_empty() {
+            content.acl = None;
+            group_obj
+        } else {
+            content.acl = Some(Acl {
+                group_obj,
+                entries: entries.to_vec(),
+            });
+            entries.iter().fold(group_obj, |mask, e| mask | e.perms)
+        };
+        content.mode = content.mode & !0o070 | (group_class as u16) << 3;
+        self.write_inode(inode, Some(&content));
+        Ok(())
+    }
+
+    // the credentials a program runs with: the file's owner and group with setuid and setgid
+    fn execute(
+        &mut self,
+        cred: &Credentials,
+        cwd: InodeId,
+        path: &str,
+    ) -> Result<Credentials, Denied> {
+        let (_, content) = self.lookup(cred, cwd, path, true)?;
+        if content.kind == FileKind::Directory {
+            return Err(denied(
+                FsError::PermissionDenied,
+                format!("{} is a directory", path),
+            ));
+        }
+        check(cred, &content, EXECUTE, path)?;
+        let mut effective = cred.clone();
+        if content.mode & SETUID != 0 {
+            effective.uid = content.uid;
+        }
+        if content.mode & SETGID != 0 {
+            effective.gid = content.gid;
+        }
+        Ok(effective)
+    }
+}
+
+pub struct AsUser<'f, D: BlockDevice> {
+    fs: &'f mut Filesystem<D>,
+    cred: Credentials,
+}
+
+impl<D: BlockDevice> AsUser<'_, D> {
+    pub fn resolve(&mut self, cwd: InodeId, path: &str) -> Result<InodeId, Denied> {
+        self.fs
+            .lookup(&self.cred, cwd, path, true)
+            .map(|(inode, _)| inode)
+    }
+
+    pub fn create(&mut self, cwd: InodeId, path: &str) -> Result<InodeId, Denied> {
+        let cred = &self.cred;
+        self.fs
+            .transaction(|fs| fs.create_inode(cred, cwd, path, FileKind::File, &[]))
+    }
+
+    pub fn mkdir(&mut self, cwd: InodeId, path: &str) -> Result<InodeId, Denied> {
+        let cred = &self.cred;
+        self.fs
+            .transaction(|fs| fs.create_inode(cred, cwd, path, FileKind::Directory, &[]))
+    }
+
+    pub fn symlink(&mut self, cwd: InodeId, target: &str, path: &str) -> Result<InodeId, Denied> {
+        let cred = &self.cred;
+        let target = target.as_bytes();
+        self.fs
+            .transaction(|fs| fs.create_inode(cred, cwd, path, FileKind::Symlink, target))
+    }
+
+    pub fn link(&mut self, cwd: InodeId, existing: &str, path: &str) -> Result<(), Denied> {
+        let cred = &self.cred;
+        self.fs
+            .transaction(|fs| fs.add_link(cred, cwd, existing, path))
+    }
+
+    pub fn readlink(&mut self, cwd: InodeId, path: &str) -> Result<String, Denied> {
+        self.fs.read_link(&self.cred, cwd, path)
+    }
+
+    pub fn unlink(&mut self, cwd: InodeId, path: &str) -> Result<(), Denied> {
+        let cred = &self.cred;
+        self.fs.transaction(|fs| fs.unlink_file(cred, cwd, path))
+    }
+
+    pub fn rmdir(&mut self, cwd: InodeId, path: &str) -> Result<(), Denied> {
+        let cred = &self.cred;
+        self.fs
+            .transaction(|fs| fs.remove_directory(cred, cwd, path))
+    }
+
+    pub fn rename(&mut self, cwd: InodeId, from: &str, to: &str) -> Result<(), Denied> {
+        let cred = &self.cred;
+        self.fs.transaction(|fs| fs.move_entry(cred, cwd, from, to))
+    }
+
+    pub fn read(
+        &mut self,
+        cwd: InodeId,
+        path: &str,
+        offset: usize,
+        len: usize,
+    ) -> Result<Vec<u8>, Denied> {
+        self.fs.read_file(&self.cred, cwd, path, offset, len)
+    }
+
+    pub fn write(
+        &mut self,
+        cwd: InodeId,
+        path: &str,
+        offset: usize,
+        data: &[u8],
+    ) -> Result<(), Denied> {
+        let cred = &self.cred;
+        self.fs.write_in_parts(offset, data, |fs, offset, data| {
+            fs.write_file(cred, cwd, path, offset, data)
+        })
+    }
+
+    pub fn read_dir(&mut self, cwd: InodeId, path: &str) -> Result<Vec<(String, InodeId)>, Denied> {
+        self.fs.list_dir(&self.cred, cwd, path)
+    }
+
+    pub fn stat(&mut self, cwd: InodeId, path: &str) -> Result<Inode, Denied> {
+        let (_, content) = self.fs.lookup(&self.cred, cwd, path, true)?;
+        Ok(content)
+    }
+
+    pub fn lstat(&mut self, cwd: InodeId, path: &str) -> Result<Inode, Denied> {
+        let (_, content) = self.fs.lookup(&self.cred, cwd, path, false)?;
+        Ok(content)
+    }
+
+    pub fn chmod(&mut self, cwd: InodeId, path: &str, mode: u16) -> Result<(), Denied> {
+        let cred = &self.cred;
+        self.fs
+            .transaction(|fs| fs.change_mode(cred, cwd, path, mode))
+    }
+
+    // `None` keeps the owner or group
+    pub fn chown(
+        &mut self,
+        cwd: InodeId,
+        path: &str,
+        uid: Option<Uid>,
+        gid: Option<Gid>,
+    ) -> Result<(), Denied> {
+        let cred = &self.cred;
+        self.fs
+            .transaction(|fs| fs.change_owner(cred, cwd, path, uid, gid))
+    }
+
+    pub fn set_acl(
+        &mut self,
+        cwd: InodeId,
+        path: &str,
+        entries: &[AclEntry],
+    ) -> Result<(), Denied> {
+        let cred = &self.cred;
+        self.fs
+            .transaction(|fs| fs.change_acl(cred, cwd, path, entries))
+    }
+
+    // access(2): may these credentials do `want` to `path`, and if not, why
+    pub fn access(&mut self, cwd: InodeId, path: &str, want: u8) -> Result<(), Denied> {
+        let (_, content) = self.fs.lookup(&self.cred, cwd, path, true)?;
+        check(&self.cred, &content, want, path)
+    }
+
+    pub fn exec(&mut self, cwd: InodeId, path: &str) -> Result<Credentials, Denied> {
+        self.fs.execute(&self.cred, cwd, path)
+    }
+}
+
+fn user(uid: Uid, groups: &[Gid]) -> Credentials {
+    Credentials::user(uid, uid, groups)
+}
+
+fn print_answer(who: &str, what: &str, path: &str, answer: &Result<(), Denied>) {
+    let answer = match answer {
+        Ok(()) => "yes".to_owned(),
+        Err(d) => format!("no, {}", d),
+    };
+    println!("{:<6} {:<8} {:<22} {}", who, what, path, answer);
+}
+
+fn print_ls<D: BlockDevice>(fs: &mut Filesystem<D>, path: &str) {
+    let inode = fs.stat(ROOT, path).expect("exists");
+    let acl = inode.acl.as_ref().map_or(String::new(), |acl| {
+        acl.entries
+            .iter()
+            .map(|e| match e.tag {
+                AclTag::User(uid) => format!("user:{}:{}", uid, rwx(e.perms)),
+                AclTag::Group(gid) => format!("group:{}:{}", gid, rwx(e.perms)),
+            })
+            .chain([
+                format!("group::{}", rwx(acl.group_obj)),
+                format!("mask::{}", rwx((inode.mode >> 3) as u8 & 7)),
+            ])
+            .join(",")
+    });
+    println!(
+        "{:<11} {:>4} {:>4}  {:<22} {}",
+        mode_string(&inode),
+        inode.uid,
+        inode.gid,
+        path,
+        acl
+    );
+}
+
+pub fn test_permissions() {
+    println!("\n## PERMISSIONS\n");
+
+    let layout = InodeLayout {
+        block_size: 1024,
+        pointer_size: 4,
+        direct: 4,
+        indirect: [1, 1, 0],
+    };
+    let mut fs = Filesystem::format(MemoryDisk::new(1024, 256), layout, 64);
+    let root = Credentials::root();
+    const STAFF: Gid = 100;
+    let alice = user(1000, &[STAFF]);
+    let bob = user(1001, &[STAFF]);
+    let carol = user(1002, &[]);
+
+    // set up like a small multi-user machine
+    {
+        let mut r = fs.as_user(&root);
+        for dir in ["/home", "/tmp", "/bin", "/etc", "/srv", "/srv/project"] {
+            r.mkdir(ROOT, dir).unwrap();
+        }
+        r.chmod(ROOT, "/tmp", 0o1777).unwrap();
+        r.chown(ROOT, "/srv/project", None, Some(STAFF)).unwrap();
+        r.chmod(ROOT, "/srv/project", 0o2775).unwrap();
+        r.create(ROOT, "/etc/shadow").unwrap();
+        r.chmod(ROOT, "/etc/shadow", 0o600).unwrap();
+        r.create(ROOT, "/bin/passwd").unwrap();
+        r.chmod(ROOT, "/bin/passwd", 0o4755).unwrap();
+        r.create(ROOT, "/etc/motd").unwrap();
+        // bob shares his home with staff
+        let homes = [
+            ("alice", &alice, alice.gid, 0o700),
+            ("bob", &bob, STAFF, 0o750),
+        ];
+        for (name, cred, gid, mode) in homes {
+            let home = format!("/home/{}", name);
+            r.mkdir(ROOT, &home).unwrap();
+            r.chown(ROOT, &home, Some(cred.uid), Some(gid)).unwrap();
+            r.chmod(ROOT, &home, mode).unwrap();
+        }
+    }
+    fs.as_user(&alice)
+        .create(ROOT, "/home/alice/notes")
+        .unwrap();
+    {
+        let mut b = fs.as_user(&bob);
+        b.create(ROOT, "/home/bob/todo").unwrap();
+        b.chown(ROOT, "/home/bob/todo", None, Some(STAFF)).unwrap();
+        b.chmod(ROOT, "/home/bob/todo", 0o640).unwrap();
+    }
+    for path in [
+        "/home/alice",
+        "/home/alice/notes",
+        "/home/bob",
+        "/home/bob/todo",
+        "/tmp",
+        "/srv/project",
+        "/bin/passwd",
+        "/etc/shadow",
+    ] {
+        print_ls(&mut fs, path);
+    }
+
+    let users = [
+        ("root", &root),
+        ("alice", &alice),
+        ("bob", &bob),
+        ("carol", &carol),
+    ];
+    let ask = |fs: &mut Filesystem<MemoryDisk>, who: usize, want: u8, path: &str| {
+        let (name, cred) = users[who];
+        let answer = fs.as_user(cred).access(ROOT, path, want);
+        let what = match want {
+            READ => "read",
+            WRITE => "write",
+            EXECUTE => "execute",
+            _ => "rwx",
+        };
+        print_answer(name, what, path, &answer);
+        answer
+    };
+    let (root_, alice_, bob_, carol_) = (0, 1, 2, 3);
+
+    println!("\n### Can X do Y on Z\n");
+    assert!(ask(&mut fs, alice_, READ, "/home/alice/notes").is_ok());
+    // bob never gets past alice's home
+    let answer = ask(&mut fs, bob_, READ, "/home/alice/notes").unwrap_err();
+    assert_eq!(answer.error, FsError::PermissionDenied);
+    assert!(answer.reason.contains("/home/alice is drwx------"));
+    // alice is in staff, the group of bob's todo
+    assert!(ask(&mut fs, alice_, READ, "/home/bob/todo").is_ok());
+    assert!(ask(&mut fs, alice_, WRITE, "/home/bob/todo").is_err());
+    assert!(ask(&mut fs, carol_, READ, "/home/bob/todo").is_err());
+    assert!(ask(&mut fs, carol_, READ, "/etc/shadow").is_err());
+    assert!(ask(&mut fs, root_, READ | WRITE, "/etc/shadow").is_ok());
+    // root skips the checks, but won't execute what isn't executable at all
+    assert!(ask(&mut fs, root_, EXECUTE, "/etc/motd").is_err());
+    assert!(ask(&mut fs, root_, EXECUTE, "/bin/passwd").is_ok());
+
+    println!("\n### ACLs\n");
+    // bob lets carol in without making her part of staff
+    let carol_reads = AclEntry {
+        tag: AclTag::User(carol.uid),
+        perms: READ,
+    };
+    let carol_searches = AclEntry {
+        tag: AclTag::User(carol.uid),
+        perms: READ | EXECUTE,
+    };
+    fs.as_user(&bob)
+        .set_acl(ROOT, "/home/bob/todo", &[carol_reads])
+        .unwrap();
+    // the file says yes, the directory still no
+    assert!(ask(&mut fs, carol_, READ, "/home/bob/todo").is_err());
+    fs.as_user(&bob)
+        .set_acl(ROOT, "/home/bob", &[carol_searches])
+        .unwrap();
+    print_ls(&mut fs, "/home/bob");
+    print_ls(&mut fs, "/home/bob/todo");
+    assert!(ask(&mut fs, carol_, READ, "/home/bob/todo").is_ok());
+    assert!(ask(&mut fs, carol_, WRITE, "/home/bob/todo").is_err());
+    // only the owner sets acls
+    let answer = fs.as_user(&alice).set_acl(ROOT, "/home/bob/todo", &[]);
+    print_answer("alice", "setfacl", "/home/bob/todo", &answer);
+    assert_eq!(answer.unwrap_err().error, FsError::NotPermitted);
+    // chmod g-r lowers the mask, and with it carol's entry
+    fs.as_user(&bob)
+        .chmod(ROOT, "/home/bob/todo", 0o600)
+        .unwrap();
+    print_ls(&mut fs, "/home/bob/todo");
+    let answer = ask(&mut fs, carol_, READ, "/home/bob/todo").unwrap_err();
+    assert!(answer.reason.contains("masked by ---"));
+    // a named group entry: staff may write through it, once the mask allows it
+    let staff_writes = AclEntry {
+        tag: AclTag::Group(STAFF),
+        perms: READ | WRITE,
+    };
+    fs.as_user(&bob)
+        .set_acl(ROOT, "/home/bob/todo", &[carol_reads, staff_writes])
+        .unwrap();
+    print_ls(&mut fs, "/home/bob/todo");
+    assert!(ask(&mut fs, alice_, WRITE, "/home/bob/todo").is_ok());
+    assert!(ask(&mut fs, carol_, READ, "/home/bob/todo").is_ok());
+    let too_many = vec![staff_writes; MAX_ACL_ENTRIES + 1];
+    assert_eq!(
+        fs.as_user(&bob)
+            .set_acl(ROOT, "/home/bob/todo", &too_many)
+            .map_err(|d| d.error),
+        Err(FsError::InvalidArgument)
+    );
+    // the acl is on disk, not only in memory
+    let expected = fs.stat(ROOT, "/home/bob/todo").unwrap();
+    let mut fs = Filesystem::mount(fs.into_device());
+    assert_eq!(fs.stat(ROOT, "/home/bob/todo"), Ok(expected));
+
+    println!("\n### Sticky /tmp\n");
+    fs.as_user(&alice).create(ROOT, "/tmp/a").unwrap();
+    fs.as_user(&bob).create(ROOT, "/tmp/b").unwrap();
+    let answer = fs.as_user(&bob).unlink(ROOT, "/tmp/a");
+    print_answer("bob", "unlink", "/tmp/a", &answer);
+    assert_eq!(answer.unwrap_err().error, FsError::NotPermitted);
+    let answer = fs.as_user(&bob).rename(ROOT, "/tmp/b", "/tmp/a");
+    print_answer("bob", "rename", "/tmp/b to /tmp/a", &answer);
+    assert_eq!(answer.unwrap_err().error, FsError::NotPermitted);
+    let answer = fs.as_user(&alice).unlink(ROOT, "/tmp/a");
+    print_answer("alice", "unlink", "/tmp/a", &answer);
+    assert!(answer.is_ok());
+    let answer = fs.as_user(&root).unlink(ROOT, "/tmp/b");
+    print_answer("root", "unlink", "/tmp/b", &answer);
+    assert!(answer.is_ok());
+
+    println!("\n### Setgid directories\n");
+    {
+        let mut a = fs.as_user(&alice);
+        a.create(ROOT, "/srv/project/plan").unwrap();
+        a.mkdir(ROOT, "/srv/project/src").unwrap();
+    }
+    print_ls(&mut fs, "/srv/project/plan");
+    print_ls(&mut fs, "/srv/project/src");
+    // files get the directory's group, directories the bit as well
+    assert_eq!(fs.stat(ROOT, "/srv/project/plan").unwrap().gid, STAFF);
+    let src = fs.stat(ROOT, "/srv/project/src").unwrap();
+    assert_eq!((src.gid, src.mode & SETGID), (STAFF, SETGID));
+    // carol isn't in staff
+    assert!(fs.as_user(&carol).create(ROOT, "/srv/project/x").is_err());
+
+    println!("\n### Setuid programs\n");
+    let answer = fs.as_user(&bob).write(ROOT, "/etc/shadow", 0, b"bob:new");
+    print_answer("bob", "write", "/etc/shadow", &answer);
+    assert!(answer.is_err());
+    let passwd = fs.as_user(&bob).exec(ROOT, "/bin/passwd").unwrap();
+    println!(
+        "bob runs /bin/passwd as uid {} gid {}",
+        passwd.uid, passwd.gid
+    );
+    assert_eq!(passwd.uid, 0);
+    let answer = fs
+        .as_user(&passwd)
+        .write(ROOT, "/etc/shadow", 0, b"bob:new");
+    print_answer("passwd", "write", "/etc/shadow", &answer);
+    assert!(answer.is_ok());
+    // a non-root write drops setuid, or anyone with write access could plant a shell
+    {
+        let mut a = fs.as_user(&alice);
+        a.create(ROOT, "/home/alice/tool").unwrap();
+        a.chmod(ROOT, "/home/alice/tool", 0o4755).unwrap();
+    }
+    print_ls(&mut fs, "/home/alice/tool");
+    fs.as_user(&alice)
+        .write(ROOT, "/home/alice/tool", 0, b"#!")
+        .unwrap();
+    print_ls(&mut fs, "/home/alice/tool");
+    assert_eq!(fs.stat(ROOT, "/home/alice/tool").unwrap().mode, 0o755);
+
+    println!("\n### Ownership\n");
+    let answer = fs
+        .as_user(&alice)
+        .chown(ROOT, "/home/alice/notes", Some(bob.uid), None);
+    print_answer("alice", "chown", "/home/alice/notes", &answer);
+    assert_eq!(answer.unwrap_err().error, FsError::NotPermitted);
+    let answer = fs
+        .as_user(&alice)
+        .chown(ROOT, "/home/alice/notes", None, Some(bob.gid));
+    print_answer("alice", "chgrp", "/home/alice/notes", &answer);
+    assert!(answer.is_err());
+    let answer = fs
+        .as_user(&alice)
+        .chown(ROOT, "/home/alice/notes", None, Some(STAFF));
+    print_answer("alice", "chgrp", "/home/alice/notes", &answer);
+    assert!(answer.is_ok());
+    let answer = fs.as_user(&carol).chmod(ROOT, "/etc/motd", 0o666);
+    print_answer("carol", "chmod", "/etc/motd", &answer);
+    assert_eq!(answer.unwrap_err().error, FsError::NotPermitted);
+}
@@ -0,0 +1,642 @@
+use itertools::Itertools;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use super::block_device::{BlockDevice, BlockId, IoStats, MemoryDisk};
+
+// several disks behind one block device. the stripe unit is one block: stripe s is block s of
+// every disk. RAID 4 keeps the parity on the last disk, RAID 5 rotates it (left symmetric, like
+// linux md), RAID 6 adds a second syndrome Q next to P:
+//   P = D0 + D1 + ...            Q = g^0 D0 + g^1 D1 + ...
+// in GF(2^8), where + is xor. one missing block comes back from P, two from P and Q.
+// a failed disk stays in the array until it gets replaced by a fresh one and rebuilt
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RaidLevel {
+    // striping, no redundancy
+    Raid0,
+    // every disk holds everything
+    Raid1,
+    Raid4,
+    Raid5,
+    Raid6,
+}
+
+impl RaidLevel {
+    fn parity_disks(self, disk_count: usize) -> usize {
+        match self {
+            RaidLevel::Raid0 => 0,
+            RaidLevel::Raid1 => disk_count - 1,
+            RaidLevel::Raid4 | RaidLevel::Raid5 => 1,
+            RaidLevel::Raid6 => 2,
+        }
+    }
+
+    // how many disks may fail without losing data
+    pub fn tolerates(self, disk_count: usize) -> usize {
+        self.parity_disks(disk_count)
+    }
+}
+
+// the generator 2 in GF(2^8) with the polynomial x^8 + x^4 + x^3 + x^2 + 1, as linux md uses it
+fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0;
+    while b != 0 {
+        if b & 1 != 0 {
+            product ^= a;
+        }
+        let carry = a & 0x80 != 0;
+        a <<= 1;
+        if carry {
+            a ^= 0x1d;
+        }
+        b >>= 1;
+    }
+    product
+}
+
+fn gf_pow(a: u8, exponent: usize) -> u8 {
+    (0..exponent % 255).fold(1, |p, _| gf_mul(p, a))
+}
+
+// g^-x == g^(255-x)
+fn gf_inv(a: u8) -> u8 {
+    gf_pow(a, 254)
+}
+
+fn xor_into(target: &mut [u8], block: &[u8]) {
+    for (t, b) in target.iter_mut().zip(block) {
+        *t ^= b;
+    }
+}
+
+fn scaled(block: &[u8], factor: u8) -> Vec<u8> {
+    block.iter().map(|b| gf_mul(*b, factor)).collect()
+}
+
+// which disk holds what in one stripe
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StripeLayout {
+    // disk of every data block, in logical order
+    pub data: Vec<usize>,
+    pub p: Option<usize>,
+    pub q: Option<usize>,
+}
+
+pub struct Raid<D: BlockDevice> {
+    level: RaidLevel,
+    disks: Vec<D>,
+    // failed, or replaced but not rebuilt yet
+    failed: Vec<bool>,
+}
+
+impl<D: BlockDevice> Raid<D> {
+    pub fn new(level: RaidLevel, disks: Vec<D>) -> Self {
+        let minimum = match level {
+            RaidLevel::Raid0 => 1,
+            RaidLevel::Raid1 => 2,
+            RaidLevel::Raid4 | RaidLevel::Raid5 => 3,
+            RaidLevel::Raid6 => 4,
+        };
+        assert!(
+            disks.len() >= minimum,
+            "{:?} needs at least {} disks",
+            level,
+            minimum
+        );
+        assert!(
+            disks
+                .iter()
+                .map(|d| (d.block_size(), d.block_count()))
+                .all_equal(),
+            "all disks need the same geometry"
+        );
+        // fresh disks are all zeros, and so is the parity of zeros
+        let failed = vec![false; disks.len()];
+        Raid {
+            level,
+            disks,
+            failed,
+        }
+    }
+
+    pub fn level(&self) -> RaidLevel {
+        self.level
+    }
+
+    pub fn disk_stats(&self) -> Vec<IoStats> {
+        self.disks.iter().map(|d| d.stats()).collect_vec()
+    }
+
+    fn data_per_stripe(&self) -> usize {
+        match self.level {
+            RaidLevel::Raid1 => 1,
+            level => self.disks.len() - level.parity_disks(self.disks.len()),
+        }
+    }
+
+    pub fn layout(&self, stripe: usize) -> StripeLayout {
+        let n = self.disks.len();
+        match self.level {
+            RaidLevel::Raid0 => StripeLayout {
+                data: (0..n).collect(),
+                p: None,
+                q: None,
+            },
+            // the first disk stands for all of them
+            RaidLevel::Raid1 => StripeLayout {
+                data: vec![0],
+                p: None,
+                q: None,
+            },
+            RaidLevel::Raid4 => StripeLayout {
+                data: (0..n - 1).collect(),
+                p: Some(n - 1),
+                q: None,
+            },
+            // parity moves one disk to the left every stripe, the data starts right of it
+            RaidLevel::Raid5 => {
+                let p = n - 1 - stripe % n;
+                StripeLayout {
+                    data: (1..n).map(|i| (p + i) % n).collect(),
+                    p: Some(p),
+                    q: None,
+                }
+            }
+            RaidLevel::Raid6 => {
+                let p = n - 1 - stripe % n;
+                StripeLayout {
+                    data: (2..n).map(|i| (p + i) % n).collect(),
+                    p: Some(p),
+                    q: Some((p + 1) % n),
+                }
+            }
+        }
+    }
+
+    // stripe and position in it
+    fn locate(&self, block: BlockId) -> (usize, usize) {
+        assert!(
+            block < self.block_count(),
+            "block {} is not on the array",
+            block
+        );
+        (
+            block / self.data_per_stripe(),
+            block % self.data_per_stripe(),
+        )
+    }
+
+    pub fn fail(&mut self, disk: usize) {
+        self.failed[disk] = true;
+    }
+
+    pub fn failed_disks(&self) -> Vec<usize> {
+        (0..self.disks.len()).filter(|d| self.failed[*d]).collect()
+    }
+
+    // swaps in a fresh disk for a failed one, `rebuild` fills it
+    pub fn replace(&mut self, disk: usize, fresh: D) -> D {
+        assert!(self.failed[disk], "disk {} didn't fail", disk);
+        std::mem::replace(&mut self.disks[disk], fresh)
+    }
+
+    fn read(&mut self, disk: usize, stripe: usize) -> Vec<u8> {
+        assert!(!self.failed[disk], "disk {} failed", disk);
+        self.disks[disk].read_block(stripe)
+    }
+
+    fn write(&mut self, disk: usize, stripe: usize, data: &[u8]) {
+        if !self.failed[disk] {
+            self.disks[disk].write_block(stripe, data);
+        }
+    }
+
+    fn syndromes(&self, data: &[Vec<u8>]) -> (Vec<u8>, Vec<u8>) {
+        let mut p = vec![0; self.block_size()];
+        let mut q = vec![0; self.block_size()];
+        for (i, block) in data.iter().enumerate() {
+            xor_into(&mut p, block);
+            xor_into(&mut q, &scaled(block, gf_pow(2, i)));
+        }
+        (p, q)
+    }
+
+    // every data block of the stripe, the ones on failed disks recovered. reads only what the
+    // recovery needs
+    fn read_stripe(&mut self, stripe: usize) -> Vec<Vec<u8>> {
+        if self.level == RaidLevel::Raid1 {
+            let disk = (0..self.disks.len())
+                .find(|d| !self.failed[*d])
+                .expect("every mirror failed, the data is lost");
+            return vec![self.read(disk, stripe)];
+        }
+        let layout = self.layout(stripe);
+        let mut data = layout
+            .data
+            .iter()
+            .map(|disk| (!self.failed[*disk]).then(|| self.read(*disk, stripe)))
+            .collect_vec();
+        let missing = (0..data.len()).filter(|i| data[*i].is_none()).collect_vec();
+        let live = |disk: Option<usize>| disk.filter(|d| !self.failed[*d]);
+        let (p_disk, q_disk) = (live(layout.p), live(layout.q));
+        // the syndromes of what's there, `missing` counts as zeros
+        let (p_rest, q_rest) = self.syndromes(
+            &data
+                .iter()
+                .map(|d| d.clone().unwrap_or_else(|| vec![0; self.block_size()]))
+                .collect_vec(),
+        );
+        match (missing.as_slice(), p_disk, q_disk) {
+            (&[], _, _) => {}
+            (&[x], Some(p_disk), _) => {
+                let mut block = self.read(p_disk, stripe);
+                xor_into(&mut block, &p_rest);
+                data[x] = Some(block);
+            }
+            // P is gone as well: Q + Q_rest == g^x D_x
+            (&[x], None, Some(q_disk)) => {
+                let mut block = self.read(q_disk, stripe);
+                xor_into(&mut block, &q_rest);
+                data[x] = Some(scaled(&block, gf_inv(gf_pow(2, x))));
+            }
+            // two equations, two unknowns:
+            //   D_x + D_y = P + P_rest          g^x D_x + g^y D_y = Q + Q_rest
+            (&[x, y], Some(p_disk), Some(q_disk)) => {
+                let mut p_xy = self.read(p_disk, stripe);
+                xor_into(&mut p_xy, &p_rest);
+                let mut q_xy = self.read(q_disk, stripe);
+                xor_into(&mut q_xy, &q_rest);
+                let g_yx = gf_pow(2, y - x);
+                let denominator = gf_inv(g_yx ^ 1);
+                let a = gf_mul(g_yx, denominator);
+                let b = gf_mul(gf_inv(gf_pow(2, x)), denominator);
+                let mut d_x = scaled(&p_xy, a);
+                xor_into(&mut d_x, &scaled(&q_xy, b));
+                let mut d_y = p_xy;
+                xor_into(&mut d_y, &d_x);
+                data[x] = Some(d_x);
+                data[y] = Some(d_y);
+            }
+            _ => panic!(
+                "stripe {}: disks {:?} failed, more than {:?} survives",
+                stripe,
+                self.failed_disks(),
+                self.level
+            ),
+        }
+        data.into_iter().map(Option::unwrap).collect_vec()
+    }
+
+    // every block of the stripe from `data`, skipping failed disks
+    fn write_stripe_blocks(&mut self, stripe: usize, data: &[Vec<u8>]) {
+        if self.level == RaidLevel::Raid1 {
+            for disk in 0..self.disks.len() {
+                self.write(disk, stripe, &data[0]);
+            }
+            return;
+        }
+        let layout = self.layout(stripe);
+        let (p, q) = self.syndromes(data);
+        for (disk, block) in layout.data.iter().zip(data) {
+            self.write(*disk, stripe, block);
+        }
+        if let Some(disk) = layout.p {
+            self.write(disk, stripe, &p);
+        }
+        if let Some(disk) = layout.q {
+            self.write(disk, stripe, &q);
+        }
+    }
+
+    // a full stripe at once: no reads at all, the parity follows from the new data
+    pub fn write_stripe(&mut self, stripe: usize, data: &[Vec<u8>]) {
+        assert_eq!(data.len(), self.data_per_stripe(), "not a full stripe");
+        let data = data
+            .iter()
+            .map(|d| {
+                let mut block = d.clone();
+                block.resize(self.block_size(), 0);
+                block
+            })
+            .collect_vec();
+        self.write_stripe_blocks(stripe, &data);
+    }
+
+    // the replaced disks get their content back, stripe by stripe
+    pub fn rebuild(&mut self) {
+        let rebuilt = self.failed_disks();
+        let disk_blocks = self.disks[0].block_count();
+        for stripe in 0..disk_blocks {
+            let data = self.read_stripe(stripe);
+            // only the new disks get written
+            let failed = std::mem::replace(&mut self.failed, vec![true; self.disks.len()]);
+            for disk in &rebuilt {
+                self.failed[*disk] = false;
+            }
+            self.write_stripe_blocks(stripe, &data);
+            self.failed = failed;
+        }
+        for disk in rebuilt {
+            self.failed[disk] = false;
+        }
+    }
+}
+
+impl<D: BlockDevice> BlockDevice for Raid<D> {
+    fn block_size(&self) -> usize {
+        self.disks[0].block_size()
+    }
+
+    fn block_count(&self) -> usize {
+        self.disks[0].block_count() * self.data_per_stripe()
+    }
+
+    fn read_block(&mut self, id: BlockId) -> Vec<u8> {
+        let (stripe, position) = self.locate(id);
+        match self.level {
+            RaidLevel::Raid1 => self.read_stripe(stripe).remove(0),
+            _ => {
+                let disk = self.layout(stripe).data[position];
+                if self.failed[disk] {
+                    self.read_stripe(stripe).swap_remove(position)
+                } else {
+                    self.read(disk, stripe)
+                }
+            }
+        }
+    }
+
+    // read-modify-write: the new parity follows from the old data, the old parity and the new
+    // data, whatever the number of disks. with a failed disk in the stripe, the whole stripe gets
+    // recovered and written again instead
+    fn write_block(&mut self, id: BlockId, data: &[u8]) {
+        let (stripe, position) = self.locate(id);
+        let mut new = data.to_vec();
+        new.resize(self.block_size(), 0);
+        let layout = self.layout(stripe);
+        let degraded = layout
+            .data
+            .iter()
+            .chain(&layout.p)
+            .chain(&layout.q)
+            .any(|d| self.failed[*d]);
+        match self.level {
+            RaidLevel::Raid0 => self.write(layout.data[position], stripe, &new),
+            RaidLevel::Raid1 => self.write_stripe_blocks(stripe, &[new]),
+            _ if degraded => {
+                let mut blocks = self.read_stripe(stripe);
+                blocks[position] = new;
+                self.write_stripe_blocks(stripe, &blocks);
+            }
+            _ => {
+                let disk = layout.data[position];
+                let mut delta = self.read(disk, stripe);
+                xor_into(&mut delta, &new);
+                self.write(disk, stripe, &new);
+                if let Some(p_disk) = layout.p {
+                    let mut p = self.read(p_disk, stripe);
+                    xor_into(&mut p, &delta);
+                    self.write(p_disk, stripe, &p);
+                }
+                if let Some(q_disk) = layout.q {
+                    let mut q = self.read(q_disk, stripe);
+                    xor_into(&mut q, &scaled(&delta, gf_pow(2, position)));
+                    self.write(q_disk, stripe, &q);
+                }
+            }
+        }
+    }
+
+    // of all disks together
+    fn stats(&self) -> IoStats {
+        self.disks
+            .iter()
+            .fold(IoStats::default(), |sum, d| IoStats {
+                reads: sum.reads + d.stats().reads,
+                writes: sum.writes + d.stats().writes,
+            })
+    }
+}
+
+// the usual exam picture, one row per stripe
+fn layout_diagram<D: BlockDevice>(raid: &Raid<D>, stripes: usize) -> String {
+    let n = raid.disks.len();
+    let mut lines = vec![(0..n)
+        .map(|d| format!("{:>6}", format!("disk{}", d)))
+        .join("")];
+    for stripe in 0..stripes {
+        let layout = raid.layout(stripe);
+        let mut cells = vec![String::new(); n];
+        let first = stripe * raid.data_per_stripe();
+        match raid.level {
+            RaidLevel::Raid1 => cells.fill(format!("{}", first)),
+            _ => {
+                for (i, disk) in layout.data.iter().enumerate() {
+                    cells[*disk] = format!("{}", first + i);
+                }
+            }
+        }
+        if let Some(p) = layout.p {
+            cells[p] = format!("P{}", stripe);
+        }
+        if let Some(q) = layout.q {
+            cells[q] = format!("Q{}", stripe);
+        }
+        lines.push(cells.iter().map(|c| format!("{:>6}", c)).join(""));
+    }
+    lines.join("\n")
+}
+
+fn array(level: RaidLevel, disks: usize) -> Raid<MemoryDisk> {
+    Raid::new(level, vec![MemoryDisk::new(512, 64); disks])
+}
+
+fn cost<D: BlockDevice>(raid: &mut Raid<D>, op: impl FnOnce(&mut Raid<D>)) -> IoStats {
+    let before = raid.stats();
+    op(raid);
+    let after = raid.stats();
+    IoStats {
+        reads: after.reads - before.reads,
+        writes: after.writes - before.writes,
+    }
+}
+
+fn random_block(rng: &mut StdRng) -> Vec<u8> {
+    (0..512).map(|_| rng.gen()).collect_vec()
+}
+
+pub fn test_raid() {
+    println!("\n## RAID\n");
+
+    let levels = [
+        (RaidLevel::Raid0, 4),
+        (RaidLevel::Raid1, 2),
+        (RaidLevel::Raid4, 4),
+        (RaidLevel::Raid5, 4),
+        (RaidLevel::Raid6, 5),
+    ];
+
+    println!("\n### Layouts\n");
+    for (level, disks) in levels {
+        let raid = array(level, disks);
+        println!(
+            "{:?}, {} disks, {} of {} blocks usable, failed disks it survives: {}",
+            level,
+            disks,
+            raid.block_count(),
+            disks * 64,
+            level.tolerates(disks)
+        );
+        println!("{}\n", layout_diagram(&raid, disks));
+    }
+    assert_eq!(
+        array(RaidLevel::Raid5, 4).layout(1),
+        StripeLayout {
+            data: vec![3, 0, 1],
+            p: Some(2),
+            q: None
+        }
+    );
+
+    println!("\n### Written blocks read back\n");
+    for (level, disks) in levels {
+        let mut raid = array(level, disks);
+        // every block its own content, then every other one again
+        let content =
+            |id: BlockId, round: u8| (0..512).map(|i| (id * 7 + i) as u8 ^ round).collect_vec();
+        for id in 0..raid.block_count() {
+            raid.write_block(id, &content(id, 0));
+        }
+        for id in (0..raid.block_count()).step_by(2) {
+            raid.write_block(id, &content(id, 1));
+        }
+        for id in 0..raid.block_count() {
+            let round = u8::from(id.is_multiple_of(2));
+            assert_eq!(raid.read_block(id), content(id, round), "{:?}", level);
+        }
+        println!("{:?}: all {} blocks as written", level, raid.block_count());
+    }
+
+    println!("\n### What a block costs\n");
+    println!(
+        "{:<8} {:>18} {:>18} {:>22} {:>20}",
+        "", "read", "small write", "full stripe write", "degraded read"
+    );
+    let mut rng = StdRng::seed_from_u64(44);
+    let mut small_writes = Vec::new();
+    for (level, disks) in levels {
+        let mut raid = array(level, disks);
+        let read = cost(&mut raid, |r| {
+            r.read_block(5);
+        });
+        let small = cost(&mut raid, |r| r.write_block(5, &[1; 512]));
+        let stripe = (0..raid.data_per_stripe())
+            .map(|_| random_block(&mut rng))
+            .collect_vec();
+        let full = cost(&mut raid, |r| r.write_stripe(2, &stripe));
+        let degraded = if level.tolerates(disks) > 0 {
+            // the disk that holds block 5
+            let disk = raid.layout(raid.locate(5).0).data[raid.locate(5).1];
+            raid.fail(disk);
+            let io = cost(&mut raid, |r| assert_eq!(r.read_block(5), vec![1; 512]));
+            format!("{} r", io.reads)
+        } else {
+            "data lost".to_owned()
+        };
+        let show = |io: IoStats| format!("{} r + {} w", io.reads, io.writes);
+        println!(
+            "{:<8} {:>18} {:>18} {:>22} {:>20}",
+            format!("{:?}", level),
+            show(read),
+            show(small),
+            show(full),
+            degraded
+        );
+        small_writes.push(small);
+    }
+    // the classic small write penalty: 1, 2, 4, 4 and 6 disk accesses
+    assert_eq!(
+        small_writes.iter().map(IoStats::total).collect_vec(),
+        [1, 2, 4, 4, 6]
+    );
+
+    println!("\n### 1000 random small writes, writes per disk\n");
+    for level in [RaidLevel::Raid4, RaidLevel::Raid5] {
+        let mut raid = array(level, 4);
+        let mut rng = StdRng::seed_from_u64(4);
+        for _ in 0..1000 {
+            let block = rng.gen_range(0..raid.block_count());
+            raid.write_block(block, &random_block(&mut rng));
+        }
+        let writes = raid.disk_stats().iter().map(|s| s.writes).collect_vec();
+        println!("{:?}: {:?}", level, writes);
+        if level == RaidLevel::Raid4 {
+            // the parity disk takes part in every single write, it's the bottleneck
+            assert_eq!(writes[3], 1000);
+        } else {
+            assert!(writes.iter().all(|w| *w < 700));
+        }
+    }
+
+    println!("\n### Failure and rebuild\n");
+    for (level, disks) in levels.into_iter().skip(1) {
+        let mut raid = array(level, disks);
+        let mut rng = StdRng::seed_from_u64(45);
+        let content = (0..raid.block_count())
+            .map(|_| random_block(&mut rng))
+            .collect_vec();
+        for (i, block) in content.iter().enumerate() {
+            raid.write_block(i, block);
+        }
+        let failing = (0..level.tolerates(disks))
+            .map(|i| i * 2 % disks)
+            .collect_vec();
+        for disk in &failing {
+            raid.fail(*disk);
+        }
+        // degraded, but everything is still there, writes included
+        let degraded = cost(&mut raid, |r| {
+            for (i, block) in content.iter().enumerate() {
+                assert_eq!(r.read_block(i), *block, "{:?}, block {}", level, i);
+            }
+        });
+        raid.write_block(0, &[9; 512]);
+        for disk in &failing {
+            raid.replace(*disk, MemoryDisk::new(512, 64));
+        }
+        let rebuild = cost(&mut raid, Raid::rebuild);
+        assert!(raid.failed_disks().is_empty());
+        assert_eq!(raid.read_block(0), vec![9; 512]);
+        for (i, block) in content.iter().enumerate().skip(1) {
+            assert_eq!(raid.read_block(i), *block);
+        }
+        println!(
+            "{:?}, disks {:?} failed: reading all {} blocks took {} disk reads, the rebuild {} reads and {} writes",
+            level,
+            failing,
+            content.len(),
+            degraded.reads,
+            rebuild.reads,
+            rebuild.writes
+        );
+    }
+
+    // after the rebuild, the array survives the failure of any other set of disks
+    let mut raid = array(RaidLevel::Raid6, 5);
+    let mut rng = StdRng::seed_from_u64(46);
+    let content = (0..raid.block_count())
+        .map(|_| random_block(&mut rng))
+        .collect_vec();
+    for (i, block) in content.iter().enumerate() {
+        raid.write_block(i, block);
+    }
+    for pair in (0..5).combinations(2) {
+        let mut degraded = Raid::new(RaidLevel::Raid6, raid.disks.clone());
+        degraded.fail(pair[0]);
+        degraded.fail(pair[1]);
+        for (i, block) in content.iter().enumerate() {
+            assert_eq!(degraded.read_block(i), *block);
+        }
+    }
+    println!("RAID6 survives each of the 10 pairs of failed disks out of 5");
+}
@@ -0,0 +1,493 @@
+use std::collections::HashMap;
+
+use itertools::Itertools;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use super::block_device::{BlockDevice, BlockId, IoStats};
+use crate::cap05_disk_scheduling::{
+    CLook, Direction, DiskParameters, DiskScheduler, Fcfs, Look, Scan, Sstf,
+};
+
+// NAND flash behind a page-mapped flash translation layer. flash is read and programmed a page at
+// a time, but a programmed page can only be erased together with its whole erase block. so an
+// overwrite goes to a fresh page and the old one turns invalid. when the free blocks run out,
+// garbage collection picks the block with the fewest valid pages, copies those to the open
+// block and erases it. every copy is a program the host never asked for: write amplification.
+// each block survives only a few thousand erases, wear leveling spreads them:
+// dynamic picks the least worn free block, static also moves cold data off blocks that hardly
+// ever get erased. the device shows `block_count` pages, the rest is overprovisioning.
+// there's no head and no rotation, what a request costs doesn't depend on the address
+
+// garbage collection starts below that many free blocks
+const GC_LOW: usize = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PageState {
+    Free,
+    // holds the given logical page
+    Valid(BlockId),
+    Invalid,
+}
+
+// all in microseconds
+#[derive(Debug, Clone, Copy)]
+pub struct FlashTiming {
+    pub read: f64,
+    pub program: f64,
+    pub erase: f64,
+}
+
+impl FlashTiming {
+    // about what MLC NAND takes
+    pub fn mlc() -> Self {
+        FlashTiming {
+            read: 25.0,
+            program: 200.0,
+            erase: 1500.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WearLeveling {
+    // the block erased last is the next one written
+    None,
+    Dynamic,
+    // dynamic, and whenever the erase counts are further apart than `threshold`, the data of the
+    // least worn block moves to a worn one
+    Static { threshold: usize },
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FlashStats {
+    pub host_writes: usize,
+    pub trims: usize,
+    pub page_reads: usize,
+    pub page_programs: usize,
+    pub erases: usize,
+    // valid pages garbage collection copied
+    pub gc_copies: usize,
+    // valid pages static wear leveling copied
+    pub wear_copies: usize,
+    pub busy_us: f64,
+    pub max_write_us: f64,
+}
+
+impl FlashStats {
+    pub fn write_amplification(&self) -> f64 {
+        self.page_programs as f64 / self.host_writes as f64
+    }
+}
+
+#[derive(Debug, Clone)]
+struct EraseBlock {
+    pages: Vec<PageState>,
+    // pages are programmed in order
+    written: usize,
+    valid: usize,
+    erases: usize,
+}
+
+pub struct Ssd {
+    page_size: usize,
+    pages_per_block: usize,
+    timing: FlashTiming,
+    wear_leveling: WearLeveling,
+    blocks: Vec<EraseBlock>,
+    // content of every physical page
+    data: Vec<Vec<u8>>,
+    // logical page -> physical page
+    map: Vec<Option<usize>>,
+    free: Vec<usize>,
+    // the block new pages go to
+    active: usize,
+    relocating: bool,
+    stats: FlashStats,
+    io: IoStats,
+}
+
+impl Ssd {
+    pub fn new(
+        page_size: usize,
+        pages_per_block: usize,
+        block_count: usize,
+        overprovisioning: f64,
+        timing: FlashTiming,
+        wear_leveling: WearLeveling,
+    ) -> Self {
+        let physical = block_count * pages_per_block;
+        let logical = (physical as f64 * (1.0 - overprovisioning)) as usize;
+        // garbage collection needs a block with an invalid page in it, even when the device is full
+        assert!(
+            logical < (block_count - GC_LOW - 1) * pages_per_block,
+            "not enough overprovisioning for garbage collection"
+        );
+        let block = EraseBlock {
+            pages: vec![PageState::Free; pages_per_block],
+            written: 0,
+            valid: 0,
+            erases: 0,
+        };
+        Ssd {
+            page_size,
+            pages_per_block,
+            timing,
+            wear_leveling,
+            blocks: vec![block; block_count],
+            data: vec![Vec::new(); physical],
+            map: vec![None; logical],
+            free: (1..block_count).rev().collect(),
+            active: 0,
+            relocating: false,
+            stats: FlashStats::default(),
+            io: IoStats::default(),
+        }
+    }
+
+    pub fn flash_stats(&self) -> FlashStats {
+        self.stats
+    }
+
+    pub fn erase_counts(&self) -> Vec<usize> {
+        self.blocks.iter().map(|b| b.erases).collect_vec()
+    }
+
+    // the file system tells the device that a page's content isn't needed anymore, so garbage
+    // collection doesn't have to copy it. reading it afterwards gives zeros
+    pub fn trim(&mut self, page: BlockId) {
+        assert!(page < self.map.len(), "page {} is not on the device", page);
+        self.invalidate(page);
+        self.stats.trims += 1;
+    }
+
+    fn invalidate(&mut self, page: BlockId) {
+        if let Some(physical) = self.map[page].take() {
+            let block = &mut self.blocks[physical / self.pages_per_block];
+            block.pages[physical % self.pages_per_block] = PageState::Invalid;
+            block.valid -= 1;
+        }
+    }
+
+    fn program(&mut self, page: BlockId, data: Vec<u8>) {
+        if self.blocks[self.active].written == self.pages_per_block {
+            self.open_block();
+        }
+        let block = &mut self.blocks[self.active];
+        let physical = self.active * self.pages_per_block + block.written;
+        block.pages[block.written] = PageState::Valid(page);
+        block.written += 1;
+        block.valid += 1;
+        self.data[physical] = data;
+        self.map[page] = Some(physical);
+        self.stats.page_programs += 1;
+        self.stats.busy_us += self.timing.program;
+    }
+
+    fn open_block(&mut self) {
+        let next = match self.wear_leveling {
+            WearLeveling::None => self.free.len() - 1,
+            WearLeveling::Dynamic | WearLeveling::Static { .. } => (0..self.free.len())
+                .min_by_key(|i| self.blocks[self.free[*i]].erases)
+                .unwrap(),
+        };
+        self.active = self.free.remove(next);
+        // the copies of garbage collection go to the active block as well, so they can open a
+        // block from the reserve themselves
+        while self.free.len() < GC_LOW && !self.relocating {
+            let victim = (0..self.blocks.len())
+                .filter(|b| *b != self.active && self.blocks[*b].written == self.pages_per_block)
+                .min_by_key(|b| self.blocks[*b].valid)
+                .expect("no block to collect");
+            assert!(
+                self.blocks[victim].valid < self.pages_per_block,
+                "every block is full of valid pages"
+            );
+            self.relocate(victim, false);
+        }
+    }
+
+    // copies the valid pages of a full block elsewhere and erases it
+    fn relocate(&mut self, block: usize, wear: bool) {
+        self.relocating = true;
+        for i in 0..self.pages_per_block {
+            if let PageState::Valid(page) = self.blocks[block].pages[i] {
+                let data = self.data[block * self.pages_per_block + i].clone();
+                self.stats.page_reads += 1;
+                self.stats.busy_us += self.timing.read;
+                self.invalidate(page);
+                self.program(page, data);
+                if wear {
+                    self.stats.wear_copies += 1;
+                } else {
+                    self.stats.gc_copies += 1;
+                }
+            }
+        }
+        self.relocating = false;
+        let erased = &mut self.blocks[block];
+        erased.pages.fill(PageState::Free);
+        erased.written = 0;
+        erased.valid = 0;
+        erased.erases += 1;
+        self.stats.erases += 1;
+        self.stats.busy_us += self.timing.erase;
+        self.free.push(block);
+        if let WearLeveling::Static { threshold } = self.wear_leveling {
+            if !wear {
+                self.level_wear(threshold);
+            }
+        }
+    }
+
+    // the least worn full block usually holds data nobody writes, it's wasted as long as it does
+    fn level_wear(&mut self, threshold: usize) {
+        let most = self.blocks.iter().map(|b| b.erases).max().unwrap();
+        let coldest = (0..self.blocks.len())
+            .filter(|b| *b != self.active && self.blocks[*b].written == self.pages_per_block)
+            .min_by_key(|b| self.blocks[*b].erases);
+        if let Some(coldest) = coldest {
+            if self.blocks[coldest].erases + threshold < most {
+                self.relocate(coldest, true);
+            }
+        }
+    }
+}
+
+impl BlockDevice for Ssd {
+    fn block_size(&self) -> usize {
+        self.page_size
+    }
+
+    fn block_count(&self) -> usize {
+        self.map.len()
+    }
+
+    // never written or trimmed pages read as zeros
+    fn read_block(&mut self, id: BlockId) -> Vec<u8> {
+        assert!(id < self.map.len(), "page {} is not on the device", id);
+        self.io.reads += 1;
+        match self.map[id] {
+            Some(physical) => {
+                self.stats.page_reads += 1;
+                self.stats.busy_us += self.timing.read;
+                let mut data = self.data[physical].clone();
+                data.resize(self.page_size, 0);
+                data
+            }
+            None => vec![0; self.page_size],
+        }
+    }
+
+    fn write_block(&mut self, id: BlockId, data: &[u8]) {
+        assert!(id < self.map.len(), "page {} is not on the device", id);
+        assert!(
+            data.len() <= self.page_size,
+            "{} bytes don't fit into a page of {}",
+            data.len(),
+            self.page_size
+        );
+        self.io.writes += 1;
+        self.stats.host_writes += 1;
+        let before = self.stats.busy_us;
+        self.invalidate(id);
+        self.program(id, data.to_vec());
+        self.stats.max_write_us = self.stats.max_write_us.max(self.stats.busy_us - before);
+    }
+
+    fn stats(&self) -> IoStats {
+        self.io
+    }
+}
+
+fn ssd(overprovisioning: f64, wear_leveling: WearLeveling) -> Ssd {
+    Ssd::new(
+        512,
+        32,
+        64,
+        overprovisioning,
+        FlashTiming::mlc(),
+        wear_leveling,
+    )
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Workload {
+    Sequential,
+    Uniform,
+    // 90% of the writes go to 10% of the pages
+    HotCold,
+    // uniform, but the file system trimmed half of the pages before
+    UniformTrimmed,
+}
+
+// fills the device, then overwrites 20 times its size. the stats are those of the overwrites
+fn run(device: &mut Ssd, workload: Workload, seed: u64) -> FlashStats {
+    let pages = device.block_count();
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut content = HashMap::new();
+    for page in 0..pages {
+        device.write_block(page, &[page as u8; 8]);
+        content.insert(page, page as u8);
+    }
+    let mut live = (0..pages).collect_vec();
+    if let Workload::UniformTrimmed = workload {
+        live.retain(|page| page % 2 == 1);
+        for page in (0..pages).step_by(2) {
+            device.trim(page);
+            content.insert(page, 0);
+        }
+    }
+    let before = device.flash_stats();
+    for i in 0..20 * pages {
+        let page = match workload {
+            Workload::Sequential => i % pages,
+            Workload::Uniform | Workload::UniformTrimmed => live[rng.gen_range(0..live.len())],
+            Workload::HotCold if rng.gen_bool(0.9) => rng.gen_range(0..pages / 10),
+            Workload::HotCold => rng.gen_range(pages / 10..pages),
+        };
+        device.write_block(page, &[i as u8; 8]);
+        content.insert(page, i as u8);
+    }
+    for (page, value) in content {
+        assert_eq!(device.read_block(page)[..8], [value; 8]);
+    }
+    let after = device.flash_stats();
+    FlashStats {
+        host_writes: after.host_writes - before.host_writes,
+        trims: after.trims - before.trims,
+        page_reads: after.page_reads - before.page_reads,
+        page_programs: after.page_programs - before.page_programs,
+        erases: after.erases - before.erases,
+        gc_copies: after.gc_copies - before.gc_copies,
+        wear_copies: after.wear_copies - before.wear_copies,
+        busy_us: after.busy_us - before.busy_us,
+        max_write_us: after.max_write_us,
+    }
+}
+
+pub fn test_ssd() {
+    println!("\n## SSD\n");
+
+    println!("\n### Probeklausur Aufgabe 4 on an SSD\n");
+    let requests = vec![2, 38, 19, 34, 9, 12, 40, 50];
+    let disk = DiskParameters {
+        min_cylinder: 0,
+        max_cylinder: 50,
+        head: 11,
+        direction: Direction::Up,
+    };
+    let schedulers: Vec<Box<dyn DiskScheduler>> = vec![
+        Box::new(Fcfs),
+        Box::new(Sstf),
+        Box::new(Scan),
+        Box::new(Look),
+        Box::new(CLook),
+    ];
+    let mut device = ssd(0.1, WearLeveling::Dynamic);
+    for page in 0..=50 {
+        device.write_block(page, &[page as u8; 8]);
+    }
+    let mut times = Vec::new();
+    for scheduler in &schedulers {
+        let movement = scheduler.schedule(&disk, &requests);
+        // the stops without the start and the edges are the order the requests are served in
+        let order = movement.0[1..]
+            .iter()
+            .filter(|c| requests.contains(c))
+            .unique()
+            .collect_vec();
+        let before = device.flash_stats().busy_us;
+        for page in &order {
+            device.read_block(**page);
+        }
+        let time = device.flash_stats().busy_us - before;
+        println!(
+            "{:<6} {:>4} cylinders on the disk, {:>4}µs on the SSD   {:?}",
+            scheduler.name(),
+            movement.total_seek_distance(),
+            time,
+            order
+        );
+        times.push(time);
+    }
+    // any order is as good as any other, sorting requests by address buys nothing
+    assert!(times.iter().all_equal());
+
+    println!("\n### Write amplification, 20 overwrites of the whole device\n");
+    println!(
+        "{:<16} {:>8} {:>8} {:>9} {:>10} {:>16}",
+        "", "spare", "WA", "erases", "gc copies", "worst write"
+    );
+    let mut amplification = HashMap::new();
+    for workload in [
+        Workload::Sequential,
+        Workload::Uniform,
+        Workload::HotCold,
+        Workload::UniformTrimmed,
+    ] {
+        for overprovisioning in [0.07, 0.25] {
+            let mut device = ssd(overprovisioning, WearLeveling::Dynamic);
+            let stats = run(&mut device, workload, 46);
+            println!(
+                "{:<16} {:>7.0}% {:>8.2} {:>9} {:>10} {:>14.0}µs",
+                format!("{:?}", workload),
+                overprovisioning * 100.0,
+                stats.write_amplification(),
+                stats.erases,
+                stats.gc_copies,
+                stats.max_write_us
+            );
+            amplification.insert(
+                (
+                    format!("{:?}", workload),
+                    (overprovisioning * 100.0) as usize,
+                ),
+                stats.write_amplification(),
+            );
+        }
+    }
+    let wa = |workload: &str, spare: usize| amplification[&(workload.to_owned(), spare)];
+    // sequential overwrites invalidate whole blocks, there's nothing to copy
+    assert_eq!(wa("Sequential", 7), 1.0);
+    assert!(wa("Uniform", 25) < wa("Uniform", 7));
+    // trimmed pages are free space the device knows about
+    assert!(wa("UniformTrimmed", 7) < wa("Uniform", 7));
+
+    println!("\n### Wear leveling, hot and cold data\n");
+    let mut spreads = Vec::new();
+    for wear_leveling in [
+        WearLeveling::None,
+        WearLeveling::Dynamic,
+        WearLeveling::Static { threshold: 8 },
+    ] {
+        let mut device = ssd(0.1, wear_leveling);
+        let stats = run(&mut device, Workload::HotCold, 47);
+        let erases = device.erase_counts();
+        let (min, max) = erases.iter().minmax().into_option().unwrap();
+        let mean = erases.iter().sum::<usize>() as f64 / erases.len() as f64;
+        println!(
+            "{:<26} WA {:.2}, {} wear copies, erases per block: min {}, mean {:.1}, max {}",
+            format!("{:?}", wear_leveling),
+            stats.write_amplification(),
+            stats.wear_copies,
+            min,
+            mean,
+            max
+        );
+        // blocks per bucket of 10 erases
+        let histogram = erases.iter().counts_by(|e| e / 10);
+        for bucket in 0..=max / 10 {
+            let count = histogram.get(&bucket).copied().unwrap_or(0);
+            println!(
+                "  {:>3}..{:<3} {:>3} {}",
+                bucket * 10,
+                bucket * 10 + 9,
+                count,
+                "#".repeat(count)
+            );
+        }
+        spreads.push(max - min);
+    }
+    // the worst block decides how long the device lives
+    assert!(spreads[2] < spreads[1] && spreads[2] < spreads[0]);
+}
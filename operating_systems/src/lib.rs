@@ -0,0 +1,9 @@
+#![feature(generators)]
+#![feature(generator_trait)]
+#![feature(iter_collect_into)]
+#![feature(drain_filter)]
+
+pub mod cap03_scheduling;
+pub mod cap04_memory_management;
+pub mod cap05_disk_scheduling;
+pub mod cap06_filesystems;
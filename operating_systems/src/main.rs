@@ -1,21 +1,54 @@
-#![feature(generators)]
-#![feature(iter_from_generator)]
-#![feature(iter_collect_into)]
-#![feature(drain_filter)]
 #![feature(trait_alias)]
 #![feature(unboxed_closures)]
 #![feature(fn_traits)]
 
-use std::iter;
-
 use itertools::Itertools;
-
-pub mod cap03_scheduling;
+use operating_systems::{
+    cap03_scheduling, cap04_memory_management,
+    cap05_disk_scheduling::{self, Direction, DiskParameters, DiskScheduler, Fcfs, Look, Sstf},
+    cap06_filesystems::{self, block_device::FreeSpaceMap, free_space::FreeSpaceManager},
+};
 
 fn main() {
-    probeklausur();
-    cap03_scheduling::test_round_robin();
-    cap03_scheduling::test_rate_monotonic();
+    let args = std::env::args().skip(1).collect_vec();
+    match args.first().map(String::as_str) {
+        Some("proc") => {
+            cap03_scheduling::proc_trace::replay(&args[1..]).expect("could not trace processes")
+        }
+        Some("fat") => {
+            cap06_filesystems::fat_image::cli(&args[1..]).expect("could not read the FAT image")
+        }
+        Some("ext2") => {
+            cap06_filesystems::ext2_image::cli(&args[1..]).expect("could not read the ext2 image")
+        }
+        _ => {
+            probeklausur();
+            cap03_scheduling::test_round_robin();
+            cap03_scheduling::test_rate_monotonic();
+            cap03_scheduling::test_deadline_monotonic();
+            cap03_scheduling::test_huge_hyperperiod();
+            cap03_scheduling::dvfs::test_dvfs();
+            cap03_scheduling::green_threads::test_green_threads();
+            cap04_memory_management::test_page_replacement();
+            cap04_memory_management::belady::test_belady();
+            cap05_disk_scheduling::test_disk_scheduling();
+            cap05_disk_scheduling::test_timed_disk_scheduling();
+            cap06_filesystems::allocation::test_allocation();
+            cap06_filesystems::inode::test_inode();
+            cap06_filesystems::filesystem::test_filesystem();
+            cap06_filesystems::free_space::test_free_space();
+            cap06_filesystems::journal::test_journaling();
+            cap06_filesystems::buffer_cache::test_buffer_cache();
+            cap06_filesystems::fsck::test_fsck();
+            cap06_filesystems::fat_image::test_fat_image();
+            cap06_filesystems::ext2_image::test_ext2_image();
+            cap06_filesystems::raid::test_raid();
+            cap06_filesystems::ssd::test_ssd();
+            cap06_filesystems::lfs::test_lfs();
+            cap06_filesystems::permissions::test_permissions();
+            cap06_filesystems::open_files::test_links();
+        }
+    }
 }
 
 struct Aufgabe {
@@ -31,26 +64,14 @@ fn probeklausur() {
         algo: Box::new(|| {
             // gegeben
             let block_size = 2_u64.pow(12);
-            let total_used_blocks = iter::from_generator(|| {
-                for i in 0..u64::MAX {
-                    if i < 301 || i % 2 == 1 {
-                        continue;
-                    }
-                    yield i;
-                }
-            });
+            let mut free_space = FreeSpaceMap::with_used(400, |i| i < 301 || i % 2 == 1);
             let file_size: u64 = 33 * 1024;
 
             // gesucht
-            let mut used_space: u64 = 0;
-            let mut used_blocks: Vec<u64> = Vec::new();
-            for block in total_used_blocks {
-                used_blocks.push(block);
-                used_space += block_size;
-                if used_space >= file_size {
-                    break;
-                }
-            }
+            let used_blocks = free_space
+                .allocate(file_size.div_ceil(block_size) as usize)
+                .expect("enough free blocks");
+            let used_space = used_blocks.len() as u64 * block_size;
 
             format!(
                 "file size: {}KiB, actual space used: {}KiB, {} used blocks: {:?}",
@@ -66,109 +87,17 @@ fn probeklausur() {
         which: "4".to_owned(),
         algo: Box::new(|| {
             // gegeben
-            let cylinders: Vec<usize> = vec![11, 2, 38, 19, 34, 9, 12, 40, 50];
-
-            // FCFS
-            let jumps_fcfs = cylinders
-                .iter()
-                .skip(1)
-                .zip(cylinders.iter())
-                .map(|(c1, c2)| usize::abs_diff(*c1, *c2))
-                .collect_vec();
-
-            // SSF
-            let mut cyls_tmp = cylinders.clone();
-            let mut last_diff = (0_usize, 0);
-            let jumps_ssf = iter::from_generator(|| loop {
-                let last_cyl = cyls_tmp.remove(last_diff.0);
-                if cyls_tmp.is_empty() {
-                    return;
-                }
-                let new_diff = cyls_tmp
-                    .iter()
-                    .map(|c| usize::abs_diff(*c, last_cyl))
-                    .enumerate()
-                    .reduce(|(i, diff), (i_cur, diff_cur)| {
-                        if diff_cur < diff {
-                            (i_cur, diff_cur)
-                        } else {
-                            (i, diff)
-                        }
-                    })
-                    .expect("cylinders were empty maybe?");
-                last_diff = new_diff;
-
-                yield last_diff.1;
-            })
-            .collect_vec();
-
-            // Aufzug
-
-            let mut cyls_tmp = cylinders;
-            cyls_tmp.sort();
-            let mut last_diff = (2_usize, 0);
-
-            #[derive(Clone, Copy, Debug)]
-            enum Direction {
-                Up,
-                Down,
-            }
-            use Direction::*;
-            let mut direction = Direction::Up;
-            let jumps_aufzug = iter::from_generator(|| loop {
-                /*println!(
-                    "DEBUG: processing cyl ({}, {}, diff {})",
-                    last_diff.0, cyls_tmp[last_diff.0], last_diff.1
-                );*/
-                let last_cyl = cyls_tmp.remove(last_diff.0);
-                if cyls_tmp.is_empty() {
-                    return;
-                }
-                // sort because we go in one direction only
-                let new_diff = cyls_tmp.iter();
-                // Aufzug part
-                let n = last_diff.0;
-                let make_considered = |direction| match direction {
-                    // kill previous (n == last cylinder idx). Works even after deletion. E.g. if the first was removed, we want to skip 0 etc.
-                    Up => new_diff.clone().skip(n).collect_vec(),
-                    // else stop after n
-                    Down => new_diff.clone().take(n).collect_vec(),
-                };
-                let mut considered = make_considered(direction);
-                if considered.is_empty() {
-                    direction = match direction {
-                        Down => Up,
-                        Up => Down,
-                    };
-                    considered = make_considered(direction);
-                    if considered.is_empty() {
-                        // both are empty -> we have no more elems and are finished
-                        // probably superfluous but an extra check nonetheless
-                        return;
-                    }
-                }
-                let new_diff = considered
-                    .iter()
-                    .map(|c| usize::abs_diff(**c, last_cyl))
-                    .enumerate()
-                    .reduce(|(i, diff), (i_cur, diff_cur)| {
-                        if diff_cur < diff {
-                            (i_cur, diff_cur)
-                        } else {
-                            (i, diff)
-                        }
-                    })
-                    .expect("cylinders were empty maybe?");
-                // we add the index back, because we were dealing with offsets relative to the previous element
-                let new_diff = match direction {
-                    Up => (new_diff.0 + n, new_diff.1),
-                    Down => new_diff,
-                };
-                last_diff = new_diff;
-
-                yield last_diff.1;
-            })
-            .collect_vec();
+            let requests: Vec<usize> = vec![2, 38, 19, 34, 9, 12, 40, 50];
+            let disk = DiskParameters {
+                min_cylinder: 0,
+                max_cylinder: 50,
+                head: 11,
+                direction: Direction::Up,
+            };
+
+            let jumps_fcfs = Fcfs.schedule(&disk, &requests).jumps();
+            let jumps_ssf = Sstf.schedule(&disk, &requests).jumps();
+            let jumps_aufzug = Look.schedule(&disk, &requests).jumps();
 
             format!(
                 "FCFS: {} {:?}, SSF: {} {:?}, Aufzug: {} {:?}",
@@ -10,6 +10,9 @@ use crossterm::{
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
 use itertools::Itertools;
+use operating_systems::cap05_disk_scheduling::{
+    self as disk, DiskModel, DiskParameters, DiskScheduler, Fcfs, Scan, Sstf, TimedRequest,
+};
 use ratatui::{
     layout::{Constraint, Direction, Layout},
     style::{Color, Style},
@@ -75,14 +78,176 @@ static DATASETS: Mutex<LazyCell<Vec<(Plot<Point>, Dataset)>>> = Mutex::new(LazyC
 /// presses 'q'.
 fn main() -> Result<()> {
     // calc datasets
+    let mut app = App {
+        view: View::Functions,
+        over_time: false,
+        head_movement: HeadMovementPlots::new(),
+    };
 
     // spin up term
     let mut terminal = setup_terminal().context("setup failed")?;
-    run(&mut terminal).context("app loop failed")?;
+    run(&mut terminal, &mut app).context("app loop failed")?;
     restore_terminal(&mut terminal).context("restore terminal failed")?;
     Ok(())
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum View {
+    Functions,
+    HeadMovement,
+}
+
+struct App {
+    view: View,
+    /// x axis of the head movement view: time instead of request order
+    over_time: bool,
+    head_movement: HeadMovementPlots,
+}
+
+/// Disk head position of several disk schedulers on the same request set, the way it is drawn on
+/// the whiteboard.
+struct HeadMovementPlots {
+    max_cylinder: usize,
+    /// (scheduler, points), x is the number of the stop
+    by_order: Vec<(String, Vec<Point>)>,
+    /// (scheduler, points), x is the time in ms
+    by_time: Vec<(String, Vec<Point>)>,
+}
+
+impl HeadMovementPlots {
+    fn new() -> Self {
+        // Silberschatz, all requests known up front
+        let requests = [98, 183, 37, 122, 14, 124, 65, 67];
+        let params = DiskParameters {
+            min_cylinder: 0,
+            max_cylinder: 199,
+            head: 53,
+            direction: disk::Direction::Down,
+        };
+        let model = DiskModel {
+            settle_time: 0.5,
+            seek_sqrt: 0.3,
+            seek_linear: 0.02,
+            rpm: 7200.0,
+            sectors_per_track: 500,
+            sectors_per_request: 8,
+        };
+        let timed = requests
+            .iter()
+            .enumerate()
+            .map(|(i, cylinder)| TimedRequest {
+                arrival: 0.0,
+                cylinder: *cylinder,
+                sector: i * 97 % model.sectors_per_track,
+            })
+            .collect_vec();
+
+        let schedulers: Vec<Box<dyn DiskScheduler>> =
+            vec![Box::new(Fcfs), Box::new(Sstf), Box::new(Scan)];
+        let by_order = schedulers
+            .iter()
+            .map(|s| {
+                let movement = s.schedule(&params, &requests);
+                let points = movement
+                    .0
+                    .iter()
+                    .enumerate()
+                    .map(|(i, c)| (i as Num, *c as Num))
+                    .collect_vec();
+                (
+                    format!("{} ({})", s.name(), movement.total_seek_distance()),
+                    points,
+                )
+            })
+            .collect_vec();
+        let by_time = schedulers
+            .iter()
+            .map(|s| {
+                let run = disk::simulate_timed(s.as_ref(), &params, &model, &timed);
+                let done = run.served.last().map(|s| s.finish).unwrap_or_default();
+                let points = run
+                    .head_trace
+                    .iter()
+                    .map(|(t, c)| (*t, *c as Num))
+                    .collect_vec();
+                (format!("{} ({:.1}ms)", s.name(), done), points)
+            })
+            .collect_vec();
+
+        HeadMovementPlots {
+            max_cylinder: params.max_cylinder,
+            by_order,
+            by_time,
+        }
+    }
+}
+
+fn render(frame: &mut ratatui::Frame<BackendImpl>, app: &App) {
+    match app.view {
+        View::Functions => render_default_app(frame),
+        View::HeadMovement => render_head_movement(frame, app),
+    }
+}
+
+fn render_head_movement(frame: &mut ratatui::Frame<BackendImpl>, app: &App) {
+    let (plots, x_title) = if app.over_time {
+        (&app.head_movement.by_time, "time [ms]")
+    } else {
+        (&app.head_movement.by_order, "request")
+    };
+    let colors = [
+        Color::Yellow,
+        Color::Cyan,
+        Color::Magenta,
+        Color::Green,
+        Color::Red,
+    ];
+
+    let datasets = plots
+        .iter()
+        .zip(colors.iter().cycle())
+        .map(|((name, points), color)| {
+            Dataset::default()
+                .name(name.as_str())
+                .marker(symbols::Marker::Braille)
+                .graph_type(GraphType::Line)
+                .style(Style::default().fg(*color))
+                .data(points)
+        })
+        .collect_vec();
+    let x_max = plots
+        .iter()
+        .flat_map(|(_, points)| points.iter().map(|p| p.0))
+        .fold(1.0, Num::max);
+    let y_max = app.head_movement.max_cylinder as Num;
+    let labels = |max: Num| {
+        [0.0, max / 2.0, max]
+            .iter()
+            .map(|l| Span::from(format!("{:.0}", l)))
+            .collect_vec()
+    };
+
+    let chart = Chart::new(datasets)
+        .block(
+            Block::default()
+                .title("Head movement (Tab: switch view, t: request order / time, q: quit)"),
+        )
+        .x_axis(
+            Axis::default()
+                .title(Span::styled(x_title, Style::default().fg(Color::Red)))
+                .bounds([0.0, x_max])
+                .labels(labels(x_max)),
+        )
+        .y_axis(
+            Axis::default()
+                .title(Span::styled("cylinder", Style::default().fg(Color::Red)))
+                .bounds([0.0, y_max])
+                .labels(labels(y_max)),
+        );
+
+    frame.render_widget(chart, frame.size());
+}
+
 /// Render the application. This is where you would draw the application UI. This example just
 /// draws a greeting.
 fn render_default_app(frame: &mut ratatui::Frame<BackendImpl>) {
@@ -194,24 +359,34 @@ fn restore_terminal(terminal: &mut Terminal<BackendImpl>) -> Result<()> {
 /// state. This example exits when the user presses 'q'. Other styles of application loops are
 /// possible, for example, you could have multiple application states and switch between them based
 /// on events, or you could have a single application state and update it based on events.
-fn run(terminal: &mut Terminal<BackendImpl>) -> Result<()> {
+fn run(terminal: &mut Terminal<BackendImpl>, app: &mut App) -> Result<()> {
     loop {
-        terminal.draw(crate::render_default_app)?;
-        if should_quit()? {
+        terminal.draw(|frame| render(frame, app))?;
+        if handle_input(app)? {
             break;
         }
     }
     Ok(())
 }
 
-/// Check if the user has pressed 'q'. This is where you would handle events. This example just
-/// checks if the user has pressed 'q' and returns true if they have. It does not handle any other
-/// events. There is a 250ms timeout on the event poll so that the application can exit in a timely
-/// manner, and to ensure that the terminal is rendered at least once every 250ms.
-fn should_quit() -> Result<bool> {
+/// Handle key presses and check if the user has pressed 'q'. Tab switches between the views, 't'
+/// switches the x axis of the head movement between request order and time. There is a timeout on
+/// the event poll so that the application can exit in a timely manner, and to ensure that the
+/// terminal is rendered at least once per tick.
+fn handle_input(app: &mut App) -> Result<bool> {
     if event::poll(Duration::from_millis(TICKRATE.into())).context("event poll failed")? {
         if let Event::Key(key) = event::read().context("event read failed")? {
-            return Ok(KeyCode::Char('q') == key.code);
+            match key.code {
+                KeyCode::Char('q') => return Ok(true),
+                KeyCode::Tab => {
+                    app.view = match app.view {
+                        View::Functions => View::HeadMovement,
+                        View::HeadMovement => View::Functions,
+                    }
+                }
+                KeyCode::Char('t') => app.over_time = !app.over_time,
+                _ => {}
+            }
         }
     }
     Ok(false)